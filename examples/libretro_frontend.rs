@@ -0,0 +1,166 @@
+//! A minimal libretro frontend for exercising the core without RetroArch.
+//! Runs a rom for a number of frames, holding down any keypad buttons
+//! given, then prints the last frame and checks that save states round trip.
+//!
+//! ```text
+//! cargo run --example libretro_frontend --features libretro -- rom.ch8 [frames] [joypad ids...]
+//! ```
+
+use std::{
+    env,
+    error::Error,
+    ffi::{
+        c_uint,
+        c_void,
+        CStr,
+    },
+    fs,
+    ptr,
+    sync::Mutex,
+};
+use chip8::libretro::*;
+
+/// The last frame received, as (width, height, pixels)
+static VIDEO: Mutex<(usize, usize, Vec<u32>)> = Mutex::new((0, 0, Vec::new()));
+/// Stereo sample frames received, and how many were not silent
+static AUDIO: Mutex<(usize, usize)> = Mutex::new((0, 0));
+/// Held joypad buttons, as a bitmask of ids
+static JOYPAD: Mutex<u16> = Mutex::new(0);
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        // SET_PIXEL_FORMAT: only XRGB8888 is expected
+        10 => *(data as *const c_uint) == 1,
+        // GET_VARIABLE: leave the core options at their defaults
+        15 => false,
+        // SET_VARIABLES
+        16 => {
+            let mut var = data as *const Variable;
+            while !(*var).key.is_null() {
+                println!("option {}: {}",
+                    CStr::from_ptr((*var).key).to_string_lossy(),
+                    CStr::from_ptr((*var).value).to_string_lossy());
+                var = var.add(1);
+            }
+            true
+        },
+        // GET_VARIABLE_UPDATE
+        17 => {
+            *(data as *mut bool) = false;
+            true
+        },
+        // SET_GEOMETRY
+        37 => {
+            let geometry = &*(data as *const GameGeometry);
+            println!("geometry changed to {}x{}", geometry.base_width, geometry.base_height);
+            true
+        },
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        pixels.extend_from_slice(std::slice::from_raw_parts(row, width));
+    }
+    *VIDEO.lock().unwrap() = (width, height, pixels);
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = std::slice::from_raw_parts(data, frames * 2);
+    let mut audio = AUDIO.lock().unwrap();
+    audio.0 += frames;
+    audio.1 += samples.chunks_exact(2).filter(|frame| frame[0] != 0).count();
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let held = port == 0 && device == 1 && id < 16 && *JOYPAD.lock().unwrap() & (1 << id) != 0;
+    held as i16
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let rom_path = args.next().ok_or("usage: libretro_frontend <rom> [frames] [joypad ids...]")?;
+    let frames: usize = args.next().map_or(Ok(600), |arg| arg.parse())?;
+    for id in args {
+        *JOYPAD.lock().unwrap() |= 1 << id.parse::<u16>()?;
+    }
+    let rom = fs::read(&rom_path)?;
+
+    unsafe {
+        assert_eq!(retro_api_version(), 1);
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let mut info: SystemInfo = std::mem::zeroed();
+        retro_get_system_info(&mut info);
+        println!("{} {} ({})",
+            CStr::from_ptr(info.library_name).to_string_lossy(),
+            CStr::from_ptr(info.library_version).to_string_lossy(),
+            CStr::from_ptr(info.valid_extensions).to_string_lossy());
+
+        let game = GameInfo {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        if !retro_load_game(&game) {
+            return Err("core failed to load the rom".into());
+        }
+
+        let mut av: SystemAvInfo = std::mem::zeroed();
+        retro_get_system_av_info(&mut av);
+        println!("{}x{} at {} fps, {} Hz audio",
+            av.geometry.base_width, av.geometry.base_height, av.timing.fps, av.timing.sample_rate);
+
+        for _ in 0..frames {
+            retro_run();
+        }
+
+        // Save, run on, then restore: the next frame should match the first run
+        let mut state = vec![0u8; retro_serialize_size()];
+        if !retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) {
+            return Err("serialize failed".into());
+        }
+        retro_run();
+        let expected = VIDEO.lock().unwrap().clone();
+        for _ in 0..60 {
+            retro_run();
+        }
+        if !retro_unserialize(state.as_ptr() as *const c_void, state.len()) {
+            return Err("unserialize failed".into());
+        }
+        retro_run();
+        if *VIDEO.lock().unwrap() != expected {
+            return Err("frame after unserialize doesn't match".into());
+        }
+        println!("save state of {} bytes round trips", state.len());
+
+        retro_unload_game();
+        retro_deinit();
+    }
+
+    let (width, height, pixels) = VIDEO.lock().unwrap().clone();
+    let background = pixels.first().copied().unwrap_or(0);
+    for row in pixels.chunks_exact(width).take(height) {
+        let line: String = row.iter().map(|&pixel| if pixel == background { '.' } else { '#' }).collect();
+        println!("{}", line);
+    }
+    let (samples, audible) = *AUDIO.lock().unwrap();
+    println!("{} audio frames, {} audible", samples, audible);
+    Ok(())
+}
@@ -0,0 +1,818 @@
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use crate::asm::{
+    AsmError,
+    lexer::Token,
+};
+
+/// Address the assembled rom is loaded at
+const ENTRY: u16 = 0x200;
+
+/// Guards against macros that expand into themselves forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// The right hand side of an arithmetic statement or comparison
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Reg(u8),
+    Imm(u8),
+}
+
+/// Width of an address that is patched in once all labels are known
+#[derive(Debug, Clone, Copy)]
+enum FixupKind {
+    /// 12-bit address in the low bits of an instruction
+    Nnn,
+    /// 16-bit address in the word following F000
+    Long,
+}
+
+#[derive(Debug)]
+struct Fixup {
+    addr: u16,
+    kind: FixupKind,
+    label: Token,
+}
+
+#[derive(Debug)]
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// An open control flow block, waiting for its closing keyword
+#[derive(Debug)]
+enum Control {
+    /// `if … begin`, with the jump to the else/end branch to patch
+    If { token: Token, jump: u16 },
+    /// `else`, with the jump past the else branch to patch
+    Else { token: Token, jump: u16 },
+    /// `loop`, with the start address and `while` exit jumps to patch
+    Loop { token: Token, start: u16, breaks: Vec<u16> },
+}
+
+/// A condition compiled into code that leaves the result in a skip.
+struct Condition {
+    /// Instructions that must run before the skip
+    prelude: Vec<u16>,
+    /// Skips the next instruction when the condition is false
+    skip_if_false: u16,
+    /// Skips the next instruction when the condition is true
+    skip_if_true: u16,
+}
+
+/// Compiles a stream of Octo tokens into a rom image.
+pub struct Compiler {
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    consts: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    control: Vec<Control>,
+    last: Token,
+    expansions: usize,
+}
+
+fn error(token: &Token, message: impl Into<String>) -> AsmError {
+    AsmError { line: token.line, col: token.col, message: message.into() }
+}
+
+/// Parses a decimal, hex (0x) or binary (0b) literal, with optional sign
+fn parse_number(text: &str) -> Option<i32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse::<i32>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+impl Compiler {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Compiler {
+            tokens: tokens.into(),
+            rom: Vec::new(),
+            here: ENTRY,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            control: Vec::new(),
+            last: Token { text: String::new(), line: 1, col: 1 },
+            expansions: 0,
+        }
+    }
+
+    /// Compiles every token, then resolves forward references.
+    /// Execution starts with a jump to the `main` label, if there is one.
+    pub fn compile(mut self) -> Result<Vec<u8>, AsmError> {
+        // Reserve room for the jump to main
+        self.emit_word(0x0000)?;
+
+        while let Some(token) = self.next() {
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.control.pop() {
+            let (token, message) = match block {
+                Control::If { token, .. } => (token, "'begin' without matching 'end'"),
+                Control::Else { token, .. } => (token, "'else' without matching 'end'"),
+                Control::Loop { token, .. } => (token, "'loop' without matching 'again'"),
+            };
+            return Err(error(&token, message));
+        }
+
+        let main = self.labels.get("main").copied().unwrap_or(ENTRY + 2);
+        let last = self.last.clone();
+        self.write_jump(ENTRY, main, &last)?;
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let addr = *self.labels.get(&fixup.label.text)
+                .ok_or_else(|| error(&fixup.label, format!("undefined name '{}'", fixup.label.text)))?;
+            match fixup.kind {
+                FixupKind::Nnn => {
+                    if addr > 0xFFF {
+                        return Err(error(&fixup.label, format!("label '{}' is out of 12-bit range", fixup.label.text)));
+                    }
+                    let word = self.read_word(fixup.addr)? | addr;
+                    self.write_word(fixup.addr, word)?;
+                },
+                FixupKind::Long => self.write_word(fixup.addr, addr)?,
+            }
+        }
+
+        Ok(self.rom)
+    }
+
+    /* Token stream */
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.pop_front()?;
+        self.last = token.clone();
+        Some(token)
+    }
+
+    fn expect(&mut self, what: &str) -> Result<Token, AsmError> {
+        self.next().ok_or_else(|| error(&self.last, format!("expected {}, found end of file", what)))
+    }
+
+    fn expect_text(&mut self, text: &str) -> Result<Token, AsmError> {
+        let token = self.expect(&format!("'{}'", text))?;
+        if token.text != text {
+            return Err(error(&token, format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.front().is_some_and(|t| t.text == text)
+    }
+
+    /* Output */
+
+    /// Offset into the rom of addr, which wraps below the entry point
+    /// once output runs past 0xFFFF
+    fn offset(&self, addr: u16) -> Result<usize, AsmError> {
+        addr.checked_sub(ENTRY)
+            .map(|offset| offset as usize)
+            .ok_or_else(|| error(&self.last, format!("address {:#06x} is out of range", addr)))
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        let offset = self.offset(self.here)?;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here = self.here.wrapping_add(1);
+        Ok(())
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), AsmError> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    fn read_word(&self, addr: u16) -> Result<u16, AsmError> {
+        let offset = self.offset(addr)?;
+        Ok((self.rom[offset] as u16) << 8 | self.rom[offset + 1] as u16)
+    }
+
+    fn write_word(&mut self, addr: u16, word: u16) -> Result<(), AsmError> {
+        let offset = self.offset(addr)?;
+        self.rom[offset] = (word >> 8) as u8;
+        self.rom[offset + 1] = word as u8;
+        Ok(())
+    }
+
+    /// Writes a jump to target at addr, which must be in 12-bit range
+    fn write_jump(&mut self, addr: u16, target: u16, token: &Token) -> Result<(), AsmError> {
+        if target > 0xFFF {
+            return Err(error(token, format!("jump target {:#06x} is out of 12-bit range", target)));
+        }
+        self.write_word(addr, 0x1000 | target)
+    }
+
+    /// Emits an instruction whose 12-bit address is the value of token
+    fn emit_address(&mut self, opcode: u16, token: Token) -> Result<(), AsmError> {
+        if let Some(value) = self.constant(&token) {
+            if !(0..=0xFFF).contains(&value) {
+                return Err(error(&token, format!("address {} is out of 12-bit range", value)));
+            }
+            self.emit_word(opcode | value as u16)?;
+        } else if self.register(&token).is_some() || self.is_keyword(&token.text) {
+            return Err(error(&token, format!("expected an address, found '{}'", token.text)));
+        } else {
+            self.fixups.push(Fixup { addr: self.here, kind: FixupKind::Nnn, label: token });
+            self.emit_word(opcode)?;
+        }
+        Ok(())
+    }
+
+    /* Operands */
+
+    fn register(&self, token: &Token) -> Option<u8> {
+        if let Some(&reg) = self.aliases.get(&token.text) {
+            return Some(reg);
+        }
+        let mut chars = token.text.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|d| d as u8),
+            _ => None,
+        }
+    }
+
+    fn expect_register(&mut self) -> Result<u8, AsmError> {
+        let token = self.expect("a register")?;
+        self.register(&token)
+            .ok_or_else(|| error(&token, format!("expected a register, found '{}'", token.text)))
+    }
+
+    /// The numeric value of a literal, constant or already defined label
+    fn constant(&self, token: &Token) -> Option<i32> {
+        parse_number(&token.text)
+            .or_else(|| self.consts.get(&token.text).copied())
+            .or_else(|| self.labels.get(&token.text).map(|&addr| addr as i32))
+    }
+
+    fn value(&self, token: &Token, min: i32, max: i32) -> Result<i32, AsmError> {
+        let value = self.constant(token)
+            .ok_or_else(|| error(token, format!("expected a number, found '{}'", token.text)))?;
+        if !(min..=max).contains(&value) {
+            return Err(error(token, format!("value {} is out of range {}..={}", value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn expect_byte(&mut self) -> Result<u8, AsmError> {
+        let token = self.expect("a number")?;
+        Ok(self.value(&token, -128, 255)? as u8)
+    }
+
+    fn expect_nibble(&mut self) -> Result<u8, AsmError> {
+        let token = self.expect("a number")?;
+        Ok(self.value(&token, 0, 15)? as u8)
+    }
+
+    fn expect_operand(&mut self) -> Result<Operand, AsmError> {
+        let token = self.expect("a register or number")?;
+        match self.register(&token) {
+            Some(reg) => Ok(Operand::Reg(reg)),
+            None => Ok(Operand::Imm(self.value(&token, -128, 255)? as u8)),
+        }
+    }
+
+    fn is_keyword(&self, text: &str) -> bool {
+        matches!(text,
+            "clear" | "return" | ";" | "exit" | "hires" | "lores" | "scroll-down" | "scroll-up"
+            | "scroll-left" | "scroll-right" | "audio" | "bcd" | "save" | "load" | "saveflags"
+            | "loadflags" | "plane" | "sprite" | "jump" | "jump0" | "native" | "i" | "delay"
+            | "buzzer" | "pitch" | "if" | "then" | "begin" | "else" | "end" | "loop" | "again"
+            | "while" | "key" | "-key" | "random" | "hex" | "bighex" | "long" | ":=")
+    }
+
+    /* Statements */
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        let x = |reg: u8| (reg as u16) << 8;
+        let y = |reg: u8| (reg as u16) << 4;
+
+        match token.text.as_str() {
+            ":"             => self.define_label()?,
+            ":alias"        => self.define_alias()?,
+            ":const"        => self.define_const()?,
+            ":macro"        => self.define_macro()?,
+            ":org"          => {
+                let addr = self.expect("an address")?;
+                self.here = self.value(&addr, ENTRY as i32, 0xFFFF)? as u16;
+            },
+            ":byte"         => {
+                let byte = self.expect_byte()?;
+                self.emit_byte(byte)?;
+            },
+            ":call"         => {
+                let target = self.expect("an address")?;
+                self.emit_address(0x2000, target)?;
+            },
+            ":breakpoint"   => { self.expect("a breakpoint name")?; },
+            "clear"         => self.emit_word(0x00E0)?,
+            "return" | ";"  => self.emit_word(0x00EE)?,
+            "exit"          => self.emit_word(0x00FD)?,
+            "lores"         => self.emit_word(0x00FE)?,
+            "hires"         => self.emit_word(0x00FF)?,
+            "scroll-down"   => {
+                let n = self.expect_nibble()?;
+                self.emit_word(0x00C0 | n as u16)?;
+            },
+            "scroll-up"     => {
+                let n = self.expect_nibble()?;
+                self.emit_word(0x00D0 | n as u16)?;
+            },
+            "scroll-right"  => self.emit_word(0x00FB)?,
+            "scroll-left"   => self.emit_word(0x00FC)?,
+            "audio"         => self.emit_word(0xF002)?,
+            "bcd"           => {
+                let vx = self.expect_register()?;
+                self.emit_word(0xF033 | x(vx))?;
+            },
+            "save" | "load" => {
+                let vx = self.expect_register()?;
+                if self.peek_is("-") {
+                    self.next();
+                    let vy = self.expect_register()?;
+                    let low = if token.text == "save" { 0x2 } else { 0x3 };
+                    self.emit_word(0x5000 | x(vx) | y(vy) | low)?;
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.emit_word(0xF000 | x(vx) | low)?;
+                }
+            },
+            "saveflags"     => {
+                let vx = self.expect_register()?;
+                self.emit_word(0xF075 | x(vx))?;
+            },
+            "loadflags"     => {
+                let vx = self.expect_register()?;
+                self.emit_word(0xF085 | x(vx))?;
+            },
+            "plane"         => {
+                let n = self.expect_nibble()?;
+                if n > 3 {
+                    return Err(error(&self.last, "plane mask must be between 0 and 3"));
+                }
+                self.emit_word(0xF001 | (n as u16) << 8)?;
+            },
+            "sprite"        => {
+                let vx = self.expect_register()?;
+                let vy = self.expect_register()?;
+                let n = self.expect_nibble()?;
+                self.emit_word(0xD000 | x(vx) | y(vy) | n as u16)?;
+            },
+            "jump"          => {
+                let target = self.expect("an address")?;
+                self.emit_address(0x1000, target)?;
+            },
+            "jump0"         => {
+                let target = self.expect("an address")?;
+                self.emit_address(0xB000, target)?;
+            },
+            "native"        => {
+                let target = self.expect("an address")?;
+                self.emit_address(0x0000, target)?;
+            },
+            "i"             => self.index_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_text(":=")?;
+                let vx = self.expect_register()?;
+                let low = match token.text.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit_word(0xF000 | x(vx) | low)?;
+            },
+            "if"            => self.if_statement()?,
+            "else"          => self.else_statement(token)?,
+            "end"           => self.end_statement(token)?,
+            "loop"          => {
+                self.control.push(Control::Loop { token, start: self.here, breaks: Vec::new() });
+            },
+            "while"         => self.while_statement(token)?,
+            "again"         => self.again_statement(token)?,
+            _ => {
+                if let Some(vx) = self.register(&token) {
+                    self.register_statement(vx)?;
+                } else if self.macros.contains_key(&token.text) {
+                    self.expand_macro(token)?;
+                } else if let Some(value) = self.constant(&token).filter(|_| !self.labels.contains_key(&token.text)) {
+                    if !(-128..=255).contains(&value) {
+                        return Err(error(&token, format!("byte {} is out of range", value)));
+                    }
+                    self.emit_byte(value as u8)?;
+                } else if token.text.starts_with(':') || self.is_keyword(&token.text) {
+                    return Err(error(&token, format!("unexpected '{}'", token.text)));
+                } else {
+                    // Any other name is a call to a label, which may be defined later
+                    self.emit_address(0x2000, token)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn define_label(&mut self) -> Result<(), AsmError> {
+        let name = self.expect("a label name")?;
+        if self.labels.contains_key(&name.text) {
+            return Err(error(&name, format!("label '{}' is already defined", name.text)));
+        }
+        if parse_number(&name.text).is_some() || self.register(&name).is_some() || self.is_keyword(&name.text) {
+            return Err(error(&name, format!("'{}' is not a valid label name", name.text)));
+        }
+        self.labels.insert(name.text, self.here);
+        Ok(())
+    }
+
+    fn define_alias(&mut self) -> Result<(), AsmError> {
+        let name = self.expect("an alias name")?;
+        let reg = self.expect_register()?;
+        self.aliases.insert(name.text, reg);
+        Ok(())
+    }
+
+    fn define_const(&mut self) -> Result<(), AsmError> {
+        let name = self.expect("a constant name")?;
+        let value = self.expect("a value")?;
+        let value = self.value(&value, -0x8000, 0xFFFF)?;
+        self.consts.insert(name.text, value);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.expect("a macro name")?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.expect("'{'")?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()
+                .ok_or_else(|| error(&name, format!("macro '{}' is missing a closing '}}'", name.text)))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => {},
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    /// Substitutes a macro's arguments into its body and queues the result
+    fn expand_macro(&mut self, name: Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(error(&name, format!("macro '{}' expands recursively", name.text)));
+        }
+
+        let arg_count = self.macros[&name.text].args.len();
+        let mut values = HashMap::new();
+        for idx in 0..arg_count {
+            let value = self.expect(&format!("argument {} of macro '{}'", idx + 1, name.text))?;
+            values.insert(self.macros[&name.text].args[idx].clone(), value);
+        }
+
+        let expansion: Vec<Token> = self.macros[&name.text].body.iter()
+            .map(|token| values.get(&token.text).cloned().unwrap_or_else(|| token.clone()))
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    /// `i := nnn`, `i := long nnnn`, `i := hex vx`, `i := bighex vx`, `i += vx`
+    fn index_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.expect("':=' or '+='")?;
+        match op.text.as_str() {
+            ":=" => {
+                let target = self.expect("an address")?;
+                match target.text.as_str() {
+                    "hex" => {
+                        let vx = self.expect_register()?;
+                        self.emit_word(0xF029 | (vx as u16) << 8)?;
+                    },
+                    "bighex" => {
+                        let vx = self.expect_register()?;
+                        self.emit_word(0xF030 | (vx as u16) << 8)?;
+                    },
+                    "long" => {
+                        let target = self.expect("an address")?;
+                        self.emit_word(0xF000)?;
+                        if self.constant(&target).is_some() {
+                            let addr = self.value(&target, 0, 0xFFFF)? as u16;
+                            self.emit_word(addr)?;
+                        } else {
+                            self.fixups.push(Fixup { addr: self.here, kind: FixupKind::Long, label: target });
+                            self.emit_word(0x0000)?;
+                        }
+                    },
+                    _ => self.emit_address(0xA000, target)?,
+                }
+            },
+            "+=" => {
+                let vx = self.expect_register()?;
+                self.emit_word(0xF01E | (vx as u16) << 8)?;
+            },
+            _ => return Err(error(&op, format!("expected ':=' or '+=', found '{}'", op.text))),
+        }
+        Ok(())
+    }
+
+    /// `vx <op> <operand>`
+    fn register_statement(&mut self, vx: u8) -> Result<(), AsmError> {
+        let op = self.expect("an operator")?;
+        let x = (vx as u16) << 8;
+
+        let reg_op = |low: u16, operand: Operand, op: &Token| match operand {
+            Operand::Reg(vy) => Ok(0x8000 | x | (vy as u16) << 4 | low),
+            Operand::Imm(_) => Err(error(op, format!("'{}' requires a register operand", op.text))),
+        };
+
+        let word = match op.text.as_str() {
+            ":=" => {
+                if self.peek_is("random") {
+                    self.next();
+                    let mask = self.expect_byte()?;
+                    0xC000 | x | mask as u16
+                } else if self.peek_is("delay") {
+                    self.next();
+                    0xF007 | x
+                } else if self.peek_is("key") {
+                    self.next();
+                    0xF00A | x
+                } else {
+                    match self.expect_operand()? {
+                        Operand::Reg(vy) => 0x8000 | x | (vy as u16) << 4,
+                        Operand::Imm(nn) => 0x6000 | x | nn as u16,
+                    }
+                }
+            },
+            "+=" => match self.expect_operand()? {
+                Operand::Reg(vy) => 0x8004 | x | (vy as u16) << 4,
+                Operand::Imm(nn) => 0x7000 | x | nn as u16,
+            },
+            "-=" => match self.expect_operand()? {
+                Operand::Reg(vy) => 0x8005 | x | (vy as u16) << 4,
+                Operand::Imm(nn) => 0x7000 | x | nn.wrapping_neg() as u16,
+            },
+            "|="  => reg_op(0x1, self.expect_operand()?, &op)?,
+            "&="  => reg_op(0x2, self.expect_operand()?, &op)?,
+            "^="  => reg_op(0x3, self.expect_operand()?, &op)?,
+            ">>=" => reg_op(0x6, self.expect_operand()?, &op)?,
+            "=-"  => reg_op(0x7, self.expect_operand()?, &op)?,
+            "<<=" => reg_op(0xE, self.expect_operand()?, &op)?,
+            _ => return Err(error(&op, format!("unknown operator '{}'", op.text))),
+        };
+        self.emit_word(word)?;
+        Ok(())
+    }
+
+    /// Parses `vx <cmp> <operand>`, `vx key` or `vx -key`
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let vx = self.expect_register()?;
+        let op = self.expect("a comparison")?;
+        let x = (vx as u16) << 8;
+
+        let skips = |skip_if_false: u16, skip_if_true: u16| Condition {
+            prelude: Vec::new(), skip_if_false, skip_if_true,
+        };
+
+        let condition = match op.text.as_str() {
+            "key"  => skips(0xE0A1 | x, 0xE09E | x),
+            "-key" => skips(0xE09E | x, 0xE0A1 | x),
+            "==" => match self.expect_operand()? {
+                Operand::Reg(vy) => skips(0x9000 | x | (vy as u16) << 4, 0x5000 | x | (vy as u16) << 4),
+                Operand::Imm(nn) => skips(0x4000 | x | nn as u16, 0x3000 | x | nn as u16),
+            },
+            "!=" => match self.expect_operand()? {
+                Operand::Reg(vy) => skips(0x5000 | x | (vy as u16) << 4, 0x9000 | x | (vy as u16) << 4),
+                Operand::Imm(nn) => skips(0x3000 | x | nn as u16, 0x4000 | x | nn as u16),
+            },
+            // Ordered comparisons subtract into VF, which then holds the NOT borrow flag
+            "<" | ">" | "<=" | ">=" => {
+                let operand = self.expect_operand()?;
+                let swapped = matches!(op.text.as_str(), ">" | "<=");
+                let prelude = match (operand, swapped) {
+                    // vf := vx; vf -= vy  => VF = (vx >= vy)
+                    (Operand::Reg(vy), false) => vec![0x8F00 | (vx as u16) << 4, 0x8F05 | (vy as u16) << 4],
+                    // vf := nn; vf =- vx  => VF = (vx >= nn)
+                    (Operand::Imm(nn), false) => vec![0x6F00 | nn as u16, 0x8F07 | (vx as u16) << 4],
+                    // vf := vy; vf -= vx  => VF = (vy >= vx)
+                    (Operand::Reg(vy), true) => vec![0x8F00 | (vy as u16) << 4, 0x8F05 | (vx as u16) << 4],
+                    // vf := nn; vf -= vx  => VF = (nn >= vx)
+                    (Operand::Imm(nn), true) => vec![0x6F00 | nn as u16, 0x8F05 | (vx as u16) << 4],
+                };
+                // '<' and '>' hold when VF is 0, '>=' and '<=' when VF is 1
+                let (skip_if_false, skip_if_true) = if matches!(op.text.as_str(), "<" | ">") {
+                    (0x4F00, 0x3F00)
+                } else {
+                    (0x3F00, 0x4F00)
+                };
+                Condition { prelude, skip_if_false, skip_if_true }
+            },
+            _ => return Err(error(&op, format!("unknown comparison '{}'", op.text))),
+        };
+        Ok(condition)
+    }
+
+    fn if_statement(&mut self) -> Result<(), AsmError> {
+        let condition = self.condition()?;
+        for word in &condition.prelude {
+            self.emit_word(*word)?;
+        }
+
+        let keyword = self.expect("'then' or 'begin'")?;
+        match keyword.text.as_str() {
+            "then" => self.emit_word(condition.skip_if_false)?,
+            "begin" => {
+                self.emit_word(condition.skip_if_true)?;
+                let jump = self.here;
+                self.emit_word(0x1000)?;
+                self.control.push(Control::If { token: keyword, jump });
+            },
+            _ => return Err(error(&keyword, format!("expected 'then' or 'begin', found '{}'", keyword.text))),
+        }
+        Ok(())
+    }
+
+    fn else_statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match self.control.pop() {
+            Some(Control::If { jump: if_jump, .. }) => {
+                let jump = self.here;
+                self.emit_word(0x1000)?;
+                self.write_jump(if_jump, self.here, &token)?;
+                self.control.push(Control::Else { token, jump });
+                Ok(())
+            },
+            _ => Err(error(&token, "'else' without matching 'if … begin'")),
+        }
+    }
+
+    fn end_statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match self.control.pop() {
+            Some(Control::If { jump, .. }) | Some(Control::Else { jump, .. }) => {
+                self.write_jump(jump, self.here, &token)
+            },
+            _ => Err(error(&token, "'end' without matching 'if … begin'")),
+        }
+    }
+
+    fn while_statement(&mut self, token: Token) -> Result<(), AsmError> {
+        if !self.control.iter().any(|block| matches!(block, Control::Loop { .. })) {
+            return Err(error(&token, "'while' outside of a loop"));
+        }
+
+        let condition = self.condition()?;
+        for word in &condition.prelude {
+            self.emit_word(*word)?;
+        }
+        self.emit_word(condition.skip_if_true)?;
+        let jump = self.here;
+        self.emit_word(0x1000)?;
+
+        if let Some(Control::Loop { breaks, .. }) = self.control.iter_mut()
+            .rev()
+            .find(|block| matches!(block, Control::Loop { .. })) {
+            breaks.push(jump);
+        }
+        Ok(())
+    }
+
+    fn again_statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match self.control.pop() {
+            Some(Control::Loop { start, breaks, .. }) => {
+                let jump = self.here;
+                self.emit_word(0x1000)?;
+                self.write_jump(jump, start, &token)?;
+                for jump in breaks {
+                    self.write_jump(jump, self.here, &token)?;
+                }
+                Ok(())
+            },
+            _ => Err(error(&token, "'again' without matching 'loop'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        asm::assemble,
+        emu::cpu::CPU,
+    };
+
+    /// Assembles source and runs it for a number of instructions
+    fn run(source: &str, steps: usize) -> CPU {
+        let rom = assemble(source).unwrap();
+        let mut cpu = CPU::initialize();
+        cpu.mem[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let rom = assemble(": main\n  sub\n  jump main\n: sub\n  return\n").unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x22, 0x06, 0x12, 0x02, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn org_moves_output() {
+        let rom = assemble(": main\n:org 0x210\n:byte 0xAB\n").unwrap();
+        assert_eq!(rom.len(), 0x11);
+        assert_eq!(rom[..2], [0x12, 0x02]);
+        assert_eq!(rom[0x10], 0xAB);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let rom = assemble(": main\n  if v0 == 1 begin\n    v1 := 2\n  else\n    v1 := 3\n  end\n").unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x30, 0x01, 0x12, 0x0A, 0x61, 0x02, 0x12, 0x0C, 0x61, 0x03]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let rom = assemble(": main\n  loop\n    v0 += 1\n    while v0 != 5\n  again\n").unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x70, 0x01, 0x40, 0x05, 0x12, 0x0A, 0x12, 0x02]);
+    }
+
+    #[test]
+    fn macros_substitute_arguments() {
+        let rom = assemble(":macro set reg val { reg := val }\n: main\n  set v3 7\n").unwrap();
+        assert_eq!(rom, [0x12, 0x02, 0x63, 0x07]);
+    }
+
+    #[test]
+    fn errors_point_at_the_token() {
+        let err = assemble(": main\n  v0 := 300\n").unwrap_err();
+        assert_eq!((err.line, err.col), (2, 9), "{}", err);
+
+        let err = assemble(": main\n  if v0 == 1 begin\n").unwrap_err();
+        assert_eq!((err.line, err.col), (2, 14), "{}", err);
+    }
+
+    #[test]
+    fn output_past_0xffff_is_an_error() {
+        let err = assemble(":org 0xFFFF\n:byte 1\n:byte 2\n").unwrap_err();
+        assert!(err.message.contains("out of range"), "{}", err);
+    }
+
+    #[test]
+    fn jumps_past_0xfff_are_an_error() {
+        let err = assemble(": main\n:org 0x1000\nloop again\n").unwrap_err();
+        assert!(err.message.contains("12-bit range"), "{}", err);
+    }
+
+    #[test]
+    fn ordered_comparisons_run() {
+        let cpu = run("
+            : main
+              v0 := 3
+              v1 := 5
+              if v0 < v1 then v2 := 1
+              if v1 < v0 then v3 := 1
+              if v1 >= v0 then v4 := 1
+              if v0 > 4 then v5 := 1
+            : halt
+              jump halt
+        ", 30);
+        assert_eq!(cpu.v[2..6], [1, 0, 1, 0]);
+    }
+}
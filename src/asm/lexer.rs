@@ -0,0 +1,36 @@
+/// A whitespace separated word of Octo source, with its position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// Splits Octo source into tokens.
+/// Comments run from `#` to the end of the line. Lines and columns start at 1.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let mut start: Option<usize> = None;
+
+        for (col_idx, c) in line.char_indices().chain(std::iter::once((line.len(), ' '))) {
+            if c == '#' && start.is_none() {
+                break;
+            }
+            if c.is_whitespace() {
+                if let Some(begin) = start.take() {
+                    tokens.push(Token {
+                        text: line[begin..col_idx].to_string(),
+                        line: line_idx + 1,
+                        col: line[..begin].chars().count() + 1,
+                    });
+                }
+            } else if start.is_none() {
+                start = Some(col_idx);
+            }
+        }
+    }
+
+    tokens
+}
@@ -0,0 +1,33 @@
+pub mod lexer;
+pub mod compiler;
+
+use std::{
+    error::Error,
+    fmt,
+};
+
+/// An assembly error, located at the token that caused it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles Octo source into a rom image, ready to be loaded at 0x200.
+///
+/// Supports labels, `:alias`, `:const`, `:macro`, `:org`, `:byte`,
+/// `if … then`, `if … begin … else … end`, `loop … while … again`,
+/// sprite data, and the SUPER-CHIP and XO-CHIP extensions.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens = lexer::tokenize(source);
+    compiler::Compiler::new(tokens).compile()
+}
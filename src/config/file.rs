@@ -0,0 +1,208 @@
+//! The TOML config file. Every setting is optional, and command line flags
+//! take priority over anything set here. For example:
+//!
+//! ```toml
+//! quirks = "schip11"
+//! ipf = 15
+//! timing = "fixed"
+//! scale = 10
+//! scaling = "fit"
+//! vsync = false
+//! volume = 0.05
+//! tone = 440.0
+//! rng = "vip"
+//! seed = 1234
+//! palette = ["#000000", "#ffffff", "#c85a3c", "#5a3c32"]
+//!
+//! # Keyboard key for each chip-8 key, by key name
+//! [keys]
+//! 5 = "Up"
+//! 8 = "Down"
+//!
+//! # Overrides for the rom with this SHA-1, as shown by --log-level info
+//! [rom.0123456789abcdef0123456789abcdef01234567]
+//! quirks = "xochip"
+//! ipf = 1000
+//! palette = ["#000000", "#ffcc00", "#ff6600", "#662200"]
+//! ```
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    path::{
+        Path,
+        PathBuf,
+    },
+};
+use serde::Deserialize;
+use crate::emu::frame::Palette;
+
+/// Settings which can be overridden for a single rom
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RomSection {
+    pub quirks: Option<String>,
+    pub ipf: Option<usize>,
+    /// Colours as "#rrggbb" or "#rgb"
+    pub palette: Option<Vec<String>>,
+}
+
+impl RomSection {
+    /// The palette, parsed from hex colour strings
+    pub fn palette(&self) -> Result<Option<Palette>, String> {
+        self.palette.as_deref().map(parse_palette).transpose()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub quirks: Option<String>,
+    pub ipf: Option<usize>,
+    pub timing: Option<String>,
+    pub scale: Option<u32>,
+    pub scaling: Option<String>,
+    pub vsync: Option<bool>,
+    pub volume: Option<f32>,
+    pub tone: Option<f32>,
+    pub rng: Option<String>,
+    pub seed: Option<u64>,
+    /// Colours as "#rrggbb" or "#rgb"
+    pub palette: Option<Vec<String>>,
+    /// Key names, by chip-8 key in hex
+    #[serde(default)]
+    pub keys: HashMap<String, String>,
+    /// Per-rom overrides, by lowercase SHA-1
+    #[serde(default)]
+    pub rom: HashMap<String, RomSection>,
+}
+
+impl ConfigFile {
+    /// Where the config file is looked for when --config isn't given:
+    /// `$XDG_CONFIG_HOME/chip8/config.toml` on Linux
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("chip8").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let file: ConfigFile = toml::from_str(&text)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        log::info!("loaded config from {}", path.display());
+        Ok(file)
+    }
+
+    /// Overrides for the rom with the given hex SHA-1, if there are any
+    pub fn rom(&self, hash: &str) -> Option<&RomSection> {
+        self.rom.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(hash))
+            .map(|(_, settings)| settings)
+    }
+
+    /// The palette, parsed from hex colour strings
+    pub fn palette(&self) -> Result<Option<Palette>, String> {
+        self.palette.as_deref().map(parse_palette).transpose()
+    }
+
+    /// Key bindings, as key names indexed by chip-8 key
+    pub fn keys(&self) -> Result<[Option<String>; 16], String> {
+        let mut keys: [Option<String>; 16] = Default::default();
+        for (key, name) in &self.keys {
+            let idx = u8::from_str_radix(key, 16).ok()
+                .filter(|&idx| idx < 16)
+                .ok_or_else(|| format!("unknown chip-8 key '{}' in [keys], expected 0-F", key))?;
+            keys[idx as usize] = Some(name.clone());
+        }
+        Ok(keys)
+    }
+}
+
+/// Parses a full palette of hex colour strings
+fn parse_palette(colours: &[String]) -> Result<Palette, String> {
+    let mut palette = Palette::default();
+    if colours.len() != palette.len() {
+        return Err(format!("palette needs {} colours, found {}", palette.len(), colours.len()));
+    }
+    for (entry, colour) in palette.iter_mut().zip(colours) {
+        *entry = parse_colour(colour)?;
+    }
+    Ok(palette)
+}
+
+/// Parses a "#rrggbb" or "#rgb" colour
+pub fn parse_colour(s: &str) -> Result<[u8; 3], String> {
+    let invalid = || format!("invalid colour '{}', expected #rrggbb or #rgb", s);
+    let hex = s.strip_prefix('#')
+        .filter(|hex| hex.len() == 6 || hex.len() == 3)
+        .filter(|hex| hex.bytes().all(|byte| byte.is_ascii_hexdigit()))
+        .ok_or_else(invalid)?;
+    let digits = hex.len() / 3;
+    let mut colour = [0; 3];
+    for (idx, channel) in colour.iter_mut().enumerate() {
+        let value = hex.get(idx * digits..(idx + 1) * digits)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(invalid)?;
+        // A single digit is repeated, so #f80 is #ff8800
+        *channel = if digits == 1 { value * 0x11 } else { value };
+    }
+    Ok(colour)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_documented_example() {
+        let doc = include_str!("file.rs").lines()
+            .skip_while(|line| !line.starts_with("//! ```toml"))
+            .skip(1)
+            .take_while(|line| !line.starts_with("//! ```"))
+            .map(|line| line.trim_start_matches("//!").trim_start())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let file: ConfigFile = toml::from_str(&doc).unwrap();
+        assert_eq!(file.quirks.as_deref(), Some("schip11"));
+        assert_eq!((file.ipf, file.scale, file.vsync, file.seed), (Some(15), Some(10), Some(false), Some(1234)));
+        assert_eq!(file.palette().unwrap().unwrap()[2], [0xc8, 0x5a, 0x3c]);
+
+        let section = file.rom("0123456789ABCDEF0123456789ABCDEF01234567").unwrap();
+        assert_eq!((section.quirks.as_deref(), section.ipf), (Some("xochip"), Some(1000)));
+        assert_eq!(section.palette().unwrap().unwrap()[1], [0xff, 0xcc, 0x00]);
+        assert!(file.rom("0000000000000000000000000000000000000000").is_none());
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("speed = 2").is_err());
+        assert!(toml::from_str::<ConfigFile>("[rom.abc]\nscale = 2").is_err());
+        assert!(toml::from_str::<ConfigFile>("ipf = \"fast\"").is_err());
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(parse_colour("#c85a3C"), Ok([0xc8, 0x5a, 0x3c]));
+        assert_eq!(parse_colour("#f80"), Ok([0xff, 0x88, 0x00]));
+        for bad in ["c85a3c", "#c85a3", "#c85a3c0", "#g00", "#", "#+1+2+3", "#ééé"] {
+            assert!(parse_colour(bad).is_err(), "{}", bad);
+        }
+
+        let short = |colours: &[&str]| parse_palette(&colours.iter().map(|c| c.to_string()).collect::<Vec<_>>());
+        assert!(short(&["#000", "#fff", "#f00"]).is_err());
+        assert_eq!(short(&["#000", "#fff", "#f00", "#00f"]).unwrap()[3], [0, 0, 0xff]);
+    }
+
+    #[test]
+    fn keys() {
+        let file: ConfigFile = toml::from_str("[keys]\n5 = \"Up\"\nf = \"Return\"").unwrap();
+        let keys = file.keys().unwrap();
+        assert_eq!((keys[5].as_deref(), keys[0xF].as_deref(), &keys[0]), (Some("Up"), Some("Return"), &None));
+
+        for bad in ["10", "g", "-1"] {
+            let file: ConfigFile = toml::from_str(&format!("[keys]\n\"{}\" = \"Up\"", bad)).unwrap();
+            assert!(file.keys().is_err(), "{}", bad);
+        }
+    }
+}
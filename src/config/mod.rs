@@ -0,0 +1,630 @@
+pub mod file;
+
+use std::{
+    error::Error,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+    time::Duration,
+};
+use clap::{
+    Args,
+    Parser,
+    Subcommand,
+};
+use crate::{
+    drivers::file::FileDriver,
+    emu::{
+        cpu::CYCLES_PER_FRAME,
+        frame::{
+            Palette,
+            PALETTE,
+        },
+        quirks::{
+            Platform,
+            Quirks,
+        },
+        rng::RngModel,
+        timing::Timing,
+        tone::TONE_FREQ,
+        trace::{
+            TraceFilter,
+            TraceFormat,
+        },
+    },
+    rom_db::RomDb,
+};
+use self::file::ConfigFile;
+
+/// Keyboard keys for each chip-8 key, in a 4x4 block from 1 to V
+const DEFAULT_KEYS: [&str; 16] = [
+    "X", "1", "2", "3",
+    "Q", "W", "E", "A",
+    "S", "D", "Z", "C",
+    "4", "R", "F", "V",
+];
+
+const DEFAULT_VOLUME: f32 = 0.03;
+
+#[derive(Parser)]
+#[clap(name = "Chip8 Emulator")]
+#[clap(author = "Wm. A. Rhodes <warhodes@gmail.com>")]
+#[clap(about = "A simple chip8 emulator")]
+#[clap(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    #[clap(value_parser)]
+    rom_path: Option<String>,
+
+    #[clap(default_value_t = String::from("off"), short, long, value_parser)]
+    log_level: String,
+
+    #[clap(flatten)]
+    emu: EmuArgs,
+}
+
+/// Options shared by every way of running a rom
+#[derive(Args)]
+struct EmuArgs {
+    /// Config file to use instead of the one in the user config directory
+    #[clap(long, value_parser)]
+    config: Option<PathBuf>,
+
+    /// Speed multiplier, applied to the default of 10 instructions per frame
+    #[clap(short, long, value_parser)]
+    speed: Option<f64>,
+
+    /// Instructions per 60Hz frame. Overrides --speed
+    #[clap(long, value_parser)]
+    ipf: Option<usize>,
+
+    /// Instructions per second, spread evenly over the 60Hz frames. Overrides --speed
+    #[clap(long, value_parser, conflicts_with = "ipf")]
+    cpu_hz: Option<u32>,
+
+    /// Initial window size, in screen pixels per low resolution pixel [default: 8]
+    #[clap(long, value_parser)]
+    scale: Option<u32>,
+
+    /// How the frame fills a resized window: integer or fit [default: integer]
+    #[clap(long, value_parser)]
+    scaling: Option<String>,
+
+    /// Don't wait for the display's refresh when drawing, and sleep between frames instead
+    #[clap(long, action)]
+    no_vsync: bool,
+
+    /// Show instructions per second and frame times in the corner (toggle with F3)
+    #[clap(long, action)]
+    stats: bool,
+
+    /// Quirk profile: vip, chip48, schip11 or xochip [default: vip]
+    #[clap(short, long, value_parser)]
+    quirks: Option<String>,
+
+    /// Timing model: fixed instructions per frame, or vip cycle costs [default: fixed]
+    #[clap(long, value_parser)]
+    timing: Option<String>,
+
+    /// Random number generator for CXNN: std, vip or lfsr [default: std]
+    #[clap(long, value_parser)]
+    rng: Option<String>,
+
+    /// Seed for the random number generator, for reproducible runs.
+    /// Windowed runs pick one from the clock otherwise, and log it
+    #[clap(long, value_parser)]
+    seed: Option<u64>,
+
+    /// Record keypad input to a movie file, to replay the run with --play
+    #[clap(long, value_parser, conflicts_with_all = &["play", "debug", "gdb"])]
+    record: Option<PathBuf>,
+
+    /// Play back a movie made with --record, then hand over to the keyboard
+    #[clap(long, value_parser, conflicts_with_all = &["debug", "gdb"])]
+    play: Option<PathBuf>,
+
+    /// Fail if the framebuffer doesn't match the movie's checkpoints during playback
+    #[clap(long, action, requires = "play")]
+    verify: bool,
+
+    /// Write each executed instruction to this file: a .bin path for
+    /// compact binary records, anything else for text
+    #[clap(long, value_parser)]
+    trace: Option<PathBuf>,
+
+    /// Only trace instructions at these hex addresses, e.g. 200-2ff,300
+    #[clap(long, value_parser, requires = "trace")]
+    trace_pc: Option<String>,
+
+    /// Only trace these opcode classes, by first hex digit, e.g. 8,d
+    #[clap(long, value_parser, requires = "trace")]
+    trace_ops: Option<String>,
+
+    /// Only trace during these frames, e.g. 100-200 or 100-
+    #[clap(long, value_parser, requires = "trace")]
+    trace_frames: Option<String>,
+
+    /// Start paused in the interactive debugger
+    #[clap(short, long, action)]
+    debug: bool,
+
+    /// Start paused, waiting for gdb to connect on this localhost port
+    #[clap(long, value_parser, conflicts_with = "debug")]
+    gdb: Option<u16>,
+
+    /// Seconds of history kept for rewinding (hold Backspace). 0 disables rewind
+    #[clap(default_value_t = 60, long, value_parser)]
+    rewind_secs: u32,
+
+    /// Play in the terminal instead of an SDL window. The --debug REPL needs
+    /// the terminal too, so debug with --gdb from another shell instead
+    #[clap(long, action, conflicts_with = "debug")]
+    tui: bool,
+
+    /// Terminal only: milliseconds after the last key press before a key counts as released
+    #[clap(default_value_t = 150, long, value_parser)]
+    key_timeout: u64,
+
+    /// Terminal only: ring the terminal bell for sound instead of showing a note
+    #[clap(long, action)]
+    bell: bool,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    /// Print an annotated disassembly of a rom
+    Disasm {
+        #[clap(value_parser)]
+        rom_path: String,
+    },
+    /// Assemble Octo source into a .ch8 rom
+    Assemble {
+        #[clap(value_parser)]
+        source_path: String,
+
+        /// Output rom path. Defaults to the source path with a .ch8 extension
+        #[clap(short, long, value_parser)]
+        output: Option<String>,
+    },
+    /// Compare two binary traces and report where they first diverge
+    TraceDiff {
+        #[clap(value_parser)]
+        left: String,
+
+        #[clap(value_parser)]
+        right: String,
+    },
+    /// Run a rom, optionally without a window for use in CI
+    Run {
+        #[clap(value_parser)]
+        rom_path: String,
+
+        /// Run without video, audio or input, then dump the final frame
+        #[clap(long, action)]
+        headless: bool,
+
+        /// Number of frames to run in headless mode. With --play, the whole movie is run instead
+        #[clap(default_value_t = 600, long, value_parser)]
+        frames: u32,
+
+        /// Where to write the final frame: a .png path for an image,
+        /// anything else for ASCII art. Defaults to ASCII art on stdout
+        #[clap(short, long, value_parser)]
+        output: Option<String>,
+
+        /// Golden image (.png or ASCII art) the final frame must match
+        #[clap(long, value_parser)]
+        expect: Option<String>,
+
+        #[clap(flatten)]
+        emu: EmuArgs,
+    },
+}
+
+/// Where an emulated rom is presented
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frontend {
+    /// An SDL window with audio
+    Sdl,
+    /// Half-block graphics in the terminal
+    Terminal { key_timeout: Duration, bell: bool },
+}
+
+/// Recording or playback of an input movie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieMode {
+    Record(PathBuf),
+    /// When verifying, a desync from the movie's checkpoints is an error
+    Play { path: PathBuf, verify: bool },
+}
+
+/// Where and what to trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub path: PathBuf,
+    pub format: TraceFormat,
+    pub filter: TraceFilter,
+}
+
+/// How the frame is scaled to fill the window, keeping its aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    /// Whole multiples of the frame size only, for even pixels
+    Integer,
+    /// As large as fits in the window
+    Fit,
+}
+
+impl FromStr for Scaling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "integer" => Ok(Scaling::Integer),
+            "fit"     => Ok(Scaling::Fit),
+            _ => Err(format!("unknown scaling '{}', expected integer or fit", s)),
+        }
+    }
+}
+
+/// What the binary should do with the provided rom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Run the rom in the emulator
+    Emulate,
+    /// Print a disassembly of the rom and exit
+    Disasm,
+    /// Assemble the source at rom_path into a rom at output_path and exit
+    Assemble { output_path: String },
+    /// Compare the binary traces at left and right, then exit
+    TraceDiff { left: String, right: String },
+    /// Run the rom for a number of frames without SDL, then dump the frame
+    /// to output_path and compare it against expect_path
+    Headless {
+        frames: u32,
+        output_path: Option<String>,
+        expect_path: Option<String>,
+    },
+}
+
+/// Emulation settings for a particular rom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomConfig {
+    /// The rom's name in the rom database, or its file name
+    pub title: String,
+    pub quirks: Quirks,
+    /// Instructions executed per 60Hz frame
+    pub cycles_per_frame: usize,
+    pub palette: Palette,
+    /// Keyboard key names bound to each chip-8 key
+    pub keys: [Vec<String>; 16],
+}
+
+/// Settings from the command line, layered over the config file.
+/// Some settings can also come from the rom database or be overridden
+/// per rom, see `rom_config`.
+pub struct Config {
+    pub mode: Mode,
+    pub rom_path: Option<String>,
+    pub log_level: log::LevelFilter,
+    pub debug: bool,
+    /// Port for the GDB remote protocol server, if enabled
+    pub gdb_port: Option<u16>,
+    /// Number of frames kept in the rewind buffer
+    pub rewind_frames: usize,
+    pub frontend: Frontend,
+    /// Initial window scale
+    pub scale_factor: u32,
+    /// Pace drawing to the display's refresh rate
+    pub vsync: bool,
+    /// Start with the stats overlay showing
+    pub stats: bool,
+    pub scaling: Scaling,
+    pub palette: Palette,
+    /// Keyboard key names bound to each chip-8 key
+    pub keys: [Vec<String>; 16],
+    /// Buzzer volume, from 0 to 1
+    pub volume: f32,
+    /// Buzzer frequency in Hz, when no XO-CHIP pattern is loaded
+    pub tone: f32,
+    pub timing: Timing,
+    /// Instructions per second, which varies the instructions per frame
+    pub cpu_hz: Option<u32>,
+    pub rng: RngModel,
+    /// Seed for the RNG, if one was chosen
+    pub seed: Option<u64>,
+    pub movie: Option<MovieMode>,
+    pub trace: Option<TraceConfig>,
+
+    file: ConfigFile,
+    /// Quirks and speed given on the command line, which override the file
+    cli_platform: Option<Platform>,
+    cli_cycles_per_frame: Option<usize>,
+}
+
+impl Config {
+    pub fn from_args() -> Result<Self, Box<dyn Error>> {
+        Config::from_cli(Cli::parse())
+    }
+
+    fn from_cli(cli: Cli) -> Result<Self, Box<dyn Error>> {
+        let (mode, rom_path, emu) = match cli.command {
+            Some(Command::Disasm { rom_path }) => (Mode::Disasm, Some(rom_path), cli.emu),
+            Some(Command::Assemble { source_path, output }) => {
+                let output_path = output.unwrap_or_else(|| {
+                    Path::new(&source_path).with_extension("ch8").to_string_lossy().into_owned()
+                });
+                (Mode::Assemble { output_path }, Some(source_path), cli.emu)
+            },
+            Some(Command::TraceDiff { left, right }) => (Mode::TraceDiff { left, right }, None, cli.emu),
+            Some(Command::Run { rom_path, headless, frames, output, expect, emu }) => {
+                let mode = if headless {
+                    Mode::Headless { frames, output_path: output, expect_path: expect }
+                } else {
+                    Mode::Emulate
+                };
+                (mode, Some(rom_path), emu)
+            },
+            None => (Mode::Emulate, cli.rom_path, cli.emu),
+        };
+
+        match rom_path.as_ref() {
+            Some(path) => log::debug!("rom path provided by cli: {}", path),
+            None => log::debug!("no rom path provided by cli"),
+        }
+
+        let log_level = match cli.log_level.as_str() {
+            "trace" | "5" => log::LevelFilter::Trace,
+            "debug" | "4" => log::LevelFilter::Debug,
+            "info"  | "3" => log::LevelFilter::Info,
+            "warn"  | "2" => log::LevelFilter::Warn,
+            "error" | "1" => log::LevelFilter::Error,
+            _ => log::LevelFilter::Off,
+        };
+
+        let file = match emu.config.or_else(|| ConfigFile::default_path().filter(|path| path.exists())) {
+            Some(path) => ConfigFile::load(&path)?,
+            None => ConfigFile::default(),
+        };
+
+        if emu.cpu_hz == Some(0) {
+            return Err("--cpu-hz must be at least 1".into());
+        }
+        let cli_cycles_per_frame = emu.ipf
+            .or(emu.cpu_hz.map(|hz| (hz as usize).div_ceil(60)))
+            .or_else(|| emu.speed.map(|speed| (CYCLES_PER_FRAME as f64 * speed).round() as usize));
+
+        let cli_platform = emu.quirks.map(|quirks| quirks.parse()).transpose()?;
+
+        let scale_factor = emu.scale.or(file.scale).unwrap_or(8).max(1);
+
+        let vsync = !emu.no_vsync && file.vsync.unwrap_or(true);
+
+        let stats = emu.stats;
+
+        let scaling = match emu.scaling.as_ref().or(file.scaling.as_ref()) {
+            Some(scaling) => scaling.parse()?,
+            None => Scaling::Integer,
+        };
+
+        let palette = file.palette()?.unwrap_or(PALETTE);
+
+        let keys = file.keys()?;
+        let keys = std::array::from_fn(|idx| {
+            vec![keys[idx].clone().unwrap_or_else(|| DEFAULT_KEYS[idx].to_string())]
+        });
+
+        let volume = file.volume.unwrap_or(DEFAULT_VOLUME).clamp(0.0, 1.0);
+
+        let tone = file.tone.unwrap_or(TONE_FREQ);
+
+        let timing = match emu.timing.as_ref().or(file.timing.as_ref()) {
+            Some(timing) => timing.parse()?,
+            None => Timing::default(),
+        };
+
+        let rng = match emu.rng.as_ref().or(file.rng.as_ref()) {
+            Some(rng) => rng.parse()?,
+            None => RngModel::default(),
+        };
+
+        let seed = emu.seed.or(file.seed);
+
+        let movie = match (emu.record, emu.play) {
+            (Some(path), _) => Some(MovieMode::Record(path)),
+            (None, Some(path)) => Some(MovieMode::Play { path, verify: emu.verify }),
+            (None, None) => None,
+        };
+
+        let trace = match emu.trace {
+            Some(path) => {
+                let format = match path.extension() {
+                    Some(ext) if ext.eq_ignore_ascii_case("bin") => TraceFormat::Binary,
+                    _ => TraceFormat::Text,
+                };
+                let filter = TraceFilter::parse(
+                    emu.trace_pc.as_deref(),
+                    emu.trace_ops.as_deref(),
+                    emu.trace_frames.as_deref(),
+                )?;
+                Some(TraceConfig { path, format, filter })
+            },
+            None => None,
+        };
+
+        let debug = emu.debug;
+
+        let gdb_port = emu.gdb;
+
+        let rewind_frames = emu.rewind_secs as usize * 60;
+
+        let frontend = if emu.tui {
+            Frontend::Terminal { key_timeout: Duration::from_millis(emu.key_timeout), bell: emu.bell }
+        } else {
+            Frontend::Sdl
+        };
+
+        Ok(Config {
+            mode,
+            rom_path,
+            log_level,
+            debug,
+            gdb_port,
+            rewind_frames,
+            frontend,
+            scale_factor,
+            vsync,
+            stats,
+            scaling,
+            palette,
+            keys,
+            volume,
+            tone,
+            timing,
+            cpu_hz: emu.cpu_hz,
+            rng,
+            seed,
+            movie,
+            trace,
+            file,
+            cli_platform,
+            cli_cycles_per_frame,
+        })
+    }
+
+    /// Resolves settings for a rom. The command line takes priority, then
+    /// the rom's section in the config file, then the rom database, then
+    /// the rest of the file. Keys from the database are bound alongside the
+    /// configured ones, and its colours are only used when neither part of
+    /// the file sets a palette.
+    pub fn rom_config(&self, rom: &FileDriver) -> Result<RomConfig, Box<dyn Error>> {
+        let hash = rom.hash_hex();
+        log::info!("rom sha1: {}", hash);
+        let section = self.file.rom(&hash);
+
+        let info = RomDb::bundled().lookup(&hash);
+        match &info {
+            Some(info) if info.authors.is_empty() => log::info!("found '{}' in the rom database", info.title),
+            Some(info) => log::info!("found '{}' by {} in the rom database", info.title, info.authors.join(", ")),
+            None => log::debug!("rom not in the rom database"),
+        }
+
+        let quirks = match self.cli_platform {
+            Some(platform) => platform.quirks(),
+            None => match section.and_then(|section| section.quirks.as_ref()) {
+                Some(quirks) => quirks.parse::<Platform>()?.quirks(),
+                None => match (info.as_ref().and_then(|info| info.quirks), self.file.quirks.as_ref()) {
+                    (Some(quirks), _) => quirks,
+                    (None, Some(quirks)) => quirks.parse::<Platform>()?.quirks(),
+                    (None, None) => Platform::default().quirks(),
+                },
+            },
+        };
+        log::debug!("quirks: {:?}", quirks);
+
+        let cycles_per_frame = self.cli_cycles_per_frame
+            .or(section.and_then(|section| section.ipf))
+            .or(info.as_ref().and_then(|info| info.tickrate))
+            .or(self.file.ipf)
+            .unwrap_or(CYCLES_PER_FRAME)
+            .max(1);
+        log::debug!("instructions per frame: {}", cycles_per_frame);
+
+        // The database's colours only stand in for a palette nobody configured
+        let mut palette = self.palette;
+        match section.map(|section| section.palette()).transpose()?.flatten() {
+            Some(rom_palette) => palette = rom_palette,
+            None if self.file.palette.is_none() => {
+                let colours = info.iter().flat_map(|info| &info.colours);
+                for (entry, &colour) in palette.iter_mut().zip(colours) {
+                    *entry = colour;
+                }
+            },
+            None => {},
+        }
+
+        let mut keys = self.keys.clone();
+        if let Some(info) = &info {
+            for &(name, key) in &info.keys {
+                keys[key as usize].push(name.to_string());
+            }
+        }
+
+        Ok(RomConfig {
+            // Roms the database doesn't know go by their file name
+            title: info.map_or_else(|| rom.name.clone(), |info| info.title),
+            quirks,
+            cycles_per_frame,
+            palette,
+            keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    const IBM_LOGO: &str = "tests/programs/ibm_logo.ch8";
+
+    /// Parses a command line, with `toml` as the config file
+    fn config(name: &str, toml: &str, args: &[&str]) -> Config {
+        let path = std::env::temp_dir().join(format!("chip8-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, toml).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let cli = Cli::try_parse_from(["chip8", "--config", &path].iter().chain(args)).unwrap();
+        let config = Config::from_cli(cli);
+        fs::remove_file(&path).unwrap();
+        config.unwrap()
+    }
+
+    fn unknown_rom() -> FileDriver {
+        FileDriver { data: vec![0x12, 0x00], name: "loop".to_string(), hash: [0xAB; 20] }
+    }
+
+    #[test]
+    fn the_database_wins_over_the_file() {
+        let config = config("database", "quirks = \"xochip\"\nipf = 15", &[]);
+        let ibm = config.rom_config(&FileDriver::from_string(IBM_LOGO).unwrap()).unwrap();
+        assert_eq!((ibm.title.as_str(), ibm.quirks, ibm.cycles_per_frame), ("IBM Logo", Quirks::cosmac_vip(), 15));
+
+        let unknown = config.rom_config(&unknown_rom()).unwrap();
+        assert_eq!((unknown.title.as_str(), unknown.quirks), ("loop", Quirks::xochip()));
+    }
+
+    #[test]
+    fn rom_sections_win_over_the_database_and_the_command_line_over_both() {
+        let toml = "quirks = \"xochip\"\nipf = 15\n\
+                    [rom.1BA58656810B67FD131EB9AF3E3987863BF26C90]\nquirks = \"schip11\"\nipf = 30";
+        let rom = FileDriver::from_string(IBM_LOGO).unwrap();
+
+        let ibm = config("section", toml, &[]).rom_config(&rom).unwrap();
+        assert_eq!((ibm.quirks, ibm.cycles_per_frame), (Quirks::schip11(), 30));
+
+        let ibm = config("cli", toml, &["--quirks", "chip48", "--ipf", "50"]).rom_config(&rom).unwrap();
+        assert_eq!((ibm.quirks, ibm.cycles_per_frame), (Quirks::chip48(), 50));
+
+        let unknown = config("section", toml, &[]).rom_config(&unknown_rom()).unwrap();
+        assert_eq!((unknown.quirks, unknown.cycles_per_frame), (Quirks::xochip(), 15));
+    }
+
+    #[test]
+    fn rom_palettes_win_over_the_file() {
+        let toml = "palette = [\"#000\", \"#111\", \"#222\", \"#333\"]\n\
+                    [rom.abababababababababababababababababababab]\n\
+                    palette = [\"#fff\", \"#eee\", \"#ddd\", \"#ccc\"]";
+        let config = config("palette", toml, &[]);
+        assert_eq!(config.palette[1], [0x11; 3]);
+        assert_eq!(config.rom_config(&unknown_rom()).unwrap().palette[1], [0xEE; 3]);
+
+        let rom = FileDriver::from_string(IBM_LOGO).unwrap();
+        assert_eq!(config.rom_config(&rom).unwrap().palette, config.palette);
+    }
+}
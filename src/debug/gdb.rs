@@ -0,0 +1,538 @@
+//! A GDB remote serial protocol stub, so gdb and other RSP clients can
+//! debug programs over TCP.
+//!
+//! Registers are numbered V0-VF (0-15), then I, PC, SP, DT and ST (16-20),
+//! as described to gdb by `TARGET_XML`. Addresses map directly onto `mem`.
+
+use std::{
+    fmt::Write as _,
+    io::{
+        self,
+        BufReader,
+        Read,
+        Write,
+    },
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    sync::mpsc::{
+        self,
+        Receiver,
+        Sender,
+    },
+    thread,
+};
+use crate::{
+    debug::{
+        Breakpoint,
+        DebugSession,
+        Debugger,
+        Register,
+        StopReason,
+        Watchpoint,
+    },
+    emu::{
+        cpu::CPU,
+        machine::Machine,
+    },
+};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+const REGISTER_COUNT: usize = 21;
+
+/// Signals reported in stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Sent from the connection thread to the stub
+enum Message {
+    Connected(TcpStream),
+    Packet(String),
+    Interrupt,
+    Disconnected,
+}
+
+/// A debugger controlled by a gdb client over TCP.
+///
+/// Connections are accepted and packets read on a background thread, so the
+/// emulator keeps rendering while waiting for gdb. Execution pauses whenever
+/// a client connects, and resumes when it detaches.
+pub struct GdbStub {
+    pub debugger: Debugger,
+    messages: Receiver<Message>,
+    client: Option<TcpStream>,
+}
+
+impl GdbStub {
+    /// Listens for gdb on localhost. The debugger starts paused.
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || serve(listener, sender));
+
+        println!("waiting for gdb on 127.0.0.1:{}, connect with 'target remote :{}'", port, port);
+        Ok(GdbStub { debugger: Debugger::new(), messages, client: None })
+    }
+
+    /// Sends a packet to the client, dropping the connection if that fails
+    fn send(&mut self, data: &str) {
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        if let Err(err) = write!(client, "${}#{:02x}", data, checksum) {
+            log::warn!("lost connection to gdb: {}", err);
+            self.client = None;
+        }
+    }
+
+    /// Removes all breakpoints and lets the program run freely
+    fn detach(&mut self) {
+        self.debugger.breakpoints.clear();
+        self.debugger.watchpoints.clear();
+        self.debugger.resume();
+    }
+
+    /// Handles a packet, returning the reply. Packets that resume execution
+    /// have no reply until the cpu stops again.
+    fn handle(&mut self, packet: &str, cpu: &mut CPU) -> Option<String> {
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    cpu.pc = addr;
+                }
+                if command == "c" {
+                    self.debugger.resume();
+                } else {
+                    self.debugger.step(1);
+                }
+                None
+            },
+            "k" => {
+                self.detach();
+                None
+            },
+            _ => Some(self.reply(command, args, cpu).unwrap_or_else(|| "E01".into())),
+        }
+    }
+
+    /// The reply to a packet which doesn't resume execution, or None if it's malformed
+    fn reply(&mut self, command: &str, args: &str, cpu: &mut CPU) -> Option<String> {
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => (0..REGISTER_COUNT).filter_map(|reg| read_register(cpu, reg)).collect(),
+            "G" => {
+                let mut bytes = decode_hex(args)?.into_iter();
+                for reg in 0..REGISTER_COUNT {
+                    let width = register_width(reg);
+                    let value: Vec<u8> = bytes.by_ref().take(width).collect();
+                    if value.len() < width {
+                        break;
+                    }
+                    write_register(cpu, reg, &value);
+                }
+                "OK".into()
+            },
+            "p" => read_register(cpu, usize::from_str_radix(args, 16).ok()?)?,
+            "P" => {
+                let (reg, value) = args.split_once('=')?;
+                let reg = usize::from_str_radix(reg, 16).ok().filter(|&reg| reg < REGISTER_COUNT)?;
+                let value = decode_hex(value).filter(|value| value.len() == register_width(reg))?;
+                write_register(cpu, reg, &value);
+                "OK".into()
+            },
+            "m" => {
+                let (addr, len) = parse_range(args)?;
+                // Reads past the end of mem are cut short
+                let bytes = cpu.mem.get(addr..addr.saturating_add(len).min(cpu.mem.len()))?;
+                if bytes.is_empty() {
+                    return None;
+                }
+                encode_hex(bytes)
+            },
+            "M" => {
+                let (range, data) = args.split_once(':')?;
+                let (addr, len) = parse_range(range)?;
+                let data = decode_hex(data).filter(|data| data.len() == len)?;
+                cpu.mem.get_mut(addr..addr.saturating_add(len))?.copy_from_slice(&data);
+                "OK".into()
+            },
+            "Z" | "z" => self.set_breakpoint(command == "Z", args)?,
+            "H" => "OK".into(),
+            "D" => {
+                self.detach();
+                "OK".into()
+            },
+            "q" if args.starts_with("Supported") => "PacketSize=1000;qXfer:features:read+".into(),
+            "q" if args == "Attached" => "1".into(),
+            "q" => match args.strip_prefix("Xfer:features:read:target.xml:") {
+                Some(range) => {
+                    let (offset, len) = parse_range(range)?;
+                    let chunk = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                    if chunk.len() > len {
+                        format!("m{}", &chunk[..len])
+                    } else {
+                        format!("l{}", chunk)
+                    }
+                },
+                None => String::new(),
+            },
+            // An empty reply tells gdb the packet isn't supported
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Handles Z (insert) and z (remove) packets for breakpoints and write watchpoints
+    fn set_breakpoint(&mut self, insert: bool, args: &str) -> Option<String> {
+        let (kind, range) = args.split_once(',')?;
+        let (addr, len) = range.split_once(',')?;
+        let addr = u16::from_str_radix(addr, 16).ok()?;
+        let len = u16::from_str_radix(len, 16).ok()?;
+
+        match kind {
+            // Software and hardware breakpoints are handled the same way
+            "0" | "1" => {
+                let breakpoint = Breakpoint { addr: Some(addr), condition: None };
+                if insert {
+                    self.debugger.breakpoints.push(breakpoint);
+                } else {
+                    self.debugger.breakpoints.retain(|bp| *bp != breakpoint);
+                }
+            },
+            "2" => {
+                let watchpoint = Watchpoint { start: addr, end: addr.saturating_add(len.max(1) - 1) };
+                if insert {
+                    self.debugger.watchpoints.push(watchpoint);
+                } else {
+                    self.debugger.watchpoints.retain(|wp| *wp != watchpoint);
+                }
+            },
+            // Read and access watchpoints aren't supported
+            _ => return Some(String::new()),
+        }
+        Some("OK".into())
+    }
+}
+
+impl DebugSession for GdbStub {
+    fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    fn poll(&mut self, cpu: &mut CPU) {
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Connected(client) => {
+                    log::info!("gdb connected");
+                    self.client = Some(client);
+                    self.debugger.pause();
+                },
+                Message::Packet(packet) => {
+                    log::trace!("gdb: {}", packet);
+                    if let Some(reply) = self.handle(&packet, cpu) {
+                        self.send(&reply);
+                    }
+                },
+                Message::Interrupt => {
+                    self.debugger.pause();
+                    self.send(&format!("S{:02x}", SIGINT));
+                },
+                Message::Disconnected => {
+                    log::info!("gdb disconnected");
+                    self.client = None;
+                    self.detach();
+                },
+            }
+        }
+    }
+
+    fn run(&mut self, machine: &mut Machine) {
+        if let Some(reason) = self.debugger.run(machine) {
+            let reply = match reason {
+                StopReason::Watchpoint { addr, .. } => format!("T{:02x}watch:{:x};", SIGTRAP, addr),
+                StopReason::Fault(err) => {
+                    log::error!("cpu fault: {}", err);
+                    format!("S{:02x}", SIGILL)
+                },
+                StopReason::Interrupted => format!("S{:02x}", SIGINT),
+                StopReason::Breakpoint(_) | StopReason::Step => format!("S{:02x}", SIGTRAP),
+            };
+            self.send(&reply);
+        }
+    }
+}
+
+impl Drop for GdbStub {
+    /// Tells the client the program has exited
+    fn drop(&mut self) {
+        self.send("W00");
+    }
+}
+
+/// Accepts one gdb connection at a time, forwarding its packets to the stub
+fn serve(listener: TcpListener, sender: Sender<Message>) {
+    for stream in listener.incoming() {
+        let stream = match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+            Ok((writer, reader)) => {
+                if sender.send(Message::Connected(writer)).is_err() {
+                    return;
+                }
+                reader
+            },
+            Err(err) => {
+                log::warn!("failed to accept gdb connection: {}", err);
+                continue;
+            },
+        };
+        if let Err(err) = read_packets(stream, &sender) {
+            log::debug!("gdb connection closed: {}", err);
+        }
+        if sender.send(Message::Disconnected).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reads `$data#checksum` packets and ctrl-c interrupts until the connection closes,
+/// acknowledging each packet
+fn read_packets(stream: TcpStream, sender: &Sender<Message>) -> io::Result<()> {
+    let mut acks = stream.try_clone()?;
+    let mut bytes = BufReader::new(stream).bytes();
+    let mut next = move || bytes.next().unwrap_or_else(|| Err(io::ErrorKind::UnexpectedEof.into()));
+
+    loop {
+        let message = match next()? {
+            0x03 => Message::Interrupt,
+            b'$' => {
+                let mut data = Vec::new();
+                loop {
+                    match next()? {
+                        b'#' => break,
+                        byte => data.push(byte),
+                    }
+                }
+                let checksum = [next()?, next()?];
+                let valid = std::str::from_utf8(&checksum).ok()
+                    .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                    .is_some_and(|checksum| checksum == data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+                if !valid {
+                    acks.write_all(b"-")?;
+                    continue;
+                }
+                acks.write_all(b"+")?;
+                Message::Packet(String::from_utf8_lossy(&data).into_owned())
+            },
+            // Acks from the client, which TCP makes redundant
+            _ => continue,
+        };
+        if sender.send(message).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// The debugger register for a gdb register number
+fn register(reg: usize) -> Option<Register> {
+    match reg {
+        0..=15 => Some(Register::V(reg as u8)),
+        16 => Some(Register::I),
+        17 => Some(Register::PC),
+        18 => Some(Register::SP),
+        19 => Some(Register::DT),
+        20 => Some(Register::ST),
+        _ => None,
+    }
+}
+
+/// Register size in bytes, per the target description
+fn register_width(reg: usize) -> usize {
+    match register(reg) {
+        Some(Register::I | Register::PC) => 2,
+        _ => 1,
+    }
+}
+
+/// A register's value as little-endian hex
+fn read_register(cpu: &CPU, reg: usize) -> Option<String> {
+    let value = register(reg)?.read(cpu).to_le_bytes();
+    Some(encode_hex(&value[..register_width(reg)]))
+}
+
+/// Sets a register from its little-endian bytes
+fn write_register(cpu: &mut CPU, reg: usize, bytes: &[u8]) {
+    if let Some(register) = register(reg) {
+        let value = bytes.iter().rev().fold(0u16, |value, &byte| value << 8 | byte as u16);
+        register.write(cpu, value);
+    }
+}
+
+/// Parses an `addr,len` pair of hex numbers
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((usize::from_str_radix(addr, 16).ok()?, usize::from_str_radix(len, 16).ok()?))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(s.get(idx..idx + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stub() -> GdbStub {
+        GdbStub { debugger: Debugger::new(), messages: mpsc::channel().1, client: None }
+    }
+
+    fn reply(stub: &mut GdbStub, cpu: &mut CPU, packet: &str) -> String {
+        stub.handle(packet, cpu).unwrap()
+    }
+
+    #[test]
+    fn registers_are_v0_to_vf_then_i_pc_sp_dt_st() {
+        let (mut stub, mut cpu) = (stub(), CPU::initialize());
+        cpu.v = core::array::from_fn(|x| x as u8 * 0x11);
+        cpu.i = 0x1234;
+        cpu.pc = 0x0abc;
+        cpu.sp = 2;
+        cpu.dt = 3;
+        cpu.st = 4;
+        let registers = "00112233445566778899aabbccddeeff".to_owned() + "3412" + "bc0a" + "02" + "03" + "04";
+        assert_eq!(reply(&mut stub, &mut cpu, "g"), registers);
+        assert_eq!(reply(&mut stub, &mut cpu, "pf"), "ff");
+        assert_eq!(reply(&mut stub, &mut cpu, "p10"), "3412");
+        assert_eq!(reply(&mut stub, &mut cpu, "p11"), "bc0a");
+        assert_eq!(reply(&mut stub, &mut cpu, "p14"), "04");
+        assert_eq!(reply(&mut stub, &mut cpu, "p15"), "E01");
+
+        let mut other = CPU::initialize();
+        assert_eq!(reply(&mut stub, &mut other, &format!("G{}", registers)), "OK");
+        assert_eq!((other.v, other.i, other.pc, other.sp, other.dt, other.st), (cpu.v, 0x1234, 0x0abc, 2, 3, 4));
+
+        assert_eq!(reply(&mut stub, &mut cpu, "P10=cdab"), "OK");
+        assert_eq!(cpu.i, 0xabcd);
+        assert_eq!(reply(&mut stub, &mut cpu, "P3=7f"), "OK");
+        assert_eq!(cpu.v[3], 0x7f);
+        // Values must be the register's width
+        assert_eq!(reply(&mut stub, &mut cpu, "P11=01"), "E01");
+        assert_eq!(reply(&mut stub, &mut cpu, "P0=0102"), "E01");
+    }
+
+    #[test]
+    fn memory_reads_and_writes() {
+        let (mut stub, mut cpu) = (stub(), CPU::initialize());
+        assert_eq!(reply(&mut stub, &mut cpu, "M300,3:a1b2c3"), "OK");
+        assert_eq!(cpu.mem[0x300..0x303], [0xa1, 0xb2, 0xc3]);
+        assert_eq!(reply(&mut stub, &mut cpu, "m2ff,5"), "00a1b2c300");
+
+        // Reads running off the end are cut short, and past it fail
+        cpu.mem[0xffff] = 0x42;
+        assert_eq!(reply(&mut stub, &mut cpu, "mfffe,10"), "0042");
+        assert_eq!(reply(&mut stub, &mut cpu, "m10000,1"), "E01");
+        assert_eq!(reply(&mut stub, &mut cpu, "Mffff,2:0102"), "E01");
+        assert_eq!(reply(&mut stub, &mut cpu, "M300,2:01"), "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let (mut stub, mut cpu) = (stub(), CPU::initialize());
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,204,2"), "OK");
+        assert_eq!(stub.debugger.breakpoints, [Breakpoint { addr: Some(0x204), condition: None }]);
+        assert_eq!(reply(&mut stub, &mut cpu, "z0,204,2"), "OK");
+        assert!(stub.debugger.breakpoints.is_empty());
+
+        assert_eq!(reply(&mut stub, &mut cpu, "Z2,300,4"), "OK");
+        assert_eq!(stub.debugger.watchpoints, [Watchpoint { start: 0x300, end: 0x303 }]);
+        assert_eq!(reply(&mut stub, &mut cpu, "z2,300,4"), "OK");
+        assert!(stub.debugger.watchpoints.is_empty());
+
+        // Read watchpoints aren't supported
+        assert_eq!(reply(&mut stub, &mut cpu, "Z3,300,1"), "");
+    }
+
+    #[test]
+    fn target_description_comes_in_chunks() {
+        let (mut stub, mut cpu) = (stub(), CPU::initialize());
+        let first = reply(&mut stub, &mut cpu, "qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+
+        let mut xml = String::new();
+        let mut offset = 0;
+        loop {
+            let chunk = reply(&mut stub, &mut cpu, &format!("qXfer:features:read:target.xml:{:x},100", offset));
+            let (kind, data) = chunk.split_at(1);
+            xml.push_str(data);
+            offset += data.len();
+            if kind == "l" {
+                break;
+            }
+            assert_eq!(kind, "m");
+        }
+        assert_eq!(xml, TARGET_XML);
+        assert_eq!(reply(&mut stub, &mut cpu, &format!("qXfer:features:read:target.xml:{:x},10", xml.len() + 5)), "l");
+    }
+
+    #[test]
+    fn packets_with_bad_checksums_are_rejected() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (sender, messages) = mpsc::channel();
+        let reader = thread::spawn(move || read_packets(server, &sender));
+
+        client.write_all(b"$g#00$g#67\x03").unwrap();
+        let mut acks = [0; 2];
+        client.read_exact(&mut acks).unwrap();
+        assert_eq!(&acks, b"-+");
+        drop(client);
+        assert!(reader.join().unwrap().is_err());
+
+        let messages: Vec<Message> = messages.try_iter().collect();
+        assert!(matches!(messages.as_slice(), [Message::Packet(packet), Message::Interrupt] if packet == "g"));
+    }
+}
@@ -0,0 +1,604 @@
+pub mod repl;
+pub mod gdb;
+
+use std::{
+    fmt,
+    fmt::Write,
+    str::FromStr,
+};
+use crate::emu::{
+    cpu::{
+        CPU,
+        StepOutcome,
+    },
+    error::CpuError,
+    instruction::Instruction,
+    machine::Machine,
+};
+
+/// A register that breakpoint conditions can test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    PC,
+    SP,
+    DT,
+    ST,
+}
+
+impl Register {
+    pub fn read(self, cpu: &CPU) -> u16 {
+        match self {
+            Register::V(x) => cpu.v[x as usize] as u16,
+            Register::I    => cpu.i,
+            Register::PC   => cpu.pc,
+            Register::SP   => cpu.sp as u16,
+            Register::DT   => cpu.dt as u16,
+            Register::ST   => cpu.st as u16,
+        }
+    }
+
+    /// Sets the register, truncating value to its width
+    pub fn write(self, cpu: &mut CPU, value: u16) {
+        match self {
+            Register::V(x) => cpu.v[x as usize] = value as u8,
+            Register::I    => cpu.i = value,
+            Register::PC   => cpu.pc = value,
+            // The stack pointer can't be allowed past the end of the stack
+            Register::SP   => cpu.sp = value.min(cpu.stack.len() as u16) as u8,
+            Register::DT   => cpu.dt = value as u8,
+            Register::ST   => cpu.st = value as u8,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "i"  => Ok(Register::I),
+            "pc" => Ok(Register::PC),
+            "sp" => Ok(Register::SP),
+            "dt" => Ok(Register::DT),
+            "st" => Ok(Register::ST),
+            reg => reg.strip_prefix('v')
+                .filter(|digit| digit.len() == 1)
+                .and_then(|digit| u8::from_str_radix(digit, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I    => write!(f, "I"),
+            Register::PC   => write!(f, "PC"),
+            Register::SP   => write!(f, "SP"),
+            Register::DT   => write!(f, "DT"),
+            Register::ST   => write!(f, "ST"),
+        }
+    }
+}
+
+/// A comparison between a register and a constant, e.g. `v3 == 5`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Condition {
+    pub reg: Register,
+    pub cmp: Comparison,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Condition {
+    pub fn holds(&self, cpu: &CPU) -> bool {
+        let reg = self.reg.read(cpu);
+        match self.cmp {
+            Comparison::Eq => reg == self.value,
+            Comparison::Ne => reg != self.value,
+            Comparison::Lt => reg < self.value,
+            Comparison::Le => reg <= self.value,
+            Comparison::Gt => reg > self.value,
+            Comparison::Ge => reg >= self.value,
+        }
+    }
+
+    /// Parses a condition from its three words, e.g. `["v3", "==", "5"]`
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        let [reg, cmp, value] = words else {
+            return Err("expected a condition like 'v3 == 5'".into());
+        };
+        let cmp = match *cmp {
+            "==" => Comparison::Eq,
+            "!=" => Comparison::Ne,
+            "<"  => Comparison::Lt,
+            "<=" => Comparison::Le,
+            ">"  => Comparison::Gt,
+            ">=" => Comparison::Ge,
+            _ => return Err(format!("unknown comparison '{}'", cmp)),
+        };
+        Ok(Condition { reg: reg.parse()?, cmp, value: parse_number(value)? })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cmp = match self.cmp {
+            Comparison::Eq => "==",
+            Comparison::Ne => "!=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        };
+        write!(f, "{} {} {:#x}", self.reg, cmp, self.value)
+    }
+}
+
+/// Parses a decimal or 0x-prefixed hex number
+pub fn parse_number(s: &str) -> Result<u16, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", s))
+}
+
+/// Stops execution when the PC reaches addr and/or a condition holds.
+/// A breakpoint with neither stops on every instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: Option<u16>,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    fn hit(&self, cpu: &CPU) -> bool {
+        self.addr.is_none_or(|addr| cpu.pc == addr)
+            && self.condition.is_none_or(|cond| cond.holds(cpu))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.addr, self.condition) {
+            (Some(addr), Some(cond)) => write!(f, "{:#06x} if {}", addr, cond),
+            (Some(addr), None) => write!(f, "{:#06x}", addr),
+            (None, Some(cond)) => write!(f, "if {}", cond),
+            (None, None) => write!(f, "always"),
+        }
+    }
+}
+
+/// Stops execution after any write to mem in start..=end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Why the debugger paused execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Watchpoint { addr: u16, old: u8, new: u8 },
+    Step,
+    Fault(CpuError),
+    Interrupted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(idx) => write!(f, "breakpoint {}", idx),
+            StopReason::Watchpoint { addr, old, new } =>
+                write!(f, "watchpoint: mem[{:#06x}] {:#04x} -> {:#04x}", addr, old, new),
+            StopReason::Step => write!(f, "step"),
+            StopReason::Fault(err) => write!(f, "cpu fault: {}", err),
+            StopReason::Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+/// How execution proceeds until the next stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Paused,
+    Running,
+    /// Execute a number of instructions, then pause
+    Step(usize),
+    /// Run until a call returns to addr with the stack at depth sp
+    StepOver { addr: u16, sp: u8 },
+    /// Run until the stack drops below depth sp
+    StepOut { sp: u8 },
+    /// Run until the PC reaches addr
+    RunTo { addr: u16 },
+}
+
+/// Controls CPU execution with breakpoints, watchpoints and stepping.
+#[derive(Debug)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    mode: RunMode,
+    /// Breakpoints are not checked for the first instruction after resuming,
+    /// so that execution can continue past the breakpoint that stopped it
+    resuming: bool,
+}
+
+#[allow(clippy::new_without_default)]
+impl Debugger {
+    /// Creates a debugger which starts paused
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            mode: RunMode::Paused,
+            resuming: false,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.mode == RunMode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = RunMode::Paused;
+    }
+
+    pub fn resume(&mut self) {
+        self.set_mode(RunMode::Running);
+    }
+
+    /// Execute count instructions, then pause
+    pub fn step(&mut self, count: usize) {
+        self.set_mode(RunMode::Step(count.max(1)));
+    }
+
+    /// Execute one instruction, running 2NNN calls to completion
+    pub fn step_over(&mut self, cpu: &CPU) {
+        let opcode = (cpu.mem[cpu.pc as usize] as u16) << 8
+            | cpu.mem.get(cpu.pc as usize + 1).copied().unwrap_or(0) as u16;
+        match Instruction::decode(opcode) {
+            Some(Instruction::Call { .. }) => self.set_mode(RunMode::StepOver {
+                addr: cpu.pc.wrapping_add(2),
+                sp: cpu.sp,
+            }),
+            _ => self.step(1),
+        }
+    }
+
+    /// Run until the current subroutine returns
+    pub fn step_out(&mut self, cpu: &CPU) -> Result<(), String> {
+        if cpu.sp == 0 {
+            return Err("not inside a subroutine".into());
+        }
+        self.set_mode(RunMode::StepOut { sp: cpu.sp });
+        Ok(())
+    }
+
+    /// Run until the PC reaches addr
+    pub fn run_to(&mut self, addr: u16) {
+        self.set_mode(RunMode::RunTo { addr });
+    }
+
+    fn set_mode(&mut self, mode: RunMode) {
+        self.mode = mode;
+        self.resuming = true;
+    }
+
+    /// Runs the rest of the machine's frame under its timing model, unless
+    /// paused. The timers only tick once the frame is done, so a paused
+    /// frame carries on where it stopped.
+    /// Returns the reason execution stopped, if it did.
+    pub fn run(&mut self, machine: &mut Machine) -> Option<StopReason> {
+        while !machine.frame_done() {
+            if self.paused() {
+                return None;
+            }
+
+            if let Some(reason) = self.step_machine(machine) {
+                self.mode = RunMode::Paused;
+                return Some(reason);
+            }
+        }
+        machine.end_frame();
+        None
+    }
+
+    /// Executes one instruction, checking for any reason to stop
+    fn step_machine(&mut self, machine: &mut Machine) -> Option<StopReason> {
+        let cpu = &machine.cpu;
+        if !self.resuming {
+            if let Some(idx) = self.breakpoints.iter().position(|bp| bp.hit(cpu)) {
+                return Some(StopReason::Breakpoint(idx));
+            }
+        }
+
+        let watched: Vec<(Watchpoint, Vec<u8>)> = self.watchpoints.iter()
+            .map(|wp| (*wp, cpu.mem[wp.start as usize..=wp.end as usize].to_vec()))
+            .collect();
+
+        let outcome = match machine.step() {
+            Ok(outcome) => outcome,
+            Err(err) => return Some(StopReason::Fault(err)),
+        };
+        // A step blocked on the next frame completes once the frame ends,
+        // so timers advance at the same rate as when running freely
+        if outcome != StepOutcome::Executed {
+            return None;
+        }
+        self.resuming = false;
+
+        let cpu = &machine.cpu;
+        for (wp, old) in watched {
+            let new = &cpu.mem[wp.start as usize..=wp.end as usize];
+            if let Some(offset) = old.iter().zip(new).position(|(a, b)| a != b) {
+                return Some(StopReason::Watchpoint {
+                    addr: wp.start + offset as u16,
+                    old: old[offset],
+                    new: new[offset],
+                });
+            }
+        }
+
+        match self.mode {
+            RunMode::Step(1) => Some(StopReason::Step),
+            RunMode::Step(n) => {
+                self.mode = RunMode::Step(n - 1);
+                None
+            },
+            RunMode::StepOver { addr, sp } if cpu.pc == addr && cpu.sp == sp => Some(StopReason::Step),
+            RunMode::StepOut { sp } if cpu.sp < sp => Some(StopReason::Step),
+            RunMode::RunTo { addr } if cpu.pc == addr => Some(StopReason::Step),
+            _ => None,
+        }
+    }
+}
+
+/// An interface to the debugger, which takes over stepping the CPU
+/// from the main loop while it's attached
+pub trait DebugSession {
+    fn debugger(&self) -> &Debugger;
+
+    /// Handles any commands received since the last poll
+    fn poll(&mut self, cpu: &mut CPU);
+
+    /// Runs the rest of the machine's frame, reporting any stop
+    fn run(&mut self, machine: &mut Machine);
+}
+
+/* Views */
+
+/// V0-VF, I, PC, SP and the timers
+pub fn registers_view(cpu: &CPU) -> String {
+    let mut out = String::new();
+    for row in 0..2 {
+        for x in row * 8..row * 8 + 8 {
+            let _ = write!(out, "V{:X}={:02x} ", x, cpu.v[x]);
+        }
+        out.push('\n');
+    }
+    let _ = writeln!(out, "I={:#06x} PC={:#06x} SP={} DT={} ST={}", cpu.i, cpu.pc, cpu.sp, cpu.dt, cpu.st);
+    out
+}
+
+/// The active stack frames, innermost first
+pub fn stack_view(cpu: &CPU) -> String {
+    if cpu.sp == 0 {
+        return "stack: empty\n".into();
+    }
+    let frames: Vec<String> = cpu.stack[..cpu.sp as usize]
+        .iter()
+        .rev()
+        .map(|addr| format!("{:#06x}", addr))
+        .collect();
+    format!("stack: {}\n", frames.join(" "))
+}
+
+/// Disassembly of count instructions starting before addr, marking the PC and breakpoints
+pub fn disassembly_view(cpu: &CPU, debugger: &Debugger, addr: u16, count: usize) -> String {
+    let mut out = String::new();
+    let mut addr = addr.saturating_sub(2 * (count as u16 / 3)) as usize;
+
+    for _ in 0..count {
+        let Some(bytes) = cpu.mem.get(addr..addr + 2) else { break };
+        let opcode = (bytes[0] as u16) << 8 | bytes[1] as u16;
+        let marker = if addr == cpu.pc as usize { "=>" } else { "  " };
+        let bp = if debugger.breakpoints.iter().any(|bp| bp.addr == Some(addr as u16)) { "*" } else { " " };
+        let text = match Instruction::decode(opcode) {
+            Some(Instruction::LoadLongI) => match cpu.mem.get(addr + 2..addr + 4) {
+                Some(long) => format!("LD I, LONG {:#06x}", (long[0] as u16) << 8 | long[1] as u16),
+                None => "LD I, LONG ?".into(),
+            },
+            Some(instruction) => instruction.to_string(),
+            None => format!("db {:#04x}, {:#04x}", bytes[0], bytes[1]),
+        };
+        let _ = writeln!(out, "{}{} {:#06x}  {:04x}  {}", marker, bp, addr, opcode, text);
+        addr += 2;
+    }
+    out
+}
+
+/// Hexdump of len bytes of mem starting at addr
+pub fn memory_view(cpu: &CPU, addr: u16, len: usize) -> String {
+    let mut out = String::new();
+    let start = addr as usize;
+    let end = (start + len).min(cpu.mem.len());
+    for (row_idx, row) in cpu.mem[start..end].chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let _ = writeln!(out, "{:#06x}  {}", start + row_idx * 16, hex.join(" "));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::{
+        machine::InputState,
+        quirks::Quirks,
+        timing::Timing,
+    };
+
+    fn machine_with(program: &[u8]) -> Machine {
+        let mut machine = Machine::new(Quirks { display_wait: true, ..Quirks::default() });
+        machine.load(program).unwrap();
+        machine
+    }
+
+    #[test]
+    fn stepping_into_a_wait_finishes_the_frame() {
+        // DXYN, then V0 = 5, with the display wait quirk
+        let mut machine = machine_with(&[0xD0, 0x01, 0x60, 0x05]);
+        machine.cpu.dt = 10;
+
+        let mut debugger = Debugger::new();
+        debugger.step(1);
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.pc, machine.cpu.dt), (0x202, 10));
+
+        debugger.step(1);
+        assert_eq!(debugger.run(&mut machine), None);
+        assert_eq!((machine.cpu.pc, machine.cpu.dt), (0x202, 9));
+
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.v[0], machine.cpu.dt), (5, 9));
+    }
+
+    #[test]
+    fn running_follows_the_machine_timing() {
+        // V0 += 1, then draw and loop
+        let program = [0x70, 0x01, 0xD1, 0x11, 0x12, 0x00];
+        for timing in [Timing::Fixed, Timing::Vip] {
+            let mut free = machine_with(&program);
+            let mut debugged = machine_with(&program);
+            free.timing = timing;
+            debugged.timing = timing;
+
+            let mut debugger = Debugger::new();
+            debugger.resume();
+            for _ in 0..5 {
+                free.run_frame(&InputState::default());
+                assert_eq!(debugger.run(&mut debugged), None);
+                assert_eq!((debugged.cpu.pc, debugged.cpu.v[0]), (free.cpu.pc, free.cpu.v[0]), "{} timing", timing);
+            }
+        }
+    }
+
+    /// Calls a subroutine which writes V0 and V1 to 0x300, then loops
+    const PROGRAM: [u8; 16] = [
+        0x22, 0x08, // 200: CALL 208
+        0x60, 0x01, // 202: V0 = 1
+        0x12, 0x04, // 204: JP 204
+        0x00, 0x00,
+        0x61, 0x05, // 208: V1 = 5
+        0xA3, 0x00, // 20A: I = 300
+        0xF1, 0x55, // 20C: store V0-V1 at I
+        0x00, 0xEE, // 20E: RET
+    ];
+
+    #[test]
+    fn conditions() {
+        let cond = Condition::parse(&["v3", "<=", "0x10"]).unwrap();
+        assert_eq!(cond, Condition { reg: Register::V(3), cmp: Comparison::Le, value: 0x10 });
+        assert_eq!(cond.to_string(), "V3 <= 0x10");
+        assert_eq!(Condition::parse(&["PC", "!=", "512"]).unwrap().value, 0x200);
+
+        assert!(Condition::parse(&["v3", "=>", "1"]).is_err());
+        assert!(Condition::parse(&["vg", "==", "1"]).is_err());
+        assert!(Condition::parse(&["v3", "==", "0xg"]).is_err());
+        assert!(Condition::parse(&["v3", "=="]).is_err());
+
+        let mut cpu = CPU::initialize();
+        cpu.v[3] = 5;
+        let holds = |cmp: &str, value: &str| Condition::parse(&["v3", cmp, value]).unwrap().holds(&cpu);
+        assert!(holds("==", "5") && !holds("==", "6"));
+        assert!(holds("!=", "6") && !holds("!=", "5"));
+        assert!(holds("<", "6") && !holds("<", "5"));
+        assert!(holds("<=", "5") && !holds("<=", "4"));
+        assert!(holds(">", "4") && !holds(">", "5"));
+        assert!(holds(">=", "5") && !holds(">=", "6"));
+    }
+
+    #[test]
+    fn breakpoints_stop_before_the_instruction_and_resume_past_it() {
+        let mut machine = machine_with(&PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.push(Breakpoint { addr: Some(0x20A), condition: None });
+        debugger.resume();
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Breakpoint(0)));
+        assert_eq!((machine.cpu.pc, machine.cpu.v[1], machine.cpu.i), (0x20A, 5, 0));
+        assert!(debugger.paused());
+        assert_eq!(debugger.run(&mut machine), None);
+        assert_eq!(machine.cpu.pc, 0x20A);
+
+        // Resuming runs the instruction the breakpoint stopped on
+        debugger.resume();
+        assert_eq!(debugger.run(&mut machine), None);
+        assert_eq!((machine.cpu.pc, machine.cpu.v[0]), (0x204, 1));
+    }
+
+    #[test]
+    fn conditional_breakpoints_stop_once_they_hold() {
+        let mut machine = machine_with(&PROGRAM);
+        let mut debugger = Debugger::new();
+        let condition = Condition::parse(&["v1", "==", "5"]).ok();
+        debugger.breakpoints.push(Breakpoint { addr: None, condition });
+        debugger.resume();
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Breakpoint(0)));
+        assert_eq!(machine.cpu.pc, 0x20A);
+    }
+
+    #[test]
+    fn watchpoints_report_the_old_and_new_value() {
+        let mut machine = machine_with(&PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.watchpoints.push(Watchpoint { start: 0x301, end: 0x302 });
+        debugger.resume();
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Watchpoint { addr: 0x301, old: 0, new: 5 }));
+        assert_eq!(machine.cpu.pc, 0x20E);
+    }
+
+    #[test]
+    fn step_over_runs_calls_to_completion() {
+        let mut machine = machine_with(&PROGRAM);
+        let mut debugger = Debugger::new();
+        debugger.step_over(&machine.cpu);
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.pc, machine.cpu.sp, machine.cpu.v[1]), (0x202, 0, 5));
+
+        // Anything else is a single step
+        debugger.step_over(&machine.cpu);
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.pc, machine.cpu.v[0]), (0x204, 1));
+    }
+
+    #[test]
+    fn step_out_and_run_to() {
+        let mut machine = machine_with(&PROGRAM);
+        let mut debugger = Debugger::new();
+        assert!(debugger.step_out(&machine.cpu).is_err());
+
+        debugger.run_to(0x20C);
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.pc, machine.cpu.sp), (0x20C, 1));
+
+        debugger.step_out(&machine.cpu).unwrap();
+        assert_eq!(debugger.run(&mut machine), Some(StopReason::Step));
+        assert_eq!((machine.cpu.pc, machine.cpu.sp, machine.cpu.mem[0x301]), (0x202, 0, 5));
+    }
+}
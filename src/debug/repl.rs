@@ -0,0 +1,227 @@
+use std::{
+    io::{
+        self,
+        BufRead,
+        Write,
+    },
+    sync::mpsc::{
+        self,
+        Receiver,
+    },
+    thread,
+};
+use crate::{
+    debug::{
+        self,
+        Breakpoint,
+        Condition,
+        DebugSession,
+        Debugger,
+        StopReason,
+        Watchpoint,
+        parse_number,
+    },
+    emu::{
+        cpu::CPU,
+        machine::Machine,
+    },
+};
+
+const HELP: &str = "\
+commands:
+  c, continue              resume execution
+  p, pause                 pause execution
+  s, step [n]              execute n instructions (default 1)
+  n, next                  step over 2NNN calls
+  finish                   run until the current subroutine returns
+  until <addr>             run until the PC reaches addr
+  b, break <addr> [if <reg> <cmp> <value>]
+  b, break if <reg> <cmp> <value>
+                           add a breakpoint, optionally conditional
+  watch <start> [end]      stop after writes to mem[start..=end]
+  delete [n]               delete breakpoint n, or all breakpoints and watchpoints
+  delete watch <n>         delete watchpoint n
+  info                     list breakpoints and watchpoints
+  r, regs                  show registers
+  stack                    show the stack
+  dis [addr] [count]       disassemble around addr (default PC)
+  mem <addr> [len]         dump memory
+  h, help                  show this message";
+
+/// A debugger driven by commands typed into the terminal.
+///
+/// Lines are read from stdin on a background thread, so the emulator keeps
+/// polling input and rendering while waiting for commands.
+pub struct Repl {
+    pub debugger: Debugger,
+    commands: Receiver<String>,
+}
+
+impl Repl {
+    /// Starts reading commands from stdin. The debugger starts paused.
+    pub fn start() -> Self {
+        let (sender, commands) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        println!("chip8 debugger: type 'help' for a list of commands");
+        prompt();
+        Repl { debugger: Debugger::new(), commands }
+    }
+
+    /// Prints why execution stopped, followed by the current machine state
+    pub fn report(&self, cpu: &CPU, reason: StopReason) {
+        println!("\nstopped: {}", reason);
+        self.print_state(cpu);
+        prompt();
+    }
+
+    fn print_state(&self, cpu: &CPU) {
+        print!("{}", debug::registers_view(cpu));
+        print!("{}", debug::stack_view(cpu));
+        print!("{}", debug::disassembly_view(cpu, &self.debugger, cpu.pc, 9));
+    }
+
+    fn execute(&mut self, line: &str, cpu: &mut CPU) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(());
+        };
+
+        match command {
+            "c" | "continue" => self.debugger.resume(),
+            "p" | "pause" => {
+                self.debugger.pause();
+                self.report(cpu, StopReason::Interrupted);
+            },
+            "s" | "step" => {
+                let count = match args.first() {
+                    Some(n) => parse_number(n)? as usize,
+                    None => 1,
+                };
+                self.debugger.step(count);
+            },
+            "n" | "next" => self.debugger.step_over(cpu),
+            "finish" => self.debugger.step_out(cpu)?,
+            "until" => {
+                let addr = args.first().ok_or("usage: until <addr>")?;
+                self.debugger.run_to(parse_number(addr)?);
+            },
+            "b" | "break" => {
+                let breakpoint = match args {
+                    ["if", condition @ ..] => Breakpoint {
+                        addr: None,
+                        condition: Some(Condition::parse(condition)?),
+                    },
+                    [addr] => Breakpoint { addr: Some(parse_number(addr)?), condition: None },
+                    [addr, "if", condition @ ..] => Breakpoint {
+                        addr: Some(parse_number(addr)?),
+                        condition: Some(Condition::parse(condition)?),
+                    },
+                    _ => return Err("usage: break <addr> [if <reg> <cmp> <value>]".into()),
+                };
+                println!("breakpoint {}: {}", self.debugger.breakpoints.len(), breakpoint);
+                self.debugger.breakpoints.push(breakpoint);
+            },
+            "watch" => {
+                let (start, end) = match args {
+                    [start] => (parse_number(start)?, parse_number(start)?),
+                    [start, end] => (parse_number(start)?, parse_number(end)?),
+                    _ => return Err("usage: watch <start> [end]".into()),
+                };
+                if end < start {
+                    return Err("watch range ends before it starts".into());
+                }
+                println!("watchpoint {}: {:#06x}..={:#06x}", self.debugger.watchpoints.len(), start, end);
+                self.debugger.watchpoints.push(Watchpoint { start, end });
+            },
+            "delete" => match args {
+                ["watch", idx] => {
+                    let idx = parse_number(idx)? as usize;
+                    if idx >= self.debugger.watchpoints.len() {
+                        return Err(format!("no watchpoint {}", idx));
+                    }
+                    self.debugger.watchpoints.remove(idx);
+                },
+                [idx] => {
+                    let idx = parse_number(idx)? as usize;
+                    if idx >= self.debugger.breakpoints.len() {
+                        return Err(format!("no breakpoint {}", idx));
+                    }
+                    self.debugger.breakpoints.remove(idx);
+                },
+                [] => {
+                    self.debugger.breakpoints.clear();
+                    self.debugger.watchpoints.clear();
+                },
+                _ => return Err("usage: delete [n] or delete watch <n>".into()),
+            },
+            "info" => {
+                for (idx, bp) in self.debugger.breakpoints.iter().enumerate() {
+                    println!("breakpoint {}: {}", idx, bp);
+                }
+                for (idx, wp) in self.debugger.watchpoints.iter().enumerate() {
+                    println!("watchpoint {}: {:#06x}..={:#06x}", idx, wp.start, wp.end);
+                }
+            },
+            "r" | "regs" => print!("{}", debug::registers_view(cpu)),
+            "stack" => print!("{}", debug::stack_view(cpu)),
+            "dis" => {
+                let addr = match args.first() {
+                    Some(addr) => parse_number(addr)?,
+                    None => cpu.pc,
+                };
+                let count = match args.get(1) {
+                    Some(count) => parse_number(count)? as usize,
+                    None => 9,
+                };
+                print!("{}", debug::disassembly_view(cpu, &self.debugger, addr, count));
+            },
+            "mem" => {
+                let addr = args.first().ok_or("usage: mem <addr> [len]")?;
+                let len = match args.get(1) {
+                    Some(len) => parse_number(len)? as usize,
+                    None => 64,
+                };
+                print!("{}", debug::memory_view(cpu, parse_number(addr)?, len));
+            },
+            "h" | "help" => println!("{}", HELP),
+            _ => return Err(format!("unknown command '{}', type 'help' for a list of commands", command)),
+        }
+        Ok(())
+    }
+}
+
+impl DebugSession for Repl {
+    fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    fn poll(&mut self, cpu: &mut CPU) {
+        while let Ok(line) = self.commands.try_recv() {
+            if let Err(err) = self.execute(&line, cpu) {
+                println!("error: {}", err);
+            }
+            if self.debugger.paused() {
+                prompt();
+            }
+        }
+    }
+
+    fn run(&mut self, machine: &mut Machine) {
+        if let Some(reason) = self.debugger.run(machine) {
+            self.report(&machine.cpu, reason);
+        }
+    }
+}
+
+fn prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
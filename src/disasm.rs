@@ -0,0 +1,234 @@
+use std::{
+    collections::{
+        BTreeMap,
+        BTreeSet,
+    },
+    fmt::Write,
+};
+use crate::emu::instruction::Instruction;
+
+/// Address programs are loaded at and start executing from
+const ENTRY: u16 = 0x200;
+
+/// The kind of reference made to an address, used to name its label.
+/// Ordered so that the most significant kind wins when merging.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Jump,
+    Call,
+}
+
+/// A rom image being disassembled, with the results of control flow tracing.
+struct Listing<'a> {
+    rom: &'a [u8],
+    code: Vec<bool>,
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+/// Disassembles a rom into an annotated listing.
+///
+/// Control flow is traced from 0x200 through jumps, calls and skips to
+/// separate code from data. Jump and call targets are given labels, and
+/// bytes never reached as code are rendered as `db` sprite data.
+pub fn disassemble(rom: &[u8]) -> String {
+    let mut listing = Listing {
+        rom,
+        code: vec![false; rom.len()],
+        labels: BTreeMap::new(),
+    };
+    listing.trace();
+    listing.prune_labels();
+    listing.render()
+}
+
+impl<'a> Listing<'a> {
+    /// Reads the word at addr, if it lies inside the rom
+    fn word(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(ENTRY)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn is_code(&self, addr: u16) -> bool {
+        addr.checked_sub(ENTRY)
+            .and_then(|offset| self.code.get(offset as usize).copied())
+            .unwrap_or(false)
+    }
+
+    fn mark_code(&mut self, addr: u16, size: u16) {
+        for byte in addr..addr.saturating_add(size) {
+            if let Some(flag) = self.code.get_mut((byte - ENTRY) as usize) {
+                *flag = true;
+            }
+        }
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        let entry = self.labels.entry(addr).or_insert(kind);
+        *entry = (*entry).max(kind);
+    }
+
+    /// Follows every reachable path from the entry point, marking code bytes
+    fn trace(&mut self) {
+        let mut pending = vec![ENTRY];
+
+        while let Some(addr) = pending.pop() {
+            if self.is_code(addr) {
+                continue;
+            }
+            let Some(opcode) = self.word(addr) else { continue };
+            let instruction = match Instruction::decode(opcode) {
+                // Machine code calls are almost always zeroed data
+                Some(Instruction::Sys { .. }) | None => continue,
+                Some(instruction) => instruction,
+            };
+
+            let size = instruction.size();
+            self.mark_code(addr, size);
+            let next = addr.wrapping_add(size);
+
+            use Instruction::*;
+            match instruction {
+                Jump { nnn } => {
+                    self.add_label(nnn, LabelKind::Jump);
+                    pending.push(nnn);
+                },
+                Call { nnn } => {
+                    self.add_label(nnn, LabelKind::Call);
+                    pending.push(nnn);
+                    pending.push(next);
+                },
+                // The offset register is unknown, so assume the base of the jump table
+                JumpOffset { nnn, .. } => {
+                    self.add_label(nnn, LabelKind::Jump);
+                    pending.push(nnn);
+                },
+                Return | Exit => {},
+                SkipEqImm { .. } | SkipNeImm { .. } | SkipEqReg { .. }
+                | SkipNeReg { .. } | SkipKey { .. } | SkipNotKey { .. } => {
+                    let skipped = if self.word(next) == Some(0xF000) { 4 } else { 2 };
+                    pending.push(next);
+                    pending.push(next.wrapping_add(skipped));
+                },
+                LoadI { nnn } => {
+                    self.add_label(nnn, LabelKind::Data);
+                    pending.push(next);
+                },
+                LoadLongI => {
+                    if let Some(nnnn) = self.word(addr.wrapping_add(2)) {
+                        self.add_label(nnnn, LabelKind::Data);
+                    }
+                    pending.push(next);
+                },
+                _ => pending.push(next),
+            }
+        }
+    }
+
+    /// Size of the item rendered at offset: a whole instruction if it's
+    /// code, otherwise a single data byte
+    fn item_size(&self, offset: usize) -> usize {
+        let addr = ENTRY + offset as u16;
+        match self.word(addr).and_then(Instruction::decode) {
+            Some(instruction) if self.is_code(addr) => instruction.size() as usize,
+            _ => 1,
+        }
+    }
+
+    /// Drops labels that render wouldn't emit: targets outside the rom,
+    /// such as the font, and ones in the middle of an instruction.
+    /// References to them are printed as plain addresses instead
+    fn prune_labels(&mut self) {
+        let mut starts = BTreeSet::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            starts.insert(ENTRY + offset as u16);
+            offset += self.item_size(offset);
+        }
+        self.labels.retain(|addr, _| starts.contains(addr));
+    }
+
+    /// The label name for addr, if one was generated
+    fn label(&self, addr: u16) -> Option<String> {
+        let prefix = match self.labels.get(&addr)? {
+            LabelKind::Call => "sub",
+            LabelKind::Jump => "lbl",
+            LabelKind::Data => if self.is_code(addr) { "lbl" } else { "data" },
+        };
+        Some(format!("{}_{:03x}", prefix, addr))
+    }
+
+    /// Formats an instruction, substituting labels for address operands
+    fn format(&self, instruction: Instruction, addr: u16) -> String {
+        use Instruction::*;
+        match instruction {
+            Jump { nnn } => self.label(nnn).map(|l| format!("JP {}", l)),
+            Call { nnn } => self.label(nnn).map(|l| format!("CALL {}", l)),
+            JumpOffset { nnn, .. } => self.label(nnn).map(|l| format!("JP V0, {}", l)),
+            LoadI { nnn } => self.label(nnn).map(|l| format!("LD I, {}", l)),
+            LoadLongI => self.word(addr.wrapping_add(2)).map(|nnnn| {
+                let target = self.label(nnnn).unwrap_or_else(|| format!("{:#06x}", nnnn));
+                format!("LD I, LONG {}", target)
+            }),
+            _ => None,
+        }.unwrap_or_else(|| instruction.to_string())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut offset = 0usize;
+
+        while offset < self.rom.len() {
+            let addr = ENTRY + offset as u16;
+            if let Some(label) = self.label(addr) {
+                let _ = writeln!(out, "\n{}:", label);
+            }
+
+            let decoded = self.word(addr).and_then(Instruction::decode);
+            match decoded {
+                Some(instruction) if self.is_code(addr) => {
+                    let size = self.item_size(offset);
+                    let end = (offset + size).min(self.rom.len());
+                    let raw: String = self.rom[offset..end]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    let _ = writeln!(out, "{:#06x}  {:<8}  {}", addr, raw, self.format(instruction, addr));
+                    offset += size;
+                },
+                _ => {
+                    let byte = self.rom[offset];
+                    let bitmap: String = (0..8)
+                        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+                        .collect();
+                    let _ = writeln!(out, "{:#06x}  {:02x}        db {:#04x}  ; {}", addr, byte, byte, bitmap);
+                    offset += 1;
+                },
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_only_targets_inside_the_rom() {
+        let rom = [
+            0xA0, 0x50, // LD I, 0x050 (the font)
+            0x22, 0x06, // CALL 0x206
+            0x12, 0x03, // JP 0x203, the middle of the call
+            0xA2, 0x0A, // LD I, 0x20a
+            0x00, 0xEE, // RET
+            0xFF,
+        ];
+        let listing = disassemble(&rom);
+        assert!(listing.contains("LD I, 0x050"), "{}", listing);
+        assert!(listing.contains("JP 0x203"), "{}", listing);
+        assert!(listing.contains("CALL sub_206") && listing.contains("\nsub_206:"), "{}", listing);
+        assert!(listing.contains("LD I, data_20a") && listing.contains("\ndata_20a:"), "{}", listing);
+    }
+}
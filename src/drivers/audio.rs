@@ -7,36 +7,34 @@ use sdl2::{
     }, 
     Sdl,
 };
+use crate::emu::{
+    machine::AudioOutput,
+    tone::Tone,
+};
+use crate::frontend::AudioSink;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
+/// Wraps the tone generator so it can be driven by SDL's audio callback
+struct SquareWave(Tone);
 
 impl AudioCallback for SquareWave {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            if self.phase <= 0.5 {
-                *x = self.volume;
-            } else {
-                *x = -self.volume;
-            }
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.0.fill(out);
     }
 }
 
 pub struct AudioDriver {
     device: AudioDevice<SquareWave>,
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
     pub state: bool,
 }
 
 impl AudioDriver {
-    pub fn new(sdl_context: &Sdl) -> Result<Self, Box<dyn Error>> {
+    /// Opens the audio device, with volume from 0 to 1 and the
+    /// frequency in Hz of the tone played without an XO-CHIP pattern
+    pub fn new(sdl_context: &Sdl, volume: f32, frequency: f32) -> Result<Self, Box<dyn Error>> {
         let audio_subsystem = sdl_context.audio()?;
 
         let desired_spec = AudioSpecDesired {
@@ -48,15 +46,21 @@ impl AudioDriver {
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
             log::debug!("audio spec obtained: {:?}", spec);
 
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.03,
-            }
+            SquareWave(Tone::new(spec.freq as f32, volume, frequency))
         })?;
 
         log::info!("SDL audio subsystem initialized");
-        Ok(AudioDriver{ device, state: false })
+        Ok(AudioDriver{ device, pattern: None, pitch: 64, state: false })
+    }
+
+    /// Sets the XO-CHIP pattern buffer and pitch used for playback.
+    /// The audio callback is only updated when either value changes.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        if self.pattern != pattern || self.pitch != pitch {
+            self.pattern = pattern;
+            self.pitch = pitch;
+            self.device.lock().0.set_pattern(pattern, pitch);
+        }
     }
 
     /// Sets the audio output to ON, lasting until turned OFF.
@@ -74,3 +78,14 @@ impl AudioDriver {
         };
     }
 }
+
+impl AudioSink for AudioDriver {
+    fn update(&mut self, audio: &AudioOutput) {
+        self.set_pattern(audio.pattern, audio.pitch);
+        if audio.playing {
+            self.on();
+        } else {
+            self.off();
+        }
+    }
+}
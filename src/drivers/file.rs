@@ -4,11 +4,14 @@ use std::{
     path::Path,
     error::Error,
 };
-
-const ROM_SIZE: usize = 4096 - 0x200;
+use crate::emu::state::HASH_LEN;
 
 pub struct FileDriver {
-    pub data: [u8; ROM_SIZE],
+    pub data: Vec<u8>,
+    /// File name without the extension
+    pub name: String,
+    /// SHA-1 digest of the rom contents
+    pub hash: [u8; HASH_LEN],
 }
 
 impl FileDriver {
@@ -18,9 +21,17 @@ impl FileDriver {
     }
 
     pub fn from_path(path: &Path) -> Result<Self, Box<dyn Error>> {
-        let mut data = [0u8; ROM_SIZE];
+        let mut data = Vec::new();
         let mut f = File::open(path)?;
-        let _ = f.read(&mut data)?;
-        Ok(FileDriver { data })
+        f.read_to_end(&mut data)?;
+
+        let hash = sha1_smol::Sha1::from(&data).digest().bytes();
+        let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        Ok(FileDriver { data, name, hash })
+    }
+
+    /// The rom hash as a lowercase hex string
+    pub fn hash_hex(&self) -> String {
+        self.hash.iter().map(|byte| format!("{:02x}", byte)).collect()
     }
 }
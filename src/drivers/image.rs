@@ -0,0 +1,208 @@
+use std::{
+    error::Error,
+    fs::{
+        self,
+        File,
+    },
+    io::BufWriter,
+    path::Path,
+};
+use crate::emu::frame::{
+    Frame,
+    PALETTE,
+};
+
+/// Characters used for each colour index in ASCII art
+const ASCII: [char; 4] = ['.', '#', '+', '@'];
+
+/// A frame snapshot read back from disk, holding a colour index per pixel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn from_frame(frame: &Frame) -> Self {
+        Image { width: frame.width, height: frame.height, data: frame.data.clone() }
+    }
+
+    /// Reads a .png file, or ASCII art for any other extension
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        if is_png(path) {
+            Image::from_png(path)
+        } else {
+            Image::from_ascii(&fs::read_to_string(path)?)
+        }
+    }
+
+    /// Writes a .png file, or ASCII art for any other extension
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        if is_png(path) {
+            self.write_png(path)
+        } else {
+            fs::write(path, self.to_ascii())?;
+            Ok(())
+        }
+    }
+
+    /// One line per row, one character per pixel
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for row in self.data.chunks_exact(self.width) {
+            out.extend(row.iter().map(|&pixel| ASCII[pixel as usize & 0b11]));
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn from_ascii(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut data = Vec::new();
+        let mut width = None;
+        let mut height = 0;
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let row: Vec<u8> = line.chars()
+                .map(|c| ASCII.iter().position(|&a| a == c).map(|idx| idx as u8))
+                .collect::<Option<_>>()
+                .ok_or_else(|| format!("unexpected character in row {} of ascii image", height))?;
+            if *width.get_or_insert(row.len()) != row.len() {
+                return Err(format!("row {} of ascii image has the wrong width", height).into());
+            }
+            data.extend(row);
+            height += 1;
+        }
+        Ok(Image { width: width.unwrap_or(0), height, data })
+    }
+
+    /// Writes an indexed png using the emulator palette
+    pub fn write_png(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let writer = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(PALETTE.concat());
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Reads a png, mapping its colours back onto the emulator palette
+    pub fn from_png(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            other => return Err(format!("unsupported png colour type {:?}", other).into()),
+        };
+        let data = buf[..info.buffer_size()]
+            .chunks_exact(channels)
+            .map(|px| PALETTE.iter().position(|colour| colour[..] == px[..3]).map(|idx| idx as u8))
+            .collect::<Option<_>>()
+            .ok_or("png contains colours outside the emulator palette")?;
+        Ok(Image { width: info.width as usize, height: info.height as usize, data })
+    }
+
+    /// Checks that two images are identical, describing the first difference
+    pub fn compare(&self, expected: &Image) -> Result<(), String> {
+        if (self.width, self.height) != (expected.width, expected.height) {
+            return Err(format!("frame is {}x{}, expected {}x{}",
+                self.width, self.height, expected.width, expected.height));
+        }
+        let mut diffs = self.data.iter().zip(&expected.data)
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected);
+        match diffs.next() {
+            None => Ok(()),
+            Some((idx, _)) => Err(format!("frame differs from expected in {} pixels, first at ({}, {})",
+                diffs.count() + 1, idx % self.width, idx / self.width)),
+        }
+    }
+}
+
+fn is_png(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> Image {
+        Image { width: 4, height: 2, data: vec![0, 1, 2, 3, 3, 2, 1, 0] }
+    }
+
+    /// A path in the temp directory that's removed when dropped
+    struct TempPath(String);
+
+    impl TempPath {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("chip8-{}-{}", std::process::id(), name));
+            TempPath(path.to_string_lossy().into_owned())
+        }
+    }
+
+    impl Drop for TempPath {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn ascii_roundtrips() {
+        let text = image().to_ascii();
+        assert_eq!(text, ".#+@\n@+#.\n");
+        assert_eq!(Image::from_ascii(&text).unwrap(), image());
+        // Blank lines are skipped, so trailing newlines don't matter
+        assert_eq!(Image::from_ascii("\n.#+@\n\n@+#.\n\n").unwrap(), image());
+    }
+
+    #[test]
+    fn bad_ascii_is_rejected() {
+        assert!(Image::from_ascii(".#+@\n@+#x\n").is_err());
+        assert!(Image::from_ascii(".#+@\n@+#\n").is_err());
+        assert_eq!(Image::from_ascii("").unwrap(), Image { width: 0, height: 0, data: vec![] });
+    }
+
+    #[test]
+    fn files_roundtrip_by_extension() {
+        for name in ["frame.png", "frame.PNG", "frame.txt"] {
+            let path = TempPath::new(name);
+            image().save(&path.0).unwrap();
+            assert_eq!(Image::load(&path.0).unwrap(), image(), "{}", name);
+        }
+
+        let path = TempPath::new("frame-ascii.png");
+        fs::write(&path.0, image().to_ascii()).unwrap();
+        assert!(Image::load(&path.0).is_err());
+    }
+
+    #[test]
+    fn comparing_reports_size_and_pixel_mismatches() {
+        assert_eq!(image().compare(&image()), Ok(()));
+
+        let wide = Image { width: 8, height: 1, data: image().data };
+        assert_eq!(wide.compare(&image()), Err("frame is 8x1, expected 4x2".to_string()));
+
+        let mut changed = image();
+        changed.data[2] = 0;
+        changed.data[7] = 1;
+        assert_eq!(changed.compare(&image()),
+            Err("frame differs from expected in 2 pixels, first at (2, 0)".to_string()));
+    }
+
+    #[test]
+    fn frames_are_captured_as_is() {
+        let mut frame = Frame::new();
+        frame.toggle(3, 1, 2, true);
+        let image = Image::from_frame(&frame);
+        assert_eq!((image.width, image.height), (64, 32));
+        assert_eq!(image.data[64 + 3], 2);
+    }
+}
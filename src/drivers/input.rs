@@ -1,40 +1,81 @@
 use std::error::Error;
 use sdl2::{
-    event::Event,
+    event::{
+        Event,
+        WindowEvent,
+    },
     keyboard::Keycode, 
     EventPump,
     Sdl,
 };
 
-use crate::emu::cpu::CPU;
-use crate::drivers::file::FileDriver;
+use crate::emu::machine::InputState;
+use crate::frontend::{
+    InputEvent,
+    InputSource,
+};
 
 pub struct InputDriver {
     events: EventPump,
+    /// Keyboard keys bound to each chip-8 key
+    keymap: [Vec<Keycode>; 16],
 }
 
 impl InputDriver {
-    pub fn new(sdl_context: &Sdl) -> Result<Self, Box<dyn Error>> {
+    /// Takes the names of the keys bound to each chip-8 key, see `set_keys`
+    pub fn new(sdl_context: &Sdl, keys: &[Vec<String>; 16]) -> Result<Self, Box<dyn Error>> {
         let events = sdl_context.event_pump()?;
+        let mut driver = InputDriver { events, keymap: Default::default() };
+        driver.set_keys(keys)?;
         log::info!("SDL input handler initialized");
-        Ok( InputDriver { events } )
+        Ok(driver)
+    }
+
+    /// Polls the sdl eventpump for DropFile events
+    /// then returns the dropped file's path
+    pub fn poll_filedrop(&mut self) -> Option<String> {
+        log::debug!("Waiting on FileDrop event");
+
+        loop {
+            for event in self.events.poll_iter() {
+                match event {
+                    Event::Quit{..} => { 
+                        return None; 
+                    },
+                    Event::DropFile{filename, ..} => {
+                        log::info!("file dropped into context: {}", filename);
+                        return Some(filename);
+                    },
+                    _ => {},
+                }
+            }
+        }
     }
+}
 
+impl InputSource for InputDriver {
     /// Polls the sdl eventpump for events, 
-    /// checks for and handles Quit or FileDrop events,
-    /// and finally sets the cpu's keypad to the proper state.
-    pub fn poll(&mut self, cpu: &mut CPU) -> Result<(), Box<dyn Error>> {
+    /// translating Quit, FileDrop and hotkeys into emulator events,
+    /// and finally reads the keypad state from the keyboard.
+    fn poll(&mut self) -> Result<(InputState, Vec<InputEvent>), Box<dyn Error>> {
+        let mut events = Vec::new();
         for event in self.events.poll_iter() {
             match event {
                 Event::Quit{..} => {
                     log::info!("Exiting");
-                    return Err("User terminated SDL context".into()); 
+                    events.push(InputEvent::Quit);
                 },
                 Event::DropFile {filename, .. } => {
                     log::info!("file dropped into context during main loop: {}", filename);
-                    let rom = FileDriver::from_string(&filename)?;
-                    cpu.reset();
-                    cpu.load(&rom.data);
+                    events.push(InputEvent::RomDropped(filename));
+                },
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    if let Some(event) = keycode_to_event(key) {
+                        events.push(event);
+                    }
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed, .. } => {
+                    events.push(InputEvent::Redraw);
                 },
                 _ => {},
             }
@@ -43,61 +84,43 @@ impl InputDriver {
         let keyboard_state = self.events.keyboard_state();
 
         // Convert scancodes into keycodes (drop invalid codes)
-        let pressed_keys = keyboard_state.pressed_scancodes()
-            .filter_map(Keycode::from_scancode);
+        let pressed_keys: Vec<Keycode> = keyboard_state.pressed_scancodes()
+            .filter_map(Keycode::from_scancode)
+            .collect();
         
         // Set keypad to true for only pressed keys
-        cpu.kp.reset();
-        for key in pressed_keys {
-            if let Some(idx) = keycode_to_idx(key) {
-                cpu.kp.set(idx, true);
-            }
+        let mut input = InputState::default();
+        for (key, keycodes) in input.keys.iter_mut().zip(&self.keymap) {
+            *key = keycodes.iter().any(|keycode| pressed_keys.contains(keycode));
+        }
+        if pressed_keys.contains(&Keycode::Backspace) {
+            events.push(InputEvent::Rewind);
         }
 
-        Ok(())
+        Ok((input, events))
     }
 
-    /// Polls the sdl eventpump for DropFile events
-    /// then returns the dropped file's path
-    pub fn poll_filedrop(&mut self) -> Option<String> {
-        log::debug!("Waiting on FileDrop event");
-
-        loop {
-            for event in self.events.poll_iter() {
-                match event {
-                    Event::Quit{..} => { 
-                        return None; 
-                    },
-                    Event::DropFile{filename, ..} => {
-                        log::info!("file dropped into context: {}", filename);
-                        return Some(filename);
-                    },
-                    _ => {},
-                }
-            }
+    /// Key names are SDL's, e.g. "Q" or "Up"
+    fn set_keys(&mut self, keys: &[Vec<String>; 16]) -> Result<(), Box<dyn Error>> {
+        for (keycodes, names) in self.keymap.iter_mut().zip(keys) {
+            *keycodes = names.iter()
+                .map(|name| Keycode::from_name(name).ok_or_else(|| format!("unknown key name '{}'", name)))
+                .collect::<Result<_, _>>()?;
         }
+        Ok(())
     }
 }
 
-/// Match keycode to chip-8 keypad idx.
-fn keycode_to_idx(key: Keycode) -> Option<usize> {
+/// Match keycode to emulator hotkey.
+fn keycode_to_event(key: Keycode) -> Option<InputEvent> {
     match key {
-        Keycode::Num1 => Some(0x1),
-        Keycode::Num2 => Some(0x2),
-        Keycode::Num3 => Some(0x3),
-        Keycode::Num4 => Some(0xC),
-        Keycode::Q    => Some(0x4),
-        Keycode::W    => Some(0x5),
-        Keycode::E    => Some(0x6),
-        Keycode::R    => Some(0xD),
-        Keycode::A    => Some(0x7),
-        Keycode::S    => Some(0x8),
-        Keycode::D    => Some(0x9),
-        Keycode::F    => Some(0xE),
-        Keycode::Z    => Some(0xA),
-        Keycode::X    => Some(0x0),
-        Keycode::C    => Some(0xB),
-        Keycode::V    => Some(0xF),
-        _             => None,
+        Keycode::F3 => Some(InputEvent::ToggleStats),
+        Keycode::F5 => Some(InputEvent::SaveState),
+        Keycode::F6 => Some(InputEvent::PrevSlot),
+        Keycode::F7 => Some(InputEvent::NextSlot),
+        Keycode::F9 => Some(InputEvent::LoadState),
+        Keycode::Equals | Keycode::KpPlus  => Some(InputEvent::SpeedUp),
+        Keycode::Minus  | Keycode::KpMinus => Some(InputEvent::SpeedDown),
+        _           => None,
     }
 }
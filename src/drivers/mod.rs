@@ -1,4 +1,11 @@
+#[cfg(feature = "sdl")]
 pub mod video;
+#[cfg(feature = "sdl")]
 pub mod input;
+#[cfg(feature = "sdl")]
 pub mod audio;
 pub mod file;
+pub mod savestate;
+pub mod image;
+#[cfg(feature = "tui")]
+pub mod terminal;
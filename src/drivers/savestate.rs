@@ -0,0 +1,76 @@
+use std::{
+    error::Error,
+    fs,
+    path::PathBuf,
+};
+
+use crate::emu::{
+    cpu::CPU,
+    state::HASH_LEN,
+};
+use crate::drivers::file::FileDriver;
+
+/// Number of save slots available per rom
+pub const SLOTS: u8 = 10;
+
+/// Stores save states on disk, one directory per rom.
+///
+/// States live in `<data dir>/chip8/states/<rom sha1>/slot<n>.state`,
+/// where the data dir is the platform's user data directory.
+pub struct SaveStateDriver {
+    dir: PathBuf,
+    rom_hash: [u8; HASH_LEN],
+    pub slot: u8,
+}
+
+impl SaveStateDriver {
+    pub fn new(rom: &FileDriver) -> Result<Self, Box<dyn Error>> {
+        let root = dirs::data_dir()
+            .ok_or("could not find a user data directory for save states")?
+            .join("chip8")
+            .join("states");
+        Ok(SaveStateDriver {
+            dir: root.join(rom.hash_hex()),
+            rom_hash: rom.hash,
+            slot: 0,
+        })
+    }
+
+    /// Point at the save directory of a newly loaded rom
+    pub fn set_rom(&mut self, rom: &FileDriver) {
+        if let Some(root) = self.dir.parent() {
+            self.dir = root.join(rom.hash_hex());
+        }
+        self.rom_hash = rom.hash;
+    }
+
+    /// Select the next slot, wrapping around
+    pub fn next_slot(&mut self) {
+        self.slot = (self.slot + 1) % SLOTS;
+    }
+
+    /// Select the previous slot, wrapping around
+    pub fn prev_slot(&mut self) {
+        self.slot = (self.slot + SLOTS - 1) % SLOTS;
+    }
+
+    /// Path of the currently selected slot
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(format!("slot{}.state", self.slot))
+    }
+
+    /// Write the cpu state to the current slot
+    pub fn save(&self, cpu: &CPU) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(), cpu.save_state(&self.rom_hash))?;
+        Ok(())
+    }
+
+    /// Restore the cpu state from the current slot
+    pub fn load(&self, cpu: &mut CPU) -> Result<(), Box<dyn Error>> {
+        let data = fs::read(self.path())
+            .map_err(|err| format!("could not read slot {}: {}", self.slot, err))?;
+        cpu.load_state(&data, &self.rom_hash)?;
+        Ok(())
+    }
+}
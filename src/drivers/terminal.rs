@@ -0,0 +1,309 @@
+use std::{
+    error::Error,
+    io::{
+        self,
+        Stdout,
+        Write,
+    },
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use crossterm::{
+    cursor,
+    event::{
+        self,
+        Event,
+        KeyCode,
+        KeyEvent,
+        KeyEventKind,
+        KeyModifiers,
+        KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
+    execute,
+    queue,
+    style::{
+        Color,
+        Print,
+        ResetColor,
+        SetBackgroundColor,
+        SetForegroundColor,
+    },
+    terminal::{
+        self,
+        Clear,
+        ClearType,
+        EnterAlternateScreen,
+        LeaveAlternateScreen,
+        SetTitle,
+    },
+};
+
+use crate::emu::{
+    frame::{
+        Frame,
+        Palette,
+    },
+    machine::{
+        AudioOutput,
+        InputState,
+    },
+};
+use crate::frontend::{
+    AudioSink,
+    InputEvent,
+    InputSource,
+    VideoSink,
+};
+
+/// The frame is drawn below a single status line
+const FRAME_ROW: u16 = 1;
+
+/// Puts the terminal into raw mode on the alternate screen,
+/// restoring it when dropped.
+pub struct TerminalGuard {
+    enhanced_keys: bool,
+}
+
+impl TerminalGuard {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide, Clear(ClearType::All))?;
+
+        // Terminals that support the kitty keyboard protocol report key
+        // releases, so the release timeout is only a fallback there
+        let enhanced_keys = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced_keys {
+            execute!(io::stdout(), PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        log::info!("terminal initialized");
+        Ok(TerminalGuard { enhanced_keys })
+    }
+
+    /// Whether the terminal reports key releases
+    pub fn reports_releases(&self) -> bool {
+        self.enhanced_keys
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced_keys {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, ResetColor, cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Draws the frame with half-block characters, two pixels per cell
+pub struct TerminalVideo {
+    stdout: Stdout,
+    palette: Palette,
+}
+
+impl TerminalVideo {
+    pub fn new(palette: Palette) -> Self {
+        TerminalVideo { stdout: io::stdout(), palette }
+    }
+
+    fn color(&self, pixel: u8) -> Color {
+        let [r, g, b] = self.palette[pixel as usize & 0b11];
+        Color::Rgb { r, g, b }
+    }
+}
+
+impl VideoSink for TerminalVideo {
+    fn draw(&mut self, frame: &Frame) -> Result<(), Box<dyn Error>> {
+        let mut out = self.stdout.lock();
+        let mut colours = None;
+
+        for (row, pair) in frame.data.chunks_exact(frame.width * 2).enumerate() {
+            queue!(out, cursor::MoveTo(0, FRAME_ROW + row as u16))?;
+            let (top, bottom) = pair.split_at(frame.width);
+            for (&top, &bottom) in top.iter().zip(bottom) {
+                // The upper half takes the foreground colour, the lower half the background
+                if colours != Some((top, bottom)) {
+                    queue!(out, SetForegroundColor(self.color(top)), SetBackgroundColor(self.color(bottom)))?;
+                    colours = Some((top, bottom));
+                }
+                queue!(out, Print('▀'))?;
+            }
+            // Clear what's left of a larger frame after switching to low resolution
+            queue!(out, ResetColor, Clear(ClearType::UntilNewLine))?;
+            colours = None;
+        }
+        queue!(out, Clear(ClearType::FromCursorDown))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<(), Box<dyn Error>> {
+        execute!(
+            self.stdout,
+            SetTitle(title),
+            cursor::MoveTo(2, 0),
+            Clear(ClearType::UntilNewLine),
+            Print(title),
+        )?;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+}
+
+/// Approximates the buzzer with the terminal bell, or a note shown in the
+/// status line while the sound timer runs
+pub struct TerminalAudio {
+    stdout: Stdout,
+    bell: bool,
+    playing: bool,
+}
+
+impl TerminalAudio {
+    pub fn new(bell: bool) -> Self {
+        TerminalAudio { stdout: io::stdout(), bell, playing: false }
+    }
+}
+
+impl AudioSink for TerminalAudio {
+    fn update(&mut self, audio: &AudioOutput) {
+        if audio.playing == self.playing {
+            return;
+        }
+        self.playing = audio.playing;
+
+        // Failing to show the indicator isn't worth stopping emulation for
+        let _ = if self.bell {
+            if self.playing { execute!(self.stdout, Print('\x07')) } else { Ok(()) }
+        } else {
+            let note = if self.playing { '♪' } else { ' ' };
+            execute!(self.stdout, cursor::MoveTo(0, 0), Print(note))
+        };
+    }
+}
+
+/// Reads the keypad from terminal key presses.
+///
+/// Most terminals only report key presses, repeated while a key is held, so
+/// a key counts as released once it hasn't been seen for `release_timeout`.
+/// Terminals that report releases keep keys held until they're let go.
+pub struct TerminalInput {
+    /// Keys bound to each chip-8 key
+    keymap: [Vec<KeyCode>; 16],
+    release_timeout: Duration,
+    reports_releases: bool,
+    pressed: [Option<Instant>; 16],
+    rewind: Option<Instant>,
+}
+
+impl TerminalInput {
+    /// Takes the names of the keys bound to each chip-8 key, see `set_keys`
+    pub fn new(keys: &[Vec<String>; 16], release_timeout: Duration, reports_releases: bool) -> Result<Self, Box<dyn Error>> {
+        let mut input = TerminalInput {
+            keymap: Default::default(),
+            release_timeout,
+            reports_releases,
+            pressed: [None; 16],
+            rewind: None,
+        };
+        input.set_keys(keys)?;
+        Ok(input)
+    }
+
+    fn held(&self, pressed: Option<Instant>, now: Instant) -> bool {
+        pressed.is_some_and(|at| self.reports_releases || now.duration_since(at) < self.release_timeout)
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self) -> Result<(InputState, Vec<InputEvent>), Box<dyn Error>> {
+        let mut events = Vec::new();
+        let now = Instant::now();
+
+        while event::poll(Duration::ZERO)? {
+            let (code, modifiers, kind) = match event::read()? {
+                Event::Key(KeyEvent { code, modifiers, kind, .. }) => (code, modifiers, kind),
+                Event::Resize(..) => {
+                    events.push(InputEvent::Redraw);
+                    continue;
+                },
+                _ => continue,
+            };
+            let down = kind != KeyEventKind::Release;
+
+            if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+                events.push(InputEvent::Quit);
+                continue;
+            }
+            let code = match code {
+                KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+                code => code,
+            };
+            if self.keymap.iter().any(|codes| codes.contains(&code)) {
+                for (pressed, codes) in self.pressed.iter_mut().zip(&self.keymap) {
+                    if codes.contains(&code) {
+                        *pressed = down.then_some(now);
+                    }
+                }
+                continue;
+            }
+            match code {
+                KeyCode::Backspace => self.rewind = down.then_some(now),
+                _ if kind != KeyEventKind::Press => {},
+                KeyCode::Esc => events.push(InputEvent::Quit),
+                KeyCode::F(3) => events.push(InputEvent::ToggleStats),
+                KeyCode::F(5) => events.push(InputEvent::SaveState),
+                KeyCode::F(6) => events.push(InputEvent::PrevSlot),
+                KeyCode::F(7) => events.push(InputEvent::NextSlot),
+                KeyCode::F(9) => events.push(InputEvent::LoadState),
+                KeyCode::Char('+' | '=') => events.push(InputEvent::SpeedUp),
+                KeyCode::Char('-') => events.push(InputEvent::SpeedDown),
+                _ => {},
+            }
+        }
+
+        let mut input = InputState::default();
+        for (key, &pressed) in input.keys.iter_mut().zip(&self.pressed) {
+            *key = self.held(pressed, now);
+        }
+        if self.held(self.rewind, now) {
+            events.push(InputEvent::Rewind);
+        }
+        Ok((input, events))
+    }
+
+    /// Key names are a character, or e.g. "Up" or "Space"
+    fn set_keys(&mut self, keys: &[Vec<String>; 16]) -> Result<(), Box<dyn Error>> {
+        for (codes, names) in self.keymap.iter_mut().zip(keys) {
+            *codes = names.iter().map(|name| parse_key(name)).collect::<Result<_, _>>()?;
+        }
+        self.pressed = [None; 16];
+        Ok(())
+    }
+}
+
+/// Parses a key name from the config: a single character, or the name of a special key
+fn parse_key(name: &str) -> Result<KeyCode, String> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(c.to_ascii_lowercase()));
+    }
+    match name.to_ascii_lowercase().as_str() {
+        "space" => Ok(KeyCode::Char(' ')),
+        "up"    => Ok(KeyCode::Up),
+        "down"  => Ok(KeyCode::Down),
+        "left"  => Ok(KeyCode::Left),
+        "right" => Ok(KeyCode::Right),
+        "enter" | "return" => Ok(KeyCode::Enter),
+        "tab"   => Ok(KeyCode::Tab),
+        _ => Err(format!("unknown key name '{}'", name)),
+    }
+}
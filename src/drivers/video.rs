@@ -5,14 +5,16 @@ use sdl2::{
     rect::Rect,
     pixels::Color,
 };
+use crate::config::Scaling;
 use crate::emu::frame::{
-    FrameBuffer,
-    FB_SIZE,
+    Frame,
+    LORES,
+    Palette,
 };
+use crate::frontend::VideoSink;
 
-const SCALE: u32 = 6;
-const X_RES: u32 = 64 * SCALE;
-const Y_RES: u32 = 32 * SCALE;
+/// Colour of the bars around the frame when the window's aspect ratio doesn't match
+const BORDER: Color = Color::RGB(0, 0, 0);
 
 macro_rules! rect(
     ($x:expr, $y:expr, $w:expr, $h:expr $(,)?) => (
@@ -22,57 +24,106 @@ macro_rules! rect(
 
 pub struct VideoDriver {
     canvas: Canvas<Window>,
+    scaling: Scaling,
+    palette: Palette,
+    vsync: bool,
 }
 
 impl VideoDriver {
-    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Self, Box<dyn Error>> {
+    /// Opens a resizable window, scale times the low resolution frame size.
+    /// With vsync, presenting a frame waits for the display to refresh.
+    pub fn new(sdl_context: &sdl2::Sdl, scale: u32, scaling: Scaling, palette: Palette, vsync: bool) -> Result<Self, Box<dyn Error>> {
 
         let video_subsystem = sdl_context.video()?;
 
-        let window = video_subsystem
-            .window("Chip-8", X_RES, Y_RES)
+        let mut window = video_subsystem
+            .window("Chip-8", LORES.x as u32 * scale, LORES.y as u32 * scale)
             .opengl()
+            .resizable()
             .build()?;
+        window.set_minimum_size(LORES.x as u32, LORES.y as u32)?;
 
         //window.set_bordered(false);
 
         let mut canvas = window.into_canvas()
-            .index(find_sdl_gl_driver().ok_or("No opengl driver")?)
-            .build()?;
+            .index(find_sdl_gl_driver().ok_or("No opengl driver")?);
+        if vsync {
+            canvas = canvas.present_vsync();
+        }
+        let mut canvas = canvas.build()?;
 
         log::info!("SDL video subsystem initialized");
 
-        canvas.set_draw_color(Color::RGB(145, 145, 135));
-        canvas.fill_rect(rect!(0, 0, X_RES, Y_RES))?;
+        canvas.set_draw_color(BORDER);
+        canvas.clear();
 
         canvas.present();
 
-        Ok( VideoDriver{ canvas } )
+        Ok( VideoDriver{ canvas, scaling, palette, vsync } )
     }
 
-    /// Update the screen subframe to correspond to the framebuffer
-    pub fn draw_screen(&mut self, framebuf: &FrameBuffer) -> Result<(), Box<dyn Error>> {
-        self.canvas.set_draw_color(Color::RGB(255,0,0));
+    /// Where the frame goes in the window: as large as the scaling mode
+    /// allows while keeping its aspect ratio, centred
+    fn frame_rect(&self, frame: &Frame) -> Result<Rect, Box<dyn Error>> {
+        let (window_width, window_height) = self.canvas.output_size()?;
+        let (frame_width, frame_height) = (frame.width as f64, frame.height as f64);
+
+        let scale = (window_width as f64 / frame_width).min(window_height as f64 / frame_height);
+        let scale = match self.scaling {
+            Scaling::Integer => scale.floor().max(1.0),
+            Scaling::Fit => scale,
+        };
+
+        let width = (frame_width * scale) as u32;
+        let height = (frame_height * scale) as u32;
+        Ok(rect!(
+            (window_width as i32 - width as i32) / 2,
+            (window_height as i32 - height as i32) / 2,
+            width,
+            height,
+        ))
+    }
+}
 
-        for (y, row) in framebuf.chunks_exact(FB_SIZE.x).enumerate() {
-            for (x, pixel) in row.iter().enumerate() {
+impl VideoSink for VideoDriver {
+    /// Update the screen subframe to correspond to the framebuffer
+    fn draw(&mut self, frame: &Frame) -> Result<(), Box<dyn Error>> {
+        let target = self.frame_rect(frame)?;
+        self.canvas.set_draw_color(BORDER);
+        self.canvas.clear();
 
-                let window_x = x as u32 * SCALE;
-                let window_y = y as u32 * SCALE;
+        // Pixel edges are rounded separately so fit scaling leaves no gaps between them
+        let edge = |idx: usize, len: usize, size: u32| (idx as u32 * size / len as u32) as i32;
 
-                let color = if *pixel {
-                    Color::RGB(32, 42, 52)
-                } else {
-                    Color::RGB(145, 145, 135)
-                };
+        for (y, row) in frame.data.chunks_exact(frame.width).enumerate() {
+            let top = target.y() + edge(y, frame.height, target.height());
+            let bottom = target.y() + edge(y + 1, frame.height, target.height());
+            for (x, pixel) in row.iter().enumerate() {
+                let left = target.x() + edge(x, frame.width, target.width());
+                let right = target.x() + edge(x + 1, frame.width, target.width());
 
-                self.canvas.set_draw_color(color);
-                self.canvas.fill_rect(rect!(window_x, window_y, SCALE, SCALE))?;
+                let [r, g, b] = self.palette[*pixel as usize & 0b11];
+                self.canvas.set_draw_color(Color::RGB(r, g, b));
+                self.canvas.fill_rect(rect!(left, top, right - left, bottom - top))?;
             }
         }
         self.canvas.present();
         Ok(())
     }
+
+    /// Set the window title
+    fn set_title(&mut self, title: &str) -> Result<(), Box<dyn Error>> {
+        self.canvas.window_mut().set_title(title)?;
+        Ok(())
+    }
+
+    fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    fn vsync(&self) -> bool {
+        self.vsync
+    }
 }
 
 /* SDL Helpers */
@@ -1,20 +1,48 @@
-use rand::{
-    thread_rng, 
-    Rng
-};
+/// Size of addressable memory. XO-CHIP extends this from 4K to 64K
+pub const MEM_SIZE: usize = 0x10000;
+/// Instructions executed between each 60Hz timer tick
+pub const CYCLES_PER_FRAME: usize = 10;
+
 use crate::emu::{
-    frame::{
-        FB_SIZE,
-        Frame,
+    error::{
+        CpuError,
+        RomTooLarge,
     },
+    frame::Frame,
+    instruction::Instruction,
     keypad::Keypad,
-    font::FONT,
+    font::{
+        FONT,
+        BIG_FONT,
+    },
+    quirks::{
+        IndexIncrement,
+        Quirks,
+    },
+    rng::Rng,
+    trace::{
+        Registers,
+        Tracer,
+    },
 };
 
+/// The result of a single successful call to CPU::step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// An instruction was executed
+    Executed,
+    /// Blocked on FX0A until a key is pressed
+    WaitingForKey,
+    /// Blocked after DXYN until the next timer tick
+    WaitingForVblank,
+    /// The program has exited through 00FD
+    Exited,
+}
+
 #[derive(Debug)]
 pub struct CPU {
     pub v: [u8; 16],      // Registers
-    pub mem: [u8; 4096],  // Memory
+    pub mem: [u8; MEM_SIZE], // Memory
     pub stack: [u16; 16], // Stack
     pub dt: u8,           // Delay timer
     pub st: u8,           // Sound timer
@@ -23,14 +51,30 @@ pub struct CPU {
     pub sp: u8,           // Stack pointer
     pub kp: Keypad,       // Keypad
     pub fb: Frame,        // Frame
+    pub rpl: [u8; 16],    // RPL user flags (SUPER-CHIP)
+    pub exit: bool,       // Set when the program has exited (SUPER-CHIP)
+    pub plane: u8,        // Selected drawing planes (XO-CHIP)
+    pub pattern: Option<[u8; 16]>, // Audio pattern buffer (XO-CHIP)
+    pub pitch: u8,        // Audio pattern playback pitch (XO-CHIP)
+    pub quirks: Quirks,   // Interpreter quirks
+    pub(crate) vblank_wait: bool, // Set by DXYN when waiting for the next tick
+    pub(crate) frame_cycles: u32, // Spent so far in the current frame, under the machine's timing
+    pub rng: Rng,         // Source for CXNN
+    pub tracer: Option<Tracer>, // Records executed instructions, if tracing
+    pub instructions: u64, // Instructions executed since power on, for measuring speed
 }
 
 #[allow(clippy::new_without_default)]
 impl CPU {
     pub fn initialize() -> Self {
+        CPU::with_quirks(Quirks::default())
+    }
+
+    /// Creates a CPU that follows the given set of interpreter quirks
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut cpu = CPU {
             v: [0; 16],
-            mem: [0; 4096],
+            mem: [0; MEM_SIZE],
             stack: [0; 16],
             dt: 0,
             st: 0,
@@ -39,6 +83,17 @@ impl CPU {
             sp: 0,
             kp: Keypad::new(),
             fb: Frame::new(),
+            rpl: [0; 16],
+            exit: false,
+            plane: 1,
+            pattern: None,
+            pitch: 64,
+            quirks,
+            vblank_wait: false,
+            frame_cycles: 0,
+            rng: Rng::default(),
+            tracer: None,
+            instructions: 0,
         };
         
         cpu.reset();
@@ -46,9 +101,9 @@ impl CPU {
     }
 
     pub fn reset(&mut self) {
-        let mut mem = [0u8; 4096];
-        let (font_region, _) = mem.split_at_mut(0x50);
-        font_region.copy_from_slice(&FONT);
+        let mut mem = [0u8; MEM_SIZE];
+        mem[..0x50].copy_from_slice(&FONT);
+        mem[0x50..0xF0].copy_from_slice(&BIG_FONT);
 
         self.v     = [0; 16];
         self.mem   = mem;
@@ -60,67 +115,112 @@ impl CPU {
         self.sp    = 0;
         self.kp.reset();
         self.fb.reset();
+        self.exit = false;
+        self.plane = 1;
+        self.pattern = None;
+        self.pitch = 64;
+        self.vblank_wait = false;
+        self.frame_cycles = 0;
     }
 
-    /// Loads a program's data into mem for execution
-    pub fn load(&mut self, rom: &[u8]) {
+    /// Reseeds the random number generator used by CXNN, keeping its model
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Rng::new(self.rng.model(), seed);
+    }
+
+    /// Loads a program's data into mem for execution, zeroing the rest of
+    /// memory above 0x200
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), RomTooLarge> {
         let (_, proc_region) = self.mem.split_at_mut(0x200);
-        proc_region.copy_from_slice(rom);
-
-        /* ========= CHIP-8 TEST SUITE DEBUG PARAMETERS ========= *
-         * ----------- Timendus' chip8-test-suite.ch8 ----------- *
-         *                                                        *
-         * Set the following memory addresses to configure tests: *
-         *                                                        *
-         * (0x1FF) Test Select:                                   *
-         *     1: IBM Logo                                        *
-         *     2: Corax89's opcode test                           *
-         *     3: Flags test                                      *
-         *     4: Quirks test                                     *
-         *     5: Keypad test                                     *
-         *                                                        *
-         *  (0x1FE) Quirk HW Target:                              *
-         *      1: Chip-8                                         *
-         *      2: SuperChip 1.1                                  *
-         *      3: XO-Chip                                        *
-         *                                                        *
-         *  (0x1FE) Keypad Test Type:                             *
-         *      1: ex9e - Down                                    *
-         *      2: ex9e - Up                                      *
-         *      3: fx0a - Get key                                 *
-         *                                                        *
-         *  (Example) Quirk test for std. Chip-8:                 *
-         *                                                        *
-         *      self.mem[0x1FF] = 4;                              *
-         *      self.mem[0x1FE] = 1;                              *
-         *                                                        *
-         * ==== DEBUGGING PURPOSES ONLY * REMEMBER TO REMOVE ==== */
-
-        //self.mem[0x1ff] = 3;
-    }
-
-    /// Fetches opcode, decodes and executes instruction
-    pub fn step(&mut self) {
-        // Check if we need to block for keypad input first
+        if rom.len() > proc_region.len() {
+            return Err(RomTooLarge { size: rom.len(), max: proc_region.len() });
+        }
+        proc_region[..rom.len()].copy_from_slice(rom);
+        proc_region[rom.len()..].fill(0);
+        Ok(())
+    }
+
+    /// Fetches opcode, decodes and executes instruction.
+    /// On error, the program counter is left pointing at the faulting instruction.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        if self.exit {
+            return Ok(StepOutcome::Exited);
+        }
+
+        // Check if we need to block for keypad input first. Like the VIP,
+        // FX0A waits for a key to be pressed and then released again
         if self.kp.block {
-            for (key_idx, &key_state) in self.kp.state.iter().enumerate() {
-                if key_state {
-                    log::trace!("key {} pressed!", key_idx);
+            match self.kp.block_key {
+                None => {
+                    self.kp.block_key = self.kp.state.iter().position(|&pressed| pressed);
+                },
+                Some(key_idx) if !self.kp.state[key_idx] => {
+                    log::trace!("key {} released!", key_idx);
                     self.v[self.kp.block_reg] = key_idx as u8;
                     self.kp.block = false;
-                    break;
-                }
+                    self.kp.block_key = None;
+                    return Ok(StepOutcome::Executed);
+                },
+                Some(_) => {},
             }
+            return Ok(StepOutcome::WaitingForKey);
+        }
+
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingForVblank);
+        }
+
+        let pc = self.pc;
+        let before = self.tracer.is_some().then(|| Registers::from(&*self));
+        let result = self.fetch()
+            .and_then(|opcode| self.decode_and_execute(opcode).map(|()| opcode));
+        match result {
+            Err(err) => {
+                self.pc = pc;
+                return Err(err);
+            },
+            Ok(opcode) => {
+                self.instructions += 1;
+                if let (Some(before), Some(mut tracer)) = (before, self.tracer.take()) {
+                    tracer.record(&before, opcode, self);
+                    self.tracer = Some(tracer);
+                }
+            },
+        }
+
+        if self.exit {
+            Ok(StepOutcome::Exited)
         } else {
-            let opcode = self.fetch();
-            self.decode_and_execute(opcode);
+            Ok(StepOutcome::Executed)
         }
     }
 
+    /// Runs one 60Hz frame: CYCLES_PER_FRAME instructions then a timer tick.
+    /// Stops early if the program exits or faults.
+    pub fn run_frame(&mut self) -> Result<StepOutcome, CpuError> {
+        self.run_frame_cycles(CYCLES_PER_FRAME)
+    }
+
+    /// Runs one 60Hz frame of the given number of instructions
+    pub fn run_frame_cycles(&mut self, cycles: usize) -> Result<StepOutcome, CpuError> {
+        for _ in 0..cycles {
+            if self.step()? == StepOutcome::Exited {
+                return Ok(StepOutcome::Exited);
+            }
+        }
+        self.tick();
+        Ok(StepOutcome::Executed)
+    }
+
     /// Progresses the sound and delay timers by 1
     pub fn tick(&mut self) {
+        self.vblank_wait = false;
         self.dt = self.dt.saturating_sub(1);
         self.st = self.st.saturating_sub(1);
+        self.rng.tick();
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.tick();
+        }
     }
 
     /// Gets the current speaker state of the cpu
@@ -128,85 +228,139 @@ impl CPU {
         self.st > 0 
     }
 
-    fn fetch(&mut self) -> u16 {
-        let mem = self.mem;
-        let pc = self.pc as usize;
-        let opcode = (mem[pc] as u16) << 8 | mem[pc+1] as u16;
-        self.pc += 2;
-        opcode
-    }
-
-    fn decode_and_execute(&mut self, opcode: u16) {
-        let op = (
-            ((opcode & 0xF000) >> 12) as u8,
-            ((opcode & 0x0F00) >> 8) as u8,
-            ((opcode & 0x00F0) >> 4) as u8,
-            ((opcode & 0x000F) as u8),
-        );
-
-        let x   = ((opcode & 0x0F00) >> 8) as usize;
-        let y   = ((opcode & 0x00F0) >> 4) as usize;
-        let n   = (opcode & 0x000F) as usize;
-        let nn  = (opcode & 0x00FF) as usize;
-        let nnn = (opcode & 0x0FFF) as usize;
-
-        match op {
-            (0x0, 0x0, 0xE, 0x0) => { self.op_00e0() }
-            (0x0, 0x0, 0xE, 0xE) => { self.op_00ee() }
-            (0x0, _, _, _) => { self.op_0nnn(nnn) }
-            (0x1, _, _, _) => { self.op_1nnn(nnn) }
-            (0x2, _, _, _) => { self.op_2nnn(nnn) }
-            (0x3, _, _, _) => { self.op_3xnn(x, nn) }
-            (0x4, _, _, _) => { self.op_4xnn(x, nn) }
-            (0x5, _, _, 0x0) => { self.op_5xy0(x, y) }
-            (0x6, _, _, _) => { self.op_6xnn(x, nn) }
-            (0x7, _, _, _) => { self.op_7xnn(x, nn) }
-            (0x8, _, _, 0x0) => { self.op_8xy0(x, y) }
-            (0x8, _, _, 0x1) => { self.op_8xy1(x, y) }
-            (0x8, _, _, 0x2) => { self.op_8xy2(x, y) }
-            (0x8, _, _, 0x3) => { self.op_8xy3(x, y) }
-            (0x8, _, _, 0x4) => { self.op_8xy4(x, y) }
-            (0x8, _, _, 0x5) => { self.op_8xy5(x, y) }
-            (0x8, _, _, 0x6) => { self.op_8xy6(x, y) }
-            (0x8, _, _, 0x7) => { self.op_8xy7(x, y) }
-            (0x8, _, _, 0xE) => { self.op_8xye(x, y) }
-            (0x9, _, _, 0x0) => { self.op_9xy0(x, y) }
-            (0xA, _, _, _) => { self.op_annn(nnn) }
-            (0xB, _, _, _) => { self.op_bnnn(nnn) }
-            (0xC, _, _, _) => { self.op_cxnn(x, nn) }
-            (0xD, _, _, _) => { self.op_dxyn(x, y, n) }
-            (0xE, _, 0x9, 0xE) => { self.op_ex9e(x) }
-            (0xE, _, 0xA, 0x1) => { self.op_exa1(x) }
-            (0xF, _, 0x0, 0x7) => { self.op_fx07(x) }
-            (0xF, _, 0x0, 0xa) => { self.op_fx0a(x) }
-            (0xF, _, 0x1, 0x5) => { self.op_fx15(x) }
-            (0xF, _, 0x1, 0x8) => { self.op_fx18(x) }
-            (0xF, _, 0x1, 0xE) => { self.op_fx1e(x) }
-            (0xF, _, 0x2, 0x9) => { self.op_fx29(x) }
-            (0xF, _, 0x3, 0x3) => { self.op_fx33(x) }
-            (0xF, _, 0x5, 0x5) => { self.op_fx55(x) }
-            (0xF, _, 0x6, 0x5) => { self.op_fx65(x) }
-            _ => { panic!("op {:#06x} is unrecognized", opcode); }
+    fn fetch(&mut self) -> Result<u16, CpuError> {
+        let opcode = self.peek(self.pc)?;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(opcode)
+    }
+
+    /// Reads the opcode at addr without advancing the program counter
+    pub(crate) fn peek(&self, addr: u16) -> Result<u16, CpuError> {
+        let bytes = self.mem_range(addr as usize, 2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn decode_and_execute(&mut self, opcode: u16) -> Result<(), CpuError> {
+        let pc = self.pc.wrapping_sub(2);
+        let instruction = Instruction::decode(opcode)
+            .ok_or(CpuError::UnknownOpcode { pc, opcode })?;
+        self.execute(instruction)
+    }
+
+    /// Executes a single decoded instruction
+    fn execute(&mut self, instruction: Instruction) -> Result<(), CpuError> {
+        use Instruction::*;
+        match instruction {
+            Sys { nnn }           => { self.op_0nnn(nnn as usize)? }
+            ScrollDown { n }      => { self.op_00cn(n as usize) }
+            ScrollUp { n }        => { self.op_00dn(n as usize) }
+            Clear                 => { self.op_00e0() }
+            Return                => { self.op_00ee()? }
+            ScrollRight           => { self.op_00fb() }
+            ScrollLeft            => { self.op_00fc() }
+            Exit                  => { self.op_00fd() }
+            Lores                 => { self.op_00fe() }
+            Hires                 => { self.op_00ff() }
+            Jump { nnn }          => { self.op_1nnn(nnn as usize) }
+            Call { nnn }          => { self.op_2nnn(nnn as usize)? }
+            SkipEqImm { x, nn }   => { self.op_3xnn(x as usize, nn as usize)? }
+            SkipNeImm { x, nn }   => { self.op_4xnn(x as usize, nn as usize)? }
+            SkipEqReg { x, y }    => { self.op_5xy0(x as usize, y as usize)? }
+            StoreRange { x, y }   => { self.op_5xy2(x as usize, y as usize)? }
+            LoadRange { x, y }    => { self.op_5xy3(x as usize, y as usize)? }
+            LoadImm { x, nn }     => { self.op_6xnn(x as usize, nn as usize) }
+            AddImm { x, nn }      => { self.op_7xnn(x as usize, nn as usize) }
+            LoadReg { x, y }      => { self.op_8xy0(x as usize, y as usize) }
+            Or { x, y }           => { self.op_8xy1(x as usize, y as usize) }
+            And { x, y }          => { self.op_8xy2(x as usize, y as usize) }
+            Xor { x, y }          => { self.op_8xy3(x as usize, y as usize) }
+            AddReg { x, y }       => { self.op_8xy4(x as usize, y as usize) }
+            Sub { x, y }          => { self.op_8xy5(x as usize, y as usize) }
+            ShiftRight { x, y }   => { self.op_8xy6(x as usize, y as usize) }
+            SubN { x, y }         => { self.op_8xy7(x as usize, y as usize) }
+            ShiftLeft { x, y }    => { self.op_8xye(x as usize, y as usize) }
+            SkipNeReg { x, y }    => { self.op_9xy0(x as usize, y as usize)? }
+            LoadI { nnn }         => { self.op_annn(nnn as usize) }
+            JumpOffset { x, nnn } => { self.op_bnnn(x as usize, nnn as usize) }
+            Random { x, nn }      => { self.op_cxnn(x as usize, nn as usize) }
+            Draw { x, y, n }      => { self.op_dxyn(x as usize, y as usize, n as usize)? }
+            SkipKey { x }         => { self.op_ex9e(x as usize)? }
+            SkipNotKey { x }      => { self.op_exa1(x as usize)? }
+            LoadLongI             => { self.op_f000()? }
+            Plane { n }           => { self.op_fn01(n as usize) }
+            Audio                 => { self.op_f002()? }
+            GetDelay { x }        => { self.op_fx07(x as usize) }
+            WaitKey { x }         => { self.op_fx0a(x as usize) }
+            SetDelay { x }        => { self.op_fx15(x as usize) }
+            SetSound { x }        => { self.op_fx18(x as usize) }
+            AddI { x }            => { self.op_fx1e(x as usize) }
+            Font { x }            => { self.op_fx29(x as usize) }
+            BigFont { x }         => { self.op_fx30(x as usize) }
+            Bcd { x }             => { self.op_fx33(x as usize)? }
+            Pitch { x }           => { self.op_fx3a(x as usize) }
+            Store { x }           => { self.op_fx55(x as usize)? }
+            Load { x }            => { self.op_fx65(x as usize)? }
+            SaveFlags { x }       => { self.op_fx75(x as usize) }
+            LoadFlags { x }       => { self.op_fx85(x as usize) }
         };
+        Ok(())
     }
 
     /* Instructions */
 
     /// OP: Call machine code routine at NNN
-    fn op_0nnn(&mut self, _nnn: usize) {
-        panic!("opcode 0x0nnn is not supported.")
+    fn op_0nnn(&mut self, nnn: usize) -> Result<(), CpuError> {
+        Err(CpuError::MachineCodeCall { pc: self.pc.wrapping_sub(2), addr: nnn as u16 })
+    }
+
+    /// OP: Scrolls the display down by N pixels (SUPER-CHIP)
+    fn op_00cn(&mut self, n: usize) {
+        self.fb.scroll_down(n, self.plane);
     }
 
-    /// OP: Clears the screen
+    /// OP: Scrolls the display up by N pixels (XO-CHIP)
+    fn op_00dn(&mut self, n: usize) {
+        self.fb.scroll_up(n, self.plane);
+    }
+
+    /// OP: Clears the selected planes of the screen
     fn op_00e0(&mut self) {
-        self.fb.data = [false; FB_SIZE.y * FB_SIZE.x];
-        self.fb.update = true;
+        self.fb.clear(self.plane);
     }
 
     /// OP: Returns from a subroutine
-    fn op_00ee(&mut self) {
-        self.pc = self.stack[self.sp as usize];
+    fn op_00ee(&mut self) -> Result<(), CpuError> {
+        if self.sp == 0 {
+            return Err(CpuError::StackUnderflow { pc: self.pc.wrapping_sub(2) });
+        }
         self.sp -= 1;
+        self.pc = self.stack[self.sp as usize];
+        Ok(())
+    }
+
+    /// OP: Scrolls the display right by 4 pixels (SUPER-CHIP)
+    fn op_00fb(&mut self) {
+        self.fb.scroll_right(4, self.plane);
+    }
+
+    /// OP: Scrolls the display left by 4 pixels (SUPER-CHIP)
+    fn op_00fc(&mut self) {
+        self.fb.scroll_left(4, self.plane);
+    }
+
+    /// OP: Exits the interpreter (SUPER-CHIP)
+    fn op_00fd(&mut self) {
+        self.exit = true;
+    }
+
+    /// OP: Switches to low resolution mode (SUPER-CHIP)
+    fn op_00fe(&mut self) {
+        self.fb.set_hires(false);
+    }
+
+    /// OP: Switches to high resolution mode (SUPER-CHIP)
+    fn op_00ff(&mut self) {
+        self.fb.set_hires(true);
     }
 
     /// OP: Jump to addres NNN
@@ -215,31 +369,62 @@ impl CPU {
     }
 
     /// OP: Calls subroutine at NNN
-    fn op_2nnn(&mut self, nnn: usize) {
-        self.sp += 1;
+    fn op_2nnn(&mut self, nnn: usize) -> Result<(), CpuError> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(CpuError::StackOverflow { pc: self.pc.wrapping_sub(2) });
+        }
         self.stack[self.sp as usize] = self.pc;
+        self.sp += 1;
         self.pc = nnn as u16;
+        Ok(())
     }
 
     /// OP: Skips the next instruction if VX == NN
-    fn op_3xnn(&mut self, x: usize, nn: usize) {
+    fn op_3xnn(&mut self, x: usize, nn: usize) -> Result<(), CpuError> {
         if self.v[x] == nn as u8 {
-            self.pc += 2;
+            self.skip()?;
         }
+        Ok(())
     }
 
     /// OP: Skips the next instruction if VX != NN
-    fn op_4xnn(&mut self, x: usize, nn: usize) {
+    fn op_4xnn(&mut self, x: usize, nn: usize) -> Result<(), CpuError> {
         if self.v[x] != nn as u8 {
-            self.pc += 2;
+            self.skip()?;
         }
+        Ok(())
     }
 
     /// OP: Skips the next instruction if VX == VY
-    fn op_5xy0(&mut self, x: usize, y: usize) {
+    fn op_5xy0(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
         if self.v[x] == self.v[y] {
-            self.pc += 2;
+            self.skip()?;
+        }
+        Ok(())
+    }
+
+    /// OP: Stores VX to VY in mem starting at address register (XO-CHIP)
+    ///     I is left unmodified
+    fn op_5xy2(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
+        let len = x.abs_diff(y) + 1;
+        let i = self.i as usize;
+        self.mem_range(i, len)?;
+        for (offset, reg) in register_range(x, y).enumerate() {
+            self.mem[i + offset] = self.v[reg];
+        }
+        Ok(())
+    }
+
+    /// OP: Fills VX to VY with values from mem, starting at address register (XO-CHIP)
+    ///     I is left unmodified
+    fn op_5xy3(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
+        let len = x.abs_diff(y) + 1;
+        let i = self.i as usize;
+        self.mem_range(i, len)?;
+        for (offset, reg) in register_range(x, y).enumerate() {
+            self.v[reg] = self.mem[i + offset];
         }
+        Ok(())
     }
 
     /// OP: Set VX to NN
@@ -260,66 +445,81 @@ impl CPU {
     /// OP: Sets VX to (VX OR VY)
     fn op_8xy1(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
-        self.v[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
     }
 
     /// OP: Sets VX to (VX AND VY)
     fn op_8xy2(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
-        self.v[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
     }
     
     /// OP: Sets VX to (VX XOR VY) 
     fn op_8xy3(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
-        self.v[0xf] = 0;
+        if self.quirks.vf_reset {
+            self.v[0xf] = 0;
+        }
     }
 
     /// OP: Adds VY to VX.
-    ///     VF = carry
+    ///     VF = carry, set after VX so the flag wins when X is F
     fn op_8xy4(&mut self, x: usize, y: usize) {
         let (result, wrapped) = self.v[x].overflowing_add(self.v[y]);
-        self.v[0xf] = if wrapped { 1 } else { 0 };
         self.v[x] = result;
+        self.v[0xf] = if wrapped { 1 } else { 0 };
     }
 
     /// OP: Subtracts VY from VX
-    ///     VF = NOT borrow
+    ///     VF = NOT borrow, set after VX so the flag wins when X is F
     fn op_8xy5(&mut self, x: usize, y: usize) {
         let (result, wrapped) = self.v[x].overflowing_sub(self.v[y]);
-        self.v[0xf] = if wrapped { 0 } else { 1 };
         self.v[x] = result;
+        self.v[0xf] = if wrapped { 0 } else { 1 };
     }
 
     /// OP: Shifts VX right by 1
     ///     Stores least signifigant bit of VX in VF
-    fn op_8xy6(&mut self, x: usize, _y: usize) {
+    ///     [Quirk] VY is shifted into VX instead
+    fn op_8xy6(&mut self, x: usize, y: usize) {
+        if self.quirks.shift_vy {
+            self.v[x] = self.v[y];
+        }
         let overflow = (self.v[x] & 1) != 0;
         self.v[x] >>= 1;
         self.v[0xf] = if overflow { 1 } else { 0 };
     }
 
     /// OP: Sets VX to VY - VX
-    ///     VF = NOT borrow
+    ///     VF = NOT borrow, set after VX so the flag wins when X is F
     fn op_8xy7(&mut self, x: usize, y: usize) {
         let (result, wrapped) = self.v[y].overflowing_sub(self.v[x]);
-        self.v[0xf] = if wrapped { 0 } else { 1 };
         self.v[x] = result;
+        self.v[0xf] = if wrapped { 0 } else { 1 };
     }
 
     /// OP: Shifts VX left by 1
     ///     Stores most signifigant bit of VX in VF
-    fn op_8xye(&mut self, x: usize, _y: usize) {
+    ///     [Quirk] VY is shifted into VX instead
+    fn op_8xye(&mut self, x: usize, y: usize) {
+        if self.quirks.shift_vy {
+            self.v[x] = self.v[y];
+        }
         let overflow = (self.v[x] & (1 << 7)) != 0;
         self.v[x] <<= 1;
         self.v[0xf] = if overflow { 1 } else { 0 };
     }
 
     /// OP: Skips next instruction if VX != VY
-    fn op_9xy0(&mut self, x: usize, y: usize) {
+    fn op_9xy0(&mut self, x: usize, y: usize) -> Result<(), CpuError> {
         if self.v[x] != self.v[y] {
-            self.pc += 2;
+            self.skip()?;
         }
+        Ok(())
     }
     
     /// OP: Set index register to NNN
@@ -328,61 +528,122 @@ impl CPU {
     }
 
     /// OP: Jump to address (NNN + V0)
-    fn op_bnnn(&mut self, nnn: usize) {
-        self.pc = self.v[0] as u16 + nnn as u16;
+    ///     [Quirk] Jump to address (XNN + VX)
+    fn op_bnnn(&mut self, x: usize, nnn: usize) {
+        let offset = if self.quirks.jump_vx { self.v[x] } else { self.v[0] };
+        self.pc = offset as u16 + nnn as u16;
     }
 
     /// OP: Set VX to (RNG AND NN)
     fn op_cxnn(&mut self, x: usize, nn: usize) {
-        let rand: u8 = thread_rng().gen();
-        self.v[x] = rand & nn as u8;
+        self.v[x] = self.rng.next(&self.mem) & nn as u8;
     }
 
     /// OP: Draw sprite to framebuffer
     ///     Display n-byte sprite starting at register I at (VX, VY), then
     ///     set VF = collision
-    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) {
-        self.v[0xf] = 0;
-        for byte_idx in 0..n as u8 {
+    ///     If n is 0, a 16x16 sprite of 32 bytes is drawn instead (SUPER-CHIP)
+    ///     Each selected plane consumes its own sprite data in turn (XO-CHIP)
+    ///     [Quirk] Sprites are clipped at the screen edge instead of wrapping
+    ///     [Quirk] Wait for the next tick before continuing
+    fn op_dxyn(&mut self, x: usize, y: usize, n: usize) -> Result<(), CpuError> {
+        let (width, height) = if n == 0 { (16, 16) } else { (8, n) };
+        let row_bytes = width / 8;
+
+        // Validate the whole sprite up front so a fault leaves the frame untouched
+        let plane_count = self.plane.count_ones() as usize;
+        self.mem_range(self.i as usize, plane_count * height * row_bytes)?;
+
+        let (fb_width, fb_height) = (self.fb.width, self.fb.height);
+        let origin_x = self.v[x] as usize % fb_width;
+        let origin_y = self.v[y] as usize % fb_height;
 
-            let y = ((self.v[y] + byte_idx) % FB_SIZE.y as u8) as usize;
-            
-            let byte = self.mem[(self.i + byte_idx as u16) as usize];
+        self.v[0xf] = 0;
+        let mut sprite_addr = self.i as usize;
+        for plane in [1u8, 2u8] {
+            if self.plane & plane == 0 {
+                continue;
+            }
 
-            for bit_idx in 0..8 {
-                let x = ((self.v[x] + bit_idx) % FB_SIZE.x as u8) as usize;
-                let pixel = (byte & (1 << (7 - bit_idx))) != 0;
-                if pixel && self.fb.data[y * FB_SIZE.x + x] {
-                    self.v[0xf] = 1;
+            for row_idx in 0..height {
+                let y = origin_y + row_idx;
+                if y >= fb_height && self.quirks.clipping {
+                    break;
+                }
+                let y = y % fb_height;
+
+                let row_addr = sprite_addr + row_idx * row_bytes;
+                let row = self.mem[row_addr..row_addr + row_bytes]
+                    .iter()
+                    .fold(0u16, |acc, &byte| acc << 8 | byte as u16);
+
+                for bit_idx in 0..width {
+                    let x = origin_x + bit_idx;
+                    if x >= fb_width && self.quirks.clipping {
+                        break;
+                    }
+                    let x = x % fb_width;
+                    let pixel = (row & (1 << (width - 1 - bit_idx))) != 0;
+                    if self.fb.toggle(x, y, plane, pixel) {
+                        self.v[0xf] = 1;
+                    }
                 }
-                self.fb.data[y * FB_SIZE.x + x] ^= pixel;
             }
+            sprite_addr += height * row_bytes;
         }
         self.fb.update = true;
+
+        if self.quirks.display_wait {
+            self.vblank_wait = true;
+        }
+        Ok(())
     }
 
     /// OP: Skips the next instruction if key in VX is pressed
-    fn op_ex9e(&mut self, x: usize) {
-        let key_idx = self.v[x] as usize;
+    fn op_ex9e(&mut self, x: usize) -> Result<(), CpuError> {
+        // Only the low nibble selects a key, as on the VIP
+        let key_idx = self.v[x] as usize & 0xf;
         if self.kp.state[key_idx] {
-            self.pc += 2;
+            self.skip()?;
         }
+        Ok(())
     }
 
     /// OP: Skips the next instruction if key in VX is not pressed
-    fn op_exa1(&mut self, x: usize) {
-        let key_idx = self.v[x] as usize;
+    fn op_exa1(&mut self, x: usize) -> Result<(), CpuError> {
+        // Only the low nibble selects a key, as on the VIP
+        let key_idx = self.v[x] as usize & 0xf;
         if !self.kp.state[key_idx] {
-            self.pc += 2;
+            self.skip()?;
         }
+        Ok(())
     }
     
+    /// OP: Sets address register to the 16-bit address NNNN in the next word (XO-CHIP)
+    fn op_f000(&mut self) -> Result<(), CpuError> {
+        self.i = self.fetch()?;
+        Ok(())
+    }
+
+    /// OP: Selects drawing planes by bitmask N (XO-CHIP)
+    fn op_fn01(&mut self, n: usize) {
+        self.plane = n as u8 & 0b11;
+    }
+
+    /// OP: Loads 16 bytes from mem at address register into the audio pattern buffer (XO-CHIP)
+    fn op_f002(&mut self) -> Result<(), CpuError> {
+        let mut pattern = [0u8; 16];
+        pattern.copy_from_slice(self.mem_range(self.i as usize, 16)?);
+        self.pattern = Some(pattern);
+        Ok(())
+    }
+
     /// OP: Sets VX to delay timer
     fn op_fx07(&mut self, x: usize) {
         self.v[x] = self.dt;
     }
 
-    /// OP: Wait for key press and store in VX [Blocking operation]
+    /// OP: Wait for a key to be pressed and released, and store it in VX [Blocking operation]
     fn op_fx0a(&mut self, x: usize) {
         self.kp.block = true;
         self.kp.block_reg = x;
@@ -400,38 +661,362 @@ impl CPU {
 
     /// OP: Adds VX to address register
     fn op_fx1e(&mut self, x: usize) {
-        self.i += self.v[x] as u16;
+        self.i = self.i.wrapping_add(self.v[x] as u16);
     }
 
     /// OP: Sets address register to location of sprite for character in VX
     //  These are font characters, and have a height of 5
     fn op_fx29(&mut self, x: usize) {
-        self.i = (self.v[x] * 5) as u16;
+        self.i = (self.v[x] & 0xF) as u16 * 5;
+    }
+
+    /// OP: Sets address register to location of big sprite for character in VX
+    //  These are SUPER-CHIP font characters, and have a height of 10
+    fn op_fx30(&mut self, x: usize) {
+        self.i = 0x50 + (self.v[x] & 0xF) as u16 * 10;
     }
 
     /// OP: Stores the binary-coded decimal representation of VX, with the most
     ///     significant of 3 digits at the address in mem[i], the middle digit 
     ///     at mem[i+1], and the least significant digit at mem[i+2].
-    fn op_fx33(&mut self, x: usize) {
+    fn op_fx33(&mut self, x: usize) -> Result<(), CpuError> {
         let vx = self.v[x];
-        for (idx, digit) in vx.to_string().chars().map(|c| c.to_digit(10).unwrap() as u8).enumerate() {
-            self.mem[self.i as usize + idx] = digit;
-        }
+        let i = self.i as usize;
+        self.mem_range(i, 3)?;
+        self.mem[i]     = vx / 100;
+        self.mem[i + 1] = vx / 10 % 10;
+        self.mem[i + 2] = vx % 10;
+        Ok(())
+    }
+
+    /// OP: Sets the audio pattern playback pitch to VX (XO-CHIP)
+    fn op_fx3a(&mut self, x: usize) {
+        self.pitch = self.v[x];
     }
 
     /// OP: Stores from V0 to VX in mem starting at address register
-    ///     I is left unmodified
-    fn op_fx55(&mut self, x: usize) {
+    ///     [Quirk] I is incremented by X + 1, X, or left unmodified
+    fn op_fx55(&mut self, x: usize) -> Result<(), CpuError> {
+        let i = self.i as usize;
+        self.mem_range(i, x + 1)?;
         for idx in 0..(x+1) {
-            self.mem[self.i as usize + idx] = self.v[idx];
+            self.mem[i + idx] = self.v[idx];
         }
+        self.increment_index(x);
+        Ok(())
     }
 
     /// OP: Fills from V0 to VX with values from mem, starting at address register
-    ///     I is left unmodified
-    fn op_fx65(&mut self, x: usize) {
+    ///     [Quirk] I is incremented by X + 1, X, or left unmodified
+    fn op_fx65(&mut self, x: usize) -> Result<(), CpuError> {
+        let i = self.i as usize;
+        self.mem_range(i, x + 1)?;
         for idx in 0..(x+1) {
-            self.v[idx] = self.mem[self.i as usize + idx];
+            self.v[idx] = self.mem[i + idx];
         }
+        self.increment_index(x);
+        Ok(())
+    }
+
+    /// OP: Stores V0 to VX in the RPL user flags (SUPER-CHIP)
+    fn op_fx75(&mut self, x: usize) {
+        self.rpl[..=x].copy_from_slice(&self.v[..=x]);
+    }
+
+    /// OP: Fills V0 to VX from the RPL user flags (SUPER-CHIP)
+    fn op_fx85(&mut self, x: usize) {
+        self.v[..=x].copy_from_slice(&self.rpl[..=x]);
+    }
+
+    /* Helpers */
+
+    /// Skips the next instruction, which is 4 bytes long if it is F000 NNNN
+    fn skip(&mut self) -> Result<(), CpuError> {
+        let len = if self.peek(self.pc)? == 0xF000 { 4 } else { 2 };
+        self.pc = self.pc.wrapping_add(len);
+        Ok(())
+    }
+
+    /// Borrows len bytes of mem starting at addr, checking that they are in bounds
+    fn mem_range(&self, addr: usize, len: usize) -> Result<&[u8], CpuError> {
+        match self.mem.get(addr..addr + len) {
+            Some(bytes) => Ok(bytes),
+            None => Err(CpuError::MemoryOutOfBounds { addr }),
+        }
+    }
+
+    /// Advances I after a bulk load/store according to the quirk profile
+    fn increment_index(&mut self, x: usize) {
+        match self.quirks.index_increment {
+            IndexIncrement::XPlusOne  => self.i = self.i.wrapping_add(x as u16 + 1),
+            IndexIncrement::X         => self.i = self.i.wrapping_add(x as u16),
+            IndexIncrement::Unchanged => {},
+        }
+    }
+}
+
+/// Iterates over register indices from x to y, in either direction
+fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cpu with the given opcodes loaded at 0x200
+    fn cpu_with(program: &[u16]) -> CPU {
+        let mut cpu = CPU::initialize();
+        for (idx, opcode) in program.iter().enumerate() {
+            cpu.mem[0x200 + idx * 2..0x202 + idx * 2].copy_from_slice(&opcode.to_be_bytes());
+        }
+        cpu
+    }
+
+    #[test]
+    fn load_zeroes_the_rest_and_rejects_oversize_roms() {
+        let mut cpu = CPU::initialize();
+        cpu.mem[0x300] = 0xFF;
+        cpu.load(&[1, 2, 3]).unwrap();
+        assert_eq!(cpu.mem[0x200..0x204], [1, 2, 3, 0]);
+        assert_eq!(cpu.mem[0x300], 0);
+
+        let rom = vec![0; MEM_SIZE - 0x200 + 1];
+        assert_eq!(cpu.load(&rom), Err(RomTooLarge { size: rom.len(), max: MEM_SIZE - 0x200 }));
+        assert_eq!(cpu.mem[0x200], 1);
+    }
+
+    #[test]
+    fn arithmetic_flags_win_over_vf_results() {
+        // VF = 0xFF; VF += VF; VF -= V0; VF =- V0
+        let mut cpu = cpu_with(&[0x6FFF, 0x8FF4, 0x8F05, 0x8F07]);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.v[0xf], 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.v[0xf], 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.v[0xf], 0);
+    }
+
+    #[test]
+    fn key_skips_use_the_low_nibble() {
+        // V0 = 0x15, skip if key 5 is pressed
+        let mut cpu = cpu_with(&[0x6015, 0xE09E, 0xE0A1]);
+        cpu.kp.set(5, true);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+
+        cpu.pc = 0x204;
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn get_key_returns_once_the_key_is_released() {
+        // V3 = key, V0 = 1
+        let mut cpu = cpu_with(&[0xF30A, 0x6001]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForKey));
+
+        cpu.kp.set(7, true);
+        assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForKey));
+        // Other keys don't matter once one is down
+        cpu.kp.set(2, true);
+        cpu.kp.set(7, false);
+        assert_eq!(cpu.step(), Ok(StepOutcome::Executed));
+        assert_eq!((cpu.v[3], cpu.pc, cpu.kp.block), (7, 0x202, false));
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.v[0], 1);
+    }
+
+    /// Runs `program` to completion under each setting of a quirk
+    fn with_quirk(program: &[u16], set: impl Fn(&mut Quirks, bool)) -> [CPU; 2] {
+        [false, true].map(|on| {
+            let mut cpu = cpu_with(program);
+            set(&mut cpu.quirks, on);
+            for _ in program {
+                cpu.step().unwrap();
+            }
+            cpu
+        })
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        // VF = 1, V0 |= V1
+        let [off, on] = with_quirk(&[0x6F01, 0x8011], |q, on| q.vf_reset = on);
+        assert_eq!((off.v[0xf], on.v[0xf]), (1, 0));
+    }
+
+    #[test]
+    fn shift_vy_quirk() {
+        // V0 = 0x10, V1 = 0x03, V0 >>= V1
+        let [off, on] = with_quirk(&[0x6010, 0x6103, 0x8016], |q, on| q.shift_vy = on);
+        assert_eq!((off.v[0], off.v[0xf]), (0x08, 0));
+        assert_eq!((on.v[0], on.v[0xf]), (0x01, 1));
+    }
+
+    #[test]
+    fn index_increment_quirk() {
+        // I = 0x300, store V0-V2
+        let program = [0xA300, 0xF255];
+        let cpus = [IndexIncrement::XPlusOne, IndexIncrement::X, IndexIncrement::Unchanged].map(|inc| {
+            let mut cpu = cpu_with(&program);
+            cpu.quirks.index_increment = inc;
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+            cpu.i
+        });
+        assert_eq!(cpus, [0x303, 0x302, 0x300]);
+    }
+
+    #[test]
+    fn jump_vx_quirk() {
+        // V0 = 0x10, V3 = 0x20, jump to 0x300 + V0 or V3
+        let [off, on] = with_quirk(&[0x6010, 0x6320, 0xB300], |q, on| q.jump_vx = on);
+        assert_eq!((off.pc, on.pc), (0x310, 0x320));
+    }
+
+    #[test]
+    fn clipping_quirk() {
+        // V0 = 60, V1 = 31, I = 0x300, draw 2 rows of 8 pixels
+        let program = [0x603C, 0x611F, 0xA300, 0xD012];
+        let [wrap, clip] = [false, true].map(|on| {
+            let mut cpu = cpu_with(&program);
+            cpu.quirks.clipping = on;
+            cpu.mem[0x300..0x302].fill(0xFF);
+            for _ in program {
+                cpu.step().unwrap();
+            }
+            cpu
+        });
+        assert_eq!((wrap.fb.get(63, 31), wrap.fb.get(0, 31), wrap.fb.get(60, 0), wrap.fb.get(0, 0)), (1, 1, 1, 1));
+        assert_eq!((clip.fb.get(63, 31), clip.fb.get(0, 31), clip.fb.get(60, 0), clip.fb.get(0, 0)), (1, 0, 0, 0));
+    }
+
+    #[test]
+    fn display_wait_quirk() {
+        // Draw, then V0 = 1
+        for on in [false, true] {
+            let mut cpu = cpu_with(&[0xD001, 0x6001]);
+            cpu.quirks.display_wait = on;
+            cpu.step().unwrap();
+            if on {
+                assert_eq!(cpu.step(), Ok(StepOutcome::WaitingForVblank));
+                cpu.tick();
+            }
+            assert_eq!(cpu.step(), Ok(StepOutcome::Executed));
+            assert_eq!(cpu.v[0], 1);
+        }
+    }
+
+    #[test]
+    fn schip_scrolling_and_resolution() {
+        // HIGH, scroll down 2, right 4, left 4, LOW
+        let mut cpu = cpu_with(&[0x00FF, 0x00C2, 0x00FB, 0x00FC, 0x00FC, 0x00FE]);
+        cpu.step().unwrap();
+        assert_eq!((cpu.fb.width, cpu.fb.height), (128, 64));
+        cpu.fb.toggle(10, 10, 1, true);
+        cpu.step().unwrap();
+        assert_eq!(cpu.fb.get(10, 12), 1);
+        cpu.step().unwrap();
+        assert_eq!(cpu.fb.get(14, 12), 1);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!((cpu.fb.get(6, 12), cpu.fb.get(10, 12)), (1, 0));
+        cpu.step().unwrap();
+        assert!(!cpu.fb.hires());
+    }
+
+    #[test]
+    fn dxy0_draws_16x16_sprites() {
+        // HIGH, I = 0x300, draw at (V0, V0)
+        let mut cpu = cpu_with(&[0x00FF, 0xA300, 0xD000]);
+        for row in 0..16 {
+            cpu.mem[0x300 + row * 2..0x302 + row * 2].copy_from_slice(&(0x8001u16 << (row % 2)).to_be_bytes());
+        }
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.fb.get(0, 0), cpu.fb.get(15, 0), cpu.fb.get(14, 0)), (1, 1, 0));
+        assert_eq!((cpu.fb.get(0, 1), cpu.fb.get(14, 1), cpu.fb.get(15, 1)), (0, 1, 0));
+        assert_eq!((cpu.fb.get(0, 14), cpu.fb.get(0, 15), cpu.fb.get(0, 16)), (1, 0, 0));
+        assert_eq!(cpu.v[0xf], 0);
+    }
+
+    #[test]
+    fn planes_select_what_draws_and_clears() {
+        // I = 0x300, planes 3, draw a 1-row sprite, plane 2, clear
+        let mut cpu = cpu_with(&[0xA300, 0xF301, 0xD001, 0xF201, 0x00E0]);
+        cpu.mem[0x300] = 0x80;
+        cpu.mem[0x301] = 0xC0;
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        // Each selected plane reads its own rows, one after the other
+        assert_eq!((cpu.fb.get(0, 0), cpu.fb.get(1, 0)), (3, 2));
+        cpu.tick();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.plane, 2);
+        assert_eq!((cpu.fb.get(0, 0), cpu.fb.get(1, 0)), (1, 0));
+    }
+
+    #[test]
+    fn register_ranges_go_both_ways() {
+        // I = 0x300, store V1-V3, store V3-V1 at 0x310, load V6-V4 from 0x300
+        let mut cpu = cpu_with(&[0xA300, 0x5132, 0xA310, 0x5312, 0xA300, 0x5643]);
+        cpu.v[1..4].copy_from_slice(&[1, 2, 3]);
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.mem[0x300..0x303], [1, 2, 3]);
+        assert_eq!(cpu.mem[0x310..0x313], [3, 2, 1]);
+        assert_eq!(cpu.v[4..7], [3, 2, 1]);
+        assert_eq!(cpu.i, 0x300);
+    }
+
+    #[test]
+    fn skips_step_over_long_loads() {
+        // Skip if V0 == 0 over I = long 0x1234, then V1 = 1
+        let mut cpu = cpu_with(&[0x3000, 0xF000, 0x1234, 0x6101]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+        cpu.pc = 0x202;
+        cpu.step().unwrap();
+        assert_eq!((cpu.i, cpu.pc), (0x1234, 0x206));
+    }
+
+    #[test]
+    fn audio_pattern_and_pitch() {
+        // I = 0x300, load the pattern, V2 = 0x70, pitch = V2
+        let mut cpu = cpu_with(&[0xA300, 0xF002, 0x6270, 0xF23A]);
+        cpu.mem[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        for _ in 0..4 {
+            cpu.step().unwrap();
+        }
+        assert_eq!((cpu.pattern, cpu.pitch), (Some([0xAA; 16]), 0x70));
+
+    }
+
+    #[test]
+    fn faults_report_where_the_access_started() {
+        // I = 0xFFF8, load an audio pattern; I = 0xFFFF, store V0-V1
+        let mut cpu = cpu_with(&[0xF000, 0xFFF8, 0xF002, 0xF000, 0xFFFF, 0xF155]);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(CpuError::MemoryOutOfBounds { addr: 0xFFF8 }));
+        assert_eq!(cpu.pc, 0x204);
+
+        cpu.pc = 0x206;
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(CpuError::MemoryOutOfBounds { addr: 0xFFFF }));
+        assert_eq!(cpu.mem[0xFFFF], 0);
     }
 }
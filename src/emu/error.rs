@@ -0,0 +1,54 @@
+use std::{
+    error::Error,
+    fmt,
+};
+
+/// A fault raised by the CPU while executing an instruction.
+/// The CPU state is left as it was when the fault occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /// The opcode at pc does not decode to any known instruction
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// 0NNN called a native machine code routine, which is not supported
+    MachineCodeCall { pc: u16, addr: u16 },
+    /// 2NNN was called with all 16 stack frames in use
+    StackOverflow { pc: u16 },
+    /// 00EE was called with an empty stack
+    StackUnderflow { pc: u16 },
+    /// An instruction accessed memory past the end of mem, starting at addr
+    MemoryOutOfBounds { addr: usize },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { pc, opcode } =>
+                write!(f, "unknown opcode {:#06x} at {:#06x}", opcode, pc),
+            CpuError::MachineCodeCall { pc, addr } =>
+                write!(f, "unsupported machine code call to {:#05x} at {:#06x}", addr, pc),
+            CpuError::StackOverflow { pc } =>
+                write!(f, "stack overflow at {:#06x}", pc),
+            CpuError::StackUnderflow { pc } =>
+                write!(f, "stack underflow at {:#06x}", pc),
+            CpuError::MemoryOutOfBounds { addr } =>
+                write!(f, "memory access out of bounds at {:#07x}", addr),
+        }
+    }
+}
+
+impl Error for CpuError {}
+
+/// A rom too large to fit in memory above 0x200
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for RomTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rom is too large: {} bytes (max {})", self.size, self.max)
+    }
+}
+
+impl Error for RomTooLarge {}
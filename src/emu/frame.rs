@@ -1,28 +1,180 @@
-/// Defines the size of the emulator frame buffer. By spec this is 64x32
-pub const FB_SIZE: FramebufSpec = FramebufSpec { x: 64, y: 32 };
+/// Defines the low resolution frame size. By spec this is 64x32
+pub const LORES: FramebufSpec = FramebufSpec { x: 64, y: 32 };
+/// Defines the SUPER-CHIP high resolution frame size, 128x64
+pub const HIRES: FramebufSpec = FramebufSpec { x: 128, y: 64 };
 pub struct FramebufSpec { pub x: usize, pub y: usize, }
 
-pub type FrameBuffer = [bool; FB_SIZE.y * FB_SIZE.x];
+/// Each pixel holds one bit per plane, giving a colour index from 0 to 3
+pub type FrameBuffer = Vec<u8>;
+
+/// Number of bitplanes (XO-CHIP)
+pub const PLANES: usize = 2;
+
+/// An RGB colour for each combination of the two bitplanes
+pub type Palette = [[u8; 3]; 1 << PLANES];
+
+/// The default colours, also used for image dumps
+pub const PALETTE: Palette = [
+    [145, 145, 135],
+    [32, 42, 52],
+    [200, 90, 60],
+    [90, 60, 50],
+];
 
 
 /// The internal emulator framebuffer.
-#[derive(Debug)]
+/// The resolution can be switched between LORES and HIRES at runtime.
+/// Pixels are made up of PLANES bitplanes which can be drawn to separately.
+#[derive(Debug, Clone)]
 pub struct Frame {
     pub data: FrameBuffer,
+    pub width: usize,
+    pub height: usize,
     pub update: bool,
 }
 
 #[allow(clippy::new_without_default)]
 impl Frame {
     pub fn new() -> Self {
-        let data = [false; FB_SIZE.y * FB_SIZE.x];
+        let data = vec![0; LORES.y * LORES.x];
         let update = false;
-        Frame { data, update }
+        Frame { data, width: LORES.x, height: LORES.y, update }
     }
 
+    /// Reset to a blank low resolution frame
     pub fn reset(&mut self) {
-        self.data.fill(false);
+        self.set_hires(false);
         self.update = false;
     }
+
+    /// Switch between low and high resolution. This clears the frame.
+    pub fn set_hires(&mut self, hires: bool) {
+        let spec = if hires { HIRES } else { LORES };
+        self.width = spec.x;
+        self.height = spec.y;
+        self.data = vec![0; spec.y * spec.x];
+        self.update = true;
+    }
+
+    /// Whether the frame is currently in high resolution mode
+    pub fn hires(&self) -> bool {
+        self.width == HIRES.x
+    }
+
+    /// Clear every pixel on the selected planes
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in self.data.iter_mut() {
+            *pixel &= !planes;
+        }
+        self.update = true;
+    }
+
+    /// Get the colour index of the pixel at (x, y)
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.data[y * self.width + x]
+    }
+
+    /// XOR a pixel into a single plane at (x, y). Returns true on collision.
+    pub fn toggle(&mut self, x: usize, y: usize, plane: u8, pixel: bool) -> bool {
+        if !pixel {
+            return false;
+        }
+        let idx = y * self.width + x;
+        let collision = self.data[idx] & plane != 0;
+        self.data[idx] ^= plane;
+        collision
+    }
+
+    /// Scroll the selected planes down by n rows
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height) as isize;
+        self.shift(0, n, planes);
+    }
+
+    /// Scroll the selected planes up by n rows
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height) as isize;
+        self.shift(0, -n, planes);
+    }
+
+    /// Scroll the selected planes right by n columns
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width) as isize;
+        self.shift(n, 0, planes);
+    }
+
+    /// Scroll the selected planes left by n columns
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width) as isize;
+        self.shift(-n, 0, planes);
+    }
+
+    /// Moves the selected planes by (dx, dy). Pixels moved off the frame are
+    /// discarded and uncovered pixels are cleared.
+    fn shift(&mut self, dx: isize, dy: isize, planes: u8) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        let src = self.data.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&src_x) && (0..height).contains(&src_y) {
+                    src[(src_y * width + src_x) as usize] & planes
+                } else {
+                    0
+                };
+                let idx = (y * width + x) as usize;
+                self.data[idx] = (self.data[idx] & !planes) | moved;
+            }
+        }
+        self.update = true;
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling_moves_only_the_selected_planes() {
+        let mut frame = Frame::new();
+        frame.toggle(10, 5, 1, true);
+        frame.toggle(10, 5, 2, true);
+
+        frame.scroll_down(3, 1);
+        assert_eq!((frame.get(10, 5), frame.get(10, 8)), (2, 1));
+        frame.scroll_up(3, 3);
+        assert_eq!((frame.get(10, 2), frame.get(10, 5)), (2, 1));
+        frame.scroll_right(4, 3);
+        assert_eq!((frame.get(14, 2), frame.get(14, 5), frame.get(10, 5)), (2, 1, 0));
+        frame.scroll_left(14, 3);
+        assert_eq!((frame.get(0, 2), frame.get(0, 5)), (2, 1));
+    }
+
+    #[test]
+    fn scrolling_discards_pixels_moved_off_the_frame() {
+        let mut frame = Frame::new();
+        frame.toggle(0, 31, 1, true);
+        frame.scroll_down(1, 1);
+        frame.scroll_up(1, 1);
+        assert!(frame.data.iter().all(|&pixel| pixel == 0));
+
+        frame.toggle(63, 0, 1, true);
+        frame.scroll_right(100, 1);
+        assert!(frame.data.iter().all(|&pixel| pixel == 0));
+    }
+
+    #[test]
+    fn switching_resolution_clears_the_frame() {
+        let mut frame = Frame::new();
+        frame.toggle(1, 1, 1, true);
+        frame.set_hires(true);
+        assert!(frame.hires());
+        assert_eq!((frame.width, frame.height, frame.data.len()), (128, 64, 128 * 64));
+        assert!(frame.data.iter().all(|&pixel| pixel == 0));
+
+        frame.toggle(100, 60, 1, true);
+        frame.reset();
+        assert!(!frame.hires());
+        assert_eq!(frame.data, vec![0; 64 * 32]);
+    }
+}
@@ -0,0 +1,378 @@
+use std::fmt;
+
+/// A decoded CHIP-8, SUPER-CHIP or XO-CHIP instruction.
+///
+/// Register operands are indices from 0x0 to 0xF. Displays as the
+/// standard (Cowgod-style) mnemonic, e.g. `ADD V3, 0x01`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN: Call machine code routine at NNN
+    Sys { nnn: u16 },
+    /// 00CN: Scroll the display down by N pixels (SUPER-CHIP)
+    ScrollDown { n: u8 },
+    /// 00DN: Scroll the display up by N pixels (XO-CHIP)
+    ScrollUp { n: u8 },
+    /// 00E0: Clear the screen
+    Clear,
+    /// 00EE: Return from a subroutine
+    Return,
+    /// 00FB: Scroll the display right by 4 pixels (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC: Scroll the display left by 4 pixels (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD: Exit the interpreter (SUPER-CHIP)
+    Exit,
+    /// 00FE: Switch to low resolution (SUPER-CHIP)
+    Lores,
+    /// 00FF: Switch to high resolution (SUPER-CHIP)
+    Hires,
+    /// 1NNN: Jump to NNN
+    Jump { nnn: u16 },
+    /// 2NNN: Call subroutine at NNN
+    Call { nnn: u16 },
+    /// 3XNN: Skip if VX == NN
+    SkipEqImm { x: u8, nn: u8 },
+    /// 4XNN: Skip if VX != NN
+    SkipNeImm { x: u8, nn: u8 },
+    /// 5XY0: Skip if VX == VY
+    SkipEqReg { x: u8, y: u8 },
+    /// 5XY2: Store VX to VY at I (XO-CHIP)
+    StoreRange { x: u8, y: u8 },
+    /// 5XY3: Load VX to VY from I (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN: Set VX to NN
+    LoadImm { x: u8, nn: u8 },
+    /// 7XNN: Add NN to VX
+    AddImm { x: u8, nn: u8 },
+    /// 8XY0: Set VX to VY
+    LoadReg { x: u8, y: u8 },
+    /// 8XY1: Set VX to VX OR VY
+    Or { x: u8, y: u8 },
+    /// 8XY2: Set VX to VX AND VY
+    And { x: u8, y: u8 },
+    /// 8XY3: Set VX to VX XOR VY
+    Xor { x: u8, y: u8 },
+    /// 8XY4: Add VY to VX, VF = carry
+    AddReg { x: u8, y: u8 },
+    /// 8XY5: Subtract VY from VX, VF = NOT borrow
+    Sub { x: u8, y: u8 },
+    /// 8XY6: Shift right, VF = shifted out bit
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7: Set VX to VY - VX, VF = NOT borrow
+    SubN { x: u8, y: u8 },
+    /// 8XYE: Shift left, VF = shifted out bit
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0: Skip if VX != VY
+    SkipNeReg { x: u8, y: u8 },
+    /// ANNN: Set I to NNN
+    LoadI { nnn: u16 },
+    /// BNNN: Jump to NNN + V0 (or XNN + VX, depending on quirks).
+    ///
+    /// `x` is always the top nibble of `nnn`, so the opcode is encoded from
+    /// `nnn` alone. It displays as `JP V0, NNN` whatever the quirk, as the
+    /// mnemonic doesn't know which register the cpu will use.
+    JumpOffset { x: u8, nnn: u16 },
+    /// CXNN: Set VX to a random number AND NN
+    Random { x: u8, nn: u8 },
+    /// DXYN: Draw an N-byte sprite at (VX, VY)
+    Draw { x: u8, y: u8, n: u8 },
+    /// EX9E: Skip if the key in VX is pressed
+    SkipKey { x: u8 },
+    /// EXA1: Skip if the key in VX is not pressed
+    SkipNotKey { x: u8 },
+    /// F000 NNNN: Set I to the 16-bit address in the next word (XO-CHIP)
+    LoadLongI,
+    /// FN01: Select drawing planes (XO-CHIP)
+    Plane { n: u8 },
+    /// F002: Load the audio pattern buffer from I (XO-CHIP)
+    Audio,
+    /// FX07: Set VX to the delay timer
+    GetDelay { x: u8 },
+    /// FX0A: Wait for a key to be pressed and released, and store it in VX
+    WaitKey { x: u8 },
+    /// FX15: Set the delay timer to VX
+    SetDelay { x: u8 },
+    /// FX18: Set the sound timer to VX
+    SetSound { x: u8 },
+    /// FX1E: Add VX to I
+    AddI { x: u8 },
+    /// FX29: Set I to the small font character in VX
+    Font { x: u8 },
+    /// FX30: Set I to the big font character in VX (SUPER-CHIP)
+    BigFont { x: u8 },
+    /// FX33: Store the BCD representation of VX at I
+    Bcd { x: u8 },
+    /// FX3A: Set the audio pitch to VX (XO-CHIP)
+    Pitch { x: u8 },
+    /// FX55: Store V0 to VX at I
+    Store { x: u8 },
+    /// FX65: Load V0 to VX from I
+    Load { x: u8 },
+    /// FX75: Store V0 to VX in the RPL flags (SUPER-CHIP)
+    SaveFlags { x: u8 },
+    /// FX85: Load V0 to VX from the RPL flags (SUPER-CHIP)
+    LoadFlags { x: u8 },
+}
+
+impl Instruction {
+    /// Decodes an opcode, returning None if it is not a known instruction
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let op = (
+            ((opcode & 0xF000) >> 12) as u8,
+            ((opcode & 0x0F00) >> 8) as u8,
+            ((opcode & 0x00F0) >> 4) as u8,
+            ((opcode & 0x000F) as u8),
+        );
+
+        let x   = ((opcode & 0x0F00) >> 8) as u8;
+        let y   = ((opcode & 0x00F0) >> 4) as u8;
+        let n   = (opcode & 0x000F) as u8;
+        let nn  = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        use Instruction::*;
+        let instruction = match op {
+            (0x0, 0x0, 0xC, _) => ScrollDown { n },
+            (0x0, 0x0, 0xD, _) => ScrollUp { n },
+            (0x0, 0x0, 0xE, 0x0) => Clear,
+            (0x0, 0x0, 0xE, 0xE) => Return,
+            (0x0, 0x0, 0xF, 0xB) => ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Exit,
+            (0x0, 0x0, 0xF, 0xE) => Lores,
+            (0x0, 0x0, 0xF, 0xF) => Hires,
+            (0x0, _, _, _) => Sys { nnn },
+            (0x1, _, _, _) => Jump { nnn },
+            (0x2, _, _, _) => Call { nnn },
+            (0x3, _, _, _) => SkipEqImm { x, nn },
+            (0x4, _, _, _) => SkipNeImm { x, nn },
+            (0x5, _, _, 0x0) => SkipEqReg { x, y },
+            (0x5, _, _, 0x2) => StoreRange { x, y },
+            (0x5, _, _, 0x3) => LoadRange { x, y },
+            (0x6, _, _, _) => LoadImm { x, nn },
+            (0x7, _, _, _) => AddImm { x, nn },
+            (0x8, _, _, 0x0) => LoadReg { x, y },
+            (0x8, _, _, 0x1) => Or { x, y },
+            (0x8, _, _, 0x2) => And { x, y },
+            (0x8, _, _, 0x3) => Xor { x, y },
+            (0x8, _, _, 0x4) => AddReg { x, y },
+            (0x8, _, _, 0x5) => Sub { x, y },
+            (0x8, _, _, 0x6) => ShiftRight { x, y },
+            (0x8, _, _, 0x7) => SubN { x, y },
+            (0x8, _, _, 0xE) => ShiftLeft { x, y },
+            (0x9, _, _, 0x0) => SkipNeReg { x, y },
+            (0xA, _, _, _) => LoadI { nnn },
+            (0xB, _, _, _) => JumpOffset { x, nnn },
+            (0xC, _, _, _) => Random { x, nn },
+            (0xD, _, _, _) => Draw { x, y, n },
+            (0xE, _, 0x9, 0xE) => SkipKey { x },
+            (0xE, _, 0xA, 0x1) => SkipNotKey { x },
+            (0xF, 0x0, 0x0, 0x0) => LoadLongI,
+            (0xF, _, 0x0, 0x1) => Plane { n: x },
+            (0xF, 0x0, 0x0, 0x2) => Audio,
+            (0xF, _, 0x0, 0x7) => GetDelay { x },
+            (0xF, _, 0x0, 0xA) => WaitKey { x },
+            (0xF, _, 0x1, 0x5) => SetDelay { x },
+            (0xF, _, 0x1, 0x8) => SetSound { x },
+            (0xF, _, 0x1, 0xE) => AddI { x },
+            (0xF, _, 0x2, 0x9) => Font { x },
+            (0xF, _, 0x3, 0x0) => BigFont { x },
+            (0xF, _, 0x3, 0x3) => Bcd { x },
+            (0xF, _, 0x3, 0xA) => Pitch { x },
+            (0xF, _, 0x5, 0x5) => Store { x },
+            (0xF, _, 0x6, 0x5) => Load { x },
+            (0xF, _, 0x7, 0x5) => SaveFlags { x },
+            (0xF, _, 0x8, 0x5) => LoadFlags { x },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// Encodes the instruction back into its opcode.
+    /// For LoadLongI this is only the first word; the address follows it.
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4;
+        let xnn = |base: u16, x: u8, nn: u8| base | (x as u16 & 0xF) << 8 | nn as u16;
+        let fx = |low: u16, x: u8| 0xF000 | (x as u16 & 0xF) << 8 | low;
+
+        use Instruction::*;
+        match *self {
+            Sys { nnn }           => nnn & 0xFFF,
+            ScrollDown { n }      => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp { n }        => 0x00D0 | (n as u16 & 0xF),
+            Clear                 => 0x00E0,
+            Return                => 0x00EE,
+            ScrollRight           => 0x00FB,
+            ScrollLeft            => 0x00FC,
+            Exit                  => 0x00FD,
+            Lores                 => 0x00FE,
+            Hires                 => 0x00FF,
+            Jump { nnn }          => 0x1000 | (nnn & 0xFFF),
+            Call { nnn }          => 0x2000 | (nnn & 0xFFF),
+            SkipEqImm { x, nn }   => xnn(0x3000, x, nn),
+            SkipNeImm { x, nn }   => xnn(0x4000, x, nn),
+            SkipEqReg { x, y }    => xy(0x5000, x, y),
+            StoreRange { x, y }   => xy(0x5002, x, y),
+            LoadRange { x, y }    => xy(0x5003, x, y),
+            LoadImm { x, nn }     => xnn(0x6000, x, nn),
+            AddImm { x, nn }      => xnn(0x7000, x, nn),
+            LoadReg { x, y }      => xy(0x8000, x, y),
+            Or { x, y }           => xy(0x8001, x, y),
+            And { x, y }          => xy(0x8002, x, y),
+            Xor { x, y }          => xy(0x8003, x, y),
+            AddReg { x, y }       => xy(0x8004, x, y),
+            Sub { x, y }          => xy(0x8005, x, y),
+            ShiftRight { x, y }   => xy(0x8006, x, y),
+            SubN { x, y }         => xy(0x8007, x, y),
+            ShiftLeft { x, y }    => xy(0x800E, x, y),
+            SkipNeReg { x, y }    => xy(0x9000, x, y),
+            LoadI { nnn }         => 0xA000 | (nnn & 0xFFF),
+            JumpOffset { nnn, .. } => 0xB000 | (nnn & 0xFFF),
+            Random { x, nn }      => xnn(0xC000, x, nn),
+            Draw { x, y, n }      => xy(0xD000, x, y) | (n as u16 & 0xF),
+            SkipKey { x }         => xnn(0xE000, x, 0x9E),
+            SkipNotKey { x }      => xnn(0xE000, x, 0xA1),
+            LoadLongI             => 0xF000,
+            Plane { n }           => fx(0x01, n),
+            Audio                 => 0xF002,
+            GetDelay { x }        => fx(0x07, x),
+            WaitKey { x }         => fx(0x0A, x),
+            SetDelay { x }        => fx(0x15, x),
+            SetSound { x }        => fx(0x18, x),
+            AddI { x }            => fx(0x1E, x),
+            Font { x }            => fx(0x29, x),
+            BigFont { x }         => fx(0x30, x),
+            Bcd { x }             => fx(0x33, x),
+            Pitch { x }           => fx(0x3A, x),
+            Store { x }           => fx(0x55, x),
+            Load { x }            => fx(0x65, x),
+            SaveFlags { x }       => fx(0x75, x),
+            LoadFlags { x }       => fx(0x85, x),
+        }
+    }
+
+    /// Size of the instruction in bytes, including any operand words
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LoadLongI => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+        match *self {
+            Sys { nnn }           => write!(f, "SYS {:#05x}", nnn),
+            ScrollDown { n }      => write!(f, "SCD {}", n),
+            ScrollUp { n }        => write!(f, "SCU {}", n),
+            Clear                 => write!(f, "CLS"),
+            Return                => write!(f, "RET"),
+            ScrollRight           => write!(f, "SCR"),
+            ScrollLeft            => write!(f, "SCL"),
+            Exit                  => write!(f, "EXIT"),
+            Lores                 => write!(f, "LOW"),
+            Hires                 => write!(f, "HIGH"),
+            Jump { nnn }          => write!(f, "JP {:#05x}", nnn),
+            Call { nnn }          => write!(f, "CALL {:#05x}", nnn),
+            SkipEqImm { x, nn }   => write!(f, "SE V{:X}, {:#04x}", x, nn),
+            SkipNeImm { x, nn }   => write!(f, "SNE V{:X}, {:#04x}", x, nn),
+            SkipEqReg { x, y }    => write!(f, "SE V{:X}, V{:X}", x, y),
+            StoreRange { x, y }   => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange { x, y }    => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LoadImm { x, nn }     => write!(f, "LD V{:X}, {:#04x}", x, nn),
+            AddImm { x, nn }      => write!(f, "ADD V{:X}, {:#04x}", x, nn),
+            LoadReg { x, y }      => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y }           => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y }          => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y }          => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y }       => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y }          => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y }   => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN { x, y }         => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y }    => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y }    => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadI { nnn }         => write!(f, "LD I, {:#05x}", nnn),
+            JumpOffset { nnn, .. } => write!(f, "JP V0, {:#05x}", nnn),
+            Random { x, nn }      => write!(f, "RND V{:X}, {:#04x}", x, nn),
+            Draw { x, y, n }      => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            SkipKey { x }         => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x }      => write!(f, "SKNP V{:X}", x),
+            LoadLongI             => write!(f, "LD I, LONG"),
+            Plane { n }           => write!(f, "PLANE {}", n),
+            Audio                 => write!(f, "AUDIO"),
+            GetDelay { x }        => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x }         => write!(f, "LD V{:X}, K", x),
+            SetDelay { x }        => write!(f, "LD DT, V{:X}", x),
+            SetSound { x }        => write!(f, "LD ST, V{:X}", x),
+            AddI { x }            => write!(f, "ADD I, V{:X}", x),
+            Font { x }            => write!(f, "LD F, V{:X}", x),
+            BigFont { x }         => write!(f, "LD HF, V{:X}", x),
+            Bcd { x }             => write!(f, "LD B, V{:X}", x),
+            Pitch { x }           => write!(f, "PITCH V{:X}", x),
+            Store { x }           => write!(f, "LD [I], V{:X}", x),
+            Load { x }            => write!(f, "LD V{:X}, [I]", x),
+            SaveFlags { x }       => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x }       => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_roundtrips() {
+        let mut known = 0;
+        for opcode in 0..=0xFFFF {
+            let Some(instruction) = Instruction::decode(opcode) else { continue };
+            known += 1;
+            assert_eq!(instruction.encode(), opcode, "{}", instruction);
+            assert_eq!(Instruction::decode(instruction.encode()), Some(instruction));
+        }
+        assert_eq!(known, 48642);
+        assert_eq!(Instruction::decode(0x5001), None);
+        assert_eq!(Instruction::decode(0xE000), None);
+        assert_eq!(Instruction::decode(0xF100), None);
+    }
+
+    #[test]
+    fn jump_offsets_keep_their_register() {
+        let instruction = Instruction::decode(0xB3A4).unwrap();
+        assert_eq!(instruction, Instruction::JumpOffset { x: 3, nnn: 0x3A4 });
+        assert_eq!(instruction.encode(), 0xB3A4);
+        assert_eq!(instruction.to_string(), "JP V0, 0x3a4");
+    }
+
+    #[test]
+    fn mnemonics() {
+        let cases = [
+            (0x00C4, "SCD 4"),
+            (0x00E0, "CLS"),
+            (0x00FF, "HIGH"),
+            (0x1234, "JP 0x234"),
+            (0x2ABC, "CALL 0xabc"),
+            (0x3A0F, "SE VA, 0x0f"),
+            (0x5122, "LD [I], V1-V2"),
+            (0x5213, "LD V2-V1, [I]"),
+            (0x8AB4, "ADD VA, VB"),
+            (0x8A0E, "SHL VA, V0"),
+            (0xA123, "LD I, 0x123"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xE19E, "SKP V1"),
+            (0xF000, "LD I, LONG"),
+            (0xF201, "PLANE 2"),
+            (0xF002, "AUDIO"),
+            (0xF50A, "LD V5, K"),
+            (0xF33A, "PITCH V3"),
+            (0xFF85, "LD VF, R"),
+        ];
+        for (opcode, text) in cases {
+            assert_eq!(Instruction::decode(opcode).unwrap().to_string(), text);
+        }
+        assert_eq!(Instruction::LoadLongI.size(), 4);
+        assert_eq!(Instruction::Clear.size(), 2);
+    }
+}
@@ -4,16 +4,23 @@ pub struct Keypad {
     pub state: [bool; 16],
     pub block: bool,
     pub block_reg: usize,
+    /// Key pressed during an FX0A wait, which completes once it's released
+    pub block_key: Option<usize>,
 }
 
 #[allow(clippy::new_without_default)]
 impl Keypad {
     pub fn new() -> Self {
-        Keypad{ state: [false; 16], block: false, block_reg: 0 }
+        Keypad{ state: [false; 16], block: false, block_reg: 0, block_key: None }
     }
 
-    /// Reset the keypad state to neutral
+    /// Reset the keypad to neutral, cancelling any FX0A wait
     pub fn reset(&mut self) {
+        *self = Keypad::new();
+    }
+
+    /// Release every key, leaving any FX0A wait in place
+    pub fn release_all(&mut self) {
         self.state.fill(false);
     }
     
@@ -0,0 +1,279 @@
+use crate::emu::{
+    cpu::{
+        CPU,
+        StepOutcome,
+        CYCLES_PER_FRAME,
+    },
+    error::{
+        CpuError,
+        RomTooLarge,
+    },
+    frame::Frame,
+    instruction::Instruction,
+    quirks::Quirks,
+    timing::{
+        self,
+        Timing,
+        VIP_INTERPRETER_CYCLES,
+    },
+};
+
+/// The state of the hex keypad for one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputState {
+    pub keys: [bool; 16],
+}
+
+/// What the speaker should be doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioOutput {
+    /// Whether the sound timer is running
+    pub playing: bool,
+    /// XO-CHIP pattern buffer, or None for the default tone
+    pub pattern: Option<[u8; 16]>,
+    /// XO-CHIP pattern playback pitch
+    pub pitch: u8,
+}
+
+/// Something that happened during a frame which the frontend may want to act on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineEvent {
+    /// The program exited through 00FD
+    Exited,
+    /// The cpu faulted. The machine stays paused until reset or reloaded
+    Fault(CpuError),
+}
+
+/// Everything a frontend needs to present a frame
+#[derive(Debug)]
+pub struct FrameOutput {
+    /// A copy of the framebuffer, if it changed since the last output
+    pub frame: Option<Frame>,
+    pub audio: AudioOutput,
+    pub events: Vec<MachineEvent>,
+}
+
+/// A complete chip-8 system, independent of any frontend.
+///
+/// Frontends feed in the keypad state once per 60Hz frame and present the
+/// framebuffer and audio state that come back.
+#[derive(Debug)]
+pub struct Machine {
+    pub cpu: CPU,
+    /// Instructions executed per frame, under fixed timing
+    pub cycles_per_frame: usize,
+    pub timing: Timing,
+    fault: Option<CpuError>,
+}
+
+impl Machine {
+    pub fn new(quirks: Quirks) -> Self {
+        Machine {
+            cpu: CPU::with_quirks(quirks),
+            cycles_per_frame: CYCLES_PER_FRAME,
+            timing: Timing::default(),
+            fault: None,
+        }
+    }
+
+    /// Resets the machine and loads a new program
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), RomTooLarge> {
+        self.cpu.reset();
+        self.cpu.load(rom)?;
+        self.fault = None;
+        Ok(())
+    }
+
+    /// The fault that paused the machine, if any
+    pub fn fault(&self) -> Option<CpuError> {
+        self.fault
+    }
+
+    /// Resume after a fault, e.g. once the cpu state has been replaced
+    pub fn clear_fault(&mut self) {
+        self.fault = None;
+    }
+
+    /// Copy the keypad state into the cpu
+    pub fn set_input(&mut self, input: &InputState) {
+        self.cpu.kp.release_all();
+        for (idx, &pressed) in input.keys.iter().enumerate() {
+            self.cpu.kp.set(idx, pressed);
+        }
+    }
+
+    /// Runs a single 60Hz frame with the given keypad state.
+    /// Does nothing while the machine is paused by a fault.
+    pub fn run_frame(&mut self, input: &InputState) -> FrameOutput {
+        self.set_input(input);
+
+        let mut events = Vec::new();
+        if self.fault.is_none() {
+            match self.finish_frame() {
+                Ok(StepOutcome::Exited) => events.push(MachineEvent::Exited),
+                Ok(_) => {},
+                Err(err) => {
+                    self.fault = Some(err);
+                    events.push(MachineEvent::Fault(err));
+                },
+            }
+        }
+
+        let mut output = self.output();
+        output.events = events;
+        output
+    }
+
+    /// Runs the rest of the current frame, then ticks the timers
+    fn finish_frame(&mut self) -> Result<StepOutcome, CpuError> {
+        while !self.frame_done() {
+            if self.step()? == StepOutcome::Exited {
+                return Ok(StepOutcome::Exited);
+            }
+        }
+        self.end_frame();
+        Ok(StepOutcome::Executed)
+    }
+
+    /// Executes one instruction, charging its cost to the current frame.
+    ///
+    /// Under fixed timing every instruction costs one. Under VIP timing it
+    /// costs the machine cycles it took, and like on the VIP, DXYN always
+    /// waits for the display interrupt. Waiting idles out the rest of the
+    /// frame under either.
+    pub fn step(&mut self) -> Result<StepOutcome, CpuError> {
+        let instruction = self.cpu.peek(self.cpu.pc).ok().and_then(Instruction::decode);
+        let cost = match self.timing {
+            Timing::Fixed => 1,
+            Timing::Vip => instruction.map_or(0, |instruction| timing::vip_cycles(instruction, &self.cpu)),
+        };
+
+        let outcome = self.cpu.step()?;
+        let drew = matches!(instruction, Some(Instruction::Draw { .. }));
+        self.cpu.frame_cycles = if outcome != StepOutcome::Executed || (self.timing == Timing::Vip && drew) {
+            self.frame_budget()
+        } else {
+            self.cpu.frame_cycles + cost
+        };
+        Ok(outcome)
+    }
+
+    /// Whether the current frame has run its course, and the timers are due
+    pub fn frame_done(&self) -> bool {
+        self.cpu.frame_cycles >= self.frame_budget()
+    }
+
+    /// Ticks the timers as the display interrupt would, starting a new frame.
+    /// Under VIP timing, cycles the last instruction ran past the end of the
+    /// frame are taken out of the next one.
+    pub fn end_frame(&mut self) {
+        self.cpu.frame_cycles = match self.timing {
+            Timing::Fixed => 0,
+            Timing::Vip => self.cpu.frame_cycles.saturating_sub(VIP_INTERPRETER_CYCLES),
+        };
+        self.cpu.tick();
+    }
+
+    /// What a frame can spend: instructions under fixed timing, or the
+    /// machine cycles the display interrupt leaves under VIP timing
+    fn frame_budget(&self) -> u32 {
+        match self.timing {
+            Timing::Fixed => self.cycles_per_frame.try_into().unwrap_or(u32::MAX),
+            Timing::Vip => VIP_INTERPRETER_CYCLES,
+        }
+    }
+
+    /// The current output without running the cpu, e.g. while paused
+    pub fn output(&mut self) -> FrameOutput {
+        let updated = std::mem::take(&mut self.cpu.fb.update);
+        FrameOutput {
+            frame: updated.then(|| self.cpu.fb.clone()),
+            audio: AudioOutput {
+                playing: self.cpu.sound_state(),
+                pattern: self.cpu.pattern,
+                pitch: self.cpu.pitch,
+            },
+            events: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine_with(timing: Timing, program: &[u8]) -> Machine {
+        let mut machine = Machine::new(Quirks::default());
+        machine.timing = timing;
+        machine.load(program).unwrap();
+        machine
+    }
+
+    #[test]
+    fn loading_cancels_a_key_wait() {
+        // V5 = key
+        let mut machine = machine_with(Timing::Fixed, &[0xF5, 0x0A]);
+        machine.run_frame(&InputState::default());
+        assert!(machine.cpu.kp.block);
+
+        // V0 = 1, then loop
+        machine.load(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        let mut input = InputState::default();
+        input.keys[3] = true;
+        machine.run_frame(&input);
+        input.keys[3] = false;
+        machine.run_frame(&input);
+        assert_eq!((machine.cpu.v[0], machine.cpu.v[5], machine.cpu.pc), (1, 0, 0x202));
+    }
+
+    #[test]
+    fn fixed_frames_run_cycles_per_frame_instructions() {
+        // V0 += 1, then loop
+        let mut machine = machine_with(Timing::Fixed, &[0x70, 0x01, 0x12, 0x00]);
+        machine.cycles_per_frame = 7;
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.v[0], machine.cpu.pc), (4, 0x202));
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.v[0], machine.cpu.pc), (7, 0x200));
+    }
+
+    #[test]
+    fn vip_frames_fill_the_interpreter_cycles() {
+        // 50 and 52 cycles, so 18 times round the loop fills the frame exactly
+        let mut machine = machine_with(Timing::Vip, &[0x70, 0x01, 0x12, 0x00]);
+        machine.cpu.dt = 5;
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.v[0], machine.cpu.pc, machine.cpu.dt), (18, 0x200, 4));
+        machine.run_frame(&InputState::default());
+        assert_eq!(machine.cpu.v[0], 36);
+    }
+
+    #[test]
+    fn vip_draws_end_the_frame() {
+        // V0 = 1, then draw, V0 += 1 and loop back to the draw
+        let mut machine = machine_with(Timing::Vip, &[0x60, 0x01, 0xD0, 0x01, 0x70, 0x01, 0x12, 0x02]);
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.v[0], machine.cpu.pc), (1, 0x204));
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.v[0], machine.cpu.pc), (2, 0x204));
+    }
+
+    #[test]
+    fn vip_overrun_carries_into_the_next_frame() {
+        // CLS takes most of two frames, then loop
+        let mut machine = machine_with(Timing::Vip, &[0x00, 0xE0, 0x12, 0x00]);
+        let cls = timing::vip_cycles(Instruction::Clear, &machine.cpu);
+        machine.run_frame(&InputState::default());
+        assert_eq!((machine.cpu.pc, machine.cpu.frame_cycles), (0x202, cls - VIP_INTERPRETER_CYCLES));
+
+        // Save states keep the overrun
+        let state = machine.cpu.save_state(&[0; 20]);
+        let mut restored = machine_with(Timing::Vip, &[0x00, 0xE0, 0x12, 0x00]);
+        restored.cpu.load_state(&state, &[0; 20]).unwrap();
+        for machine in [&mut machine, &mut restored] {
+            machine.run_frame(&InputState::default());
+            assert_eq!(machine.cpu.pc, 0x202);
+            assert_eq!(machine.cpu.frame_cycles, 2 * cls + 52 - 2 * VIP_INTERPRETER_CYCLES);
+        }
+    }
+}
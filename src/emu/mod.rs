@@ -2,3 +2,15 @@ pub mod keypad;
 pub mod cpu;
 pub mod frame;
 pub mod font;
+pub mod quirks;
+pub mod error;
+pub mod instruction;
+pub mod state;
+pub mod rewind;
+pub mod test_suite;
+pub mod machine;
+pub mod tone;
+pub mod rng;
+pub mod movie;
+pub mod trace;
+pub mod timing;
@@ -0,0 +1,516 @@
+//! Input movies: a recording of the keypad over a run, which replays it
+//! exactly given the same rom, quirks, speed and RNG seed.
+//!
+//! Movies are text, so they diff well and can be attached to bug reports:
+//!
+//! ```text
+//! chip8-movie 1
+//! rom 0123456789abcdef0123456789abcdef01234567
+//! frames 240
+//! ipf 10
+//! timing fixed
+//! rng std
+//! seed 200
+//! vf_reset true
+//! shift_vy true
+//! index_increment x+1
+//! jump_vx false
+//! clipping true
+//! display_wait true
+//!
+//! 12 +5
+//! 30 -5 +a
+//! 59 check=4f0e...
+//! 75 ipf=20
+//! ```
+//!
+//! After the header, each line is a frame number followed by what happened
+//! at the start of it: keys pressed (+) or released (-) and speed changes,
+//! or the hash of the framebuffer after it ran, to detect desyncs.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    str::FromStr,
+};
+use crate::emu::{
+    frame::Frame,
+    machine::{
+        FrameOutput,
+        InputState,
+        Machine,
+    },
+    quirks::{
+        IndexIncrement,
+        Quirks,
+    },
+    rng::{
+        Rng,
+        RngModel,
+    },
+    timing::Timing,
+};
+
+/// First line of every movie
+const MAGIC: &str = "chip8-movie";
+/// Bumped whenever the format changes incompatibly
+pub const VERSION: u32 = 1;
+/// How often the recorder writes a framebuffer checkpoint
+pub const CHECKPOINT_FRAMES: u32 = 60;
+
+/// Everything needed to start a run the same way as the recording
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+    /// Hex SHA-1 of the rom
+    pub rom_hash: String,
+    pub quirks: Quirks,
+    /// Instructions per frame at the start of the movie
+    pub cycles_per_frame: usize,
+    pub timing: Timing,
+    pub rng: RngModel,
+    pub seed: u64,
+}
+
+impl MovieHeader {
+    /// Sets up a machine to match the recording. Load the rom afterwards
+    pub fn apply(&self, machine: &mut Machine) {
+        machine.cpu.quirks = self.quirks;
+        machine.cycles_per_frame = self.cycles_per_frame;
+        machine.timing = self.timing;
+        machine.cpu.rng = Rng::new(self.rng, self.seed);
+    }
+}
+
+/// Something that happened during a movie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieEvent {
+    /// A chip-8 key was pressed, before the frame ran
+    Press(u8),
+    /// A chip-8 key was released, before the frame ran
+    Release(u8),
+    /// The instructions per frame changed, before the frame ran
+    Ipf(usize),
+    /// Hash of the framebuffer after the frame ran
+    Check(String),
+}
+
+impl fmt::Display for MovieEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieEvent::Press(key)   => write!(f, "+{:x}", key),
+            MovieEvent::Release(key) => write!(f, "-{:x}", key),
+            MovieEvent::Ipf(ipf)     => write!(f, "ipf={}", ipf),
+            MovieEvent::Check(hash)  => write!(f, "check={}", hash),
+        }
+    }
+}
+
+impl FromStr for MovieEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = |hex: &str| u8::from_str_radix(hex, 16).ok().filter(|&key| key < 16);
+        let event = if let Some(hex) = s.strip_prefix('+') {
+            key(hex).map(MovieEvent::Press)
+        } else if let Some(hex) = s.strip_prefix('-') {
+            key(hex).map(MovieEvent::Release)
+        } else if let Some(ipf) = s.strip_prefix("ipf=") {
+            ipf.parse().ok().map(MovieEvent::Ipf)
+        } else {
+            s.strip_prefix("check=").map(|hash| MovieEvent::Check(hash.to_string()))
+        };
+        event.ok_or_else(|| format!("unknown event '{}'", s))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    /// Number of frames recorded
+    pub frames: u32,
+    /// Events by frame number, in order
+    pub events: Vec<(u32, MovieEvent)>,
+}
+
+impl Movie {
+    pub fn new(header: MovieHeader) -> Self {
+        Movie { header, frames: 0, events: Vec::new() }
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        let quirks = &header.quirks;
+        writeln!(f, "{} {}", MAGIC, VERSION)?;
+        writeln!(f, "rom {}", header.rom_hash)?;
+        writeln!(f, "frames {}", self.frames)?;
+        writeln!(f, "ipf {}", header.cycles_per_frame)?;
+        writeln!(f, "timing {}", header.timing)?;
+        writeln!(f, "rng {}", header.rng)?;
+        writeln!(f, "seed {}", header.seed)?;
+        writeln!(f, "vf_reset {}", quirks.vf_reset)?;
+        writeln!(f, "shift_vy {}", quirks.shift_vy)?;
+        writeln!(f, "index_increment {}", index_increment_name(quirks.index_increment))?;
+        writeln!(f, "jump_vx {}", quirks.jump_vx)?;
+        writeln!(f, "clipping {}", quirks.clipping)?;
+        writeln!(f, "display_wait {}", quirks.display_wait)?;
+
+        let mut last = None;
+        for (frame, event) in &self.events {
+            if last == Some(frame) {
+                write!(f, " {}", event)?;
+            } else {
+                write!(f, "\n{} {}", frame, event)?;
+                last = Some(frame);
+            }
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for Movie {
+    type Err = String;
+
+    /// Parses a movie, with errors given as "line N: message" where possible
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate()
+            .map(|(idx, line)| (idx + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        match lines.next() {
+            Some((_, line)) if line == format!("{} {}", MAGIC, VERSION) => {},
+            Some((num, line)) if line.starts_with(MAGIC) => {
+                return Err(format!("line {}: unsupported movie version in '{}'", num, line));
+            },
+            _ => return Err(String::from("not a chip8 movie")),
+        }
+
+        let mut fields = HashMap::new();
+        let mut events = Vec::new();
+        for (num, line) in lines {
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap_or_default();
+            match first.parse::<u32>() {
+                Ok(frame) => {
+                    if events.last().is_some_and(|&(last, _)| frame < last) {
+                        return Err(format!("line {}: frame {} is out of order", num, frame));
+                    }
+                    for word in words {
+                        events.push((frame, word.parse().map_err(|err| format!("line {}: {}", num, err))?));
+                    }
+                },
+                Err(_) => {
+                    let value = words.next().ok_or_else(|| format!("line {}: missing value for '{}'", num, first))?;
+                    fields.insert(first, (num, value));
+                },
+            }
+        }
+
+        let field = |name: &str| fields.get(name).copied().ok_or_else(|| format!("missing '{}' in header", name));
+
+        let (num, index_increment) = field("index_increment")?;
+        let header = MovieHeader {
+            rom_hash: field("rom")?.1.to_ascii_lowercase(),
+            quirks: Quirks {
+                vf_reset: parse(field("vf_reset")?)?,
+                shift_vy: parse(field("shift_vy")?)?,
+                index_increment: parse_index_increment(index_increment)
+                    .ok_or_else(|| format!("line {}: invalid value '{}'", num, index_increment))?,
+                jump_vx: parse(field("jump_vx")?)?,
+                clipping: parse(field("clipping")?)?,
+                display_wait: parse(field("display_wait")?)?,
+            },
+            cycles_per_frame: parse(field("ipf")?)?,
+            // Movies from before VIP timing don't say
+            timing: match fields.get("timing") {
+                Some(&(num, timing)) => timing.parse().map_err(|err| format!("line {}: {}", num, err))?,
+                None => Timing::Fixed,
+            },
+            rng: field("rng").and_then(|(num, rng)| rng.parse().map_err(|err| format!("line {}: {}", num, err)))?,
+            seed: parse(field("seed")?)?,
+        };
+        Ok(Movie { header, frames: parse(field("frames")?)?, events })
+    }
+}
+
+/// Parses a header value, given with its line number
+fn parse<T: FromStr>((num, value): (usize, &str)) -> Result<T, String> {
+    value.parse().map_err(|_| format!("line {}: invalid value '{}'", num, value))
+}
+
+fn index_increment_name(increment: IndexIncrement) -> &'static str {
+    match increment {
+        IndexIncrement::XPlusOne  => "x+1",
+        IndexIncrement::X         => "x",
+        IndexIncrement::Unchanged => "unchanged",
+    }
+}
+
+fn parse_index_increment(name: &str) -> Option<IndexIncrement> {
+    match name {
+        "x+1"       => Some(IndexIncrement::XPlusOne),
+        "x"         => Some(IndexIncrement::X),
+        "unchanged" => Some(IndexIncrement::Unchanged),
+        _ => None,
+    }
+}
+
+/// Hex SHA-1 of the framebuffer's size and pixels
+pub fn frame_hash(frame: &Frame) -> String {
+    let mut hasher = sha1_smol::Sha1::new();
+    hasher.update(&(frame.width as u16).to_le_bytes());
+    hasher.update(&(frame.height as u16).to_le_bytes());
+    hasher.update(&frame.data);
+    hasher.digest().to_string()
+}
+
+/// Records a movie as frames are run through it
+pub struct Recorder {
+    movie: Movie,
+    keys: InputState,
+    cycles_per_frame: usize,
+}
+
+impl Recorder {
+    pub fn new(header: MovieHeader) -> Self {
+        let cycles_per_frame = header.cycles_per_frame;
+        Recorder { movie: Movie::new(header), keys: InputState::default(), cycles_per_frame }
+    }
+
+    /// Runs a frame, recording its input and any change of speed
+    pub fn run_frame(&mut self, machine: &mut Machine, input: &InputState) -> FrameOutput {
+        let frame = self.movie.frames;
+        for (key, (&was, &now)) in self.keys.keys.iter().zip(&input.keys).enumerate() {
+            match (was, now) {
+                (false, true) => self.movie.events.push((frame, MovieEvent::Press(key as u8))),
+                (true, false) => self.movie.events.push((frame, MovieEvent::Release(key as u8))),
+                _ => {},
+            }
+        }
+        self.keys = *input;
+        if machine.cycles_per_frame != self.cycles_per_frame {
+            self.cycles_per_frame = machine.cycles_per_frame;
+            self.movie.events.push((frame, MovieEvent::Ipf(self.cycles_per_frame)));
+        }
+
+        let output = machine.run_frame(input);
+        self.movie.frames += 1;
+        if self.movie.frames.is_multiple_of(CHECKPOINT_FRAMES) {
+            self.movie.events.push((frame, MovieEvent::Check(frame_hash(&machine.cpu.fb))));
+        }
+        output
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+/// A checkpoint didn't match during playback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u32,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "movie desynced at frame {}: expected frame hash {}, found {}",
+            self.frame, self.expected, self.found)
+    }
+}
+
+impl Error for Desync {}
+
+/// Feeds a movie's input into a machine, frame by frame
+pub struct Player {
+    movie: Movie,
+    /// Index of the next event to apply
+    next: usize,
+    frame: u32,
+    keys: InputState,
+    /// Whether checkpoints are compared against the framebuffer
+    verify: bool,
+}
+
+impl Player {
+    pub fn new(movie: Movie, verify: bool) -> Self {
+        Player { movie, next: 0, frame: 0, keys: InputState::default(), verify }
+    }
+
+    pub fn header(&self) -> &MovieHeader {
+        &self.movie.header
+    }
+
+    /// The number of the next frame to play
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Length of the movie, in frames
+    pub fn frames(&self) -> u32 {
+        self.movie.frames
+    }
+
+    /// Whether every recorded frame has been played
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    /// Runs the next frame with the recorded input. When verifying, fails if
+    /// the framebuffer doesn't match a checkpoint taken on this frame.
+    pub fn run_frame(&mut self, machine: &mut Machine) -> Result<FrameOutput, Desync> {
+        let frame = self.frame;
+        let mut checks = Vec::new();
+        while let Some((_, event)) = self.movie.events.get(self.next).filter(|(at, _)| *at == frame) {
+            match event {
+                MovieEvent::Press(key)   => self.keys.keys[*key as usize] = true,
+                MovieEvent::Release(key) => self.keys.keys[*key as usize] = false,
+                MovieEvent::Ipf(ipf)     => machine.cycles_per_frame = *ipf,
+                MovieEvent::Check(hash)  => checks.push(hash),
+            }
+            self.next += 1;
+        }
+
+        let output = machine.run_frame(&self.keys);
+        self.frame += 1;
+
+        if self.verify {
+            let found = frame_hash(&machine.cpu.fb);
+            if let Some(expected) = checks.into_iter().find(|&hash| *hash != found) {
+                return Err(Desync { frame, expected: expected.clone(), found });
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::quirks::Platform;
+
+    /// Draws a random digit whenever key 0 is held
+    const ROM: [u8; 14] = [
+        0x00, 0xE0, // CLS
+        0xE0, 0x9E, // SKP V0
+        0x12, 0x0A, // JP 20A
+        0xC1, 0x0F, // RND V1, 0xF
+        0xF1, 0x29, // LD F, V1
+        0xD2, 0x25, // DRW V2, V2, 5
+        0x12, 0x00, // JP 200
+    ];
+
+    fn header() -> MovieHeader {
+        MovieHeader {
+            rom_hash: String::from("0123456789abcdef0123456789abcdef01234567"),
+            quirks: Platform::CosmacVip.quirks(),
+            cycles_per_frame: 15,
+            timing: Timing::Fixed,
+            rng: RngModel::Lfsr,
+            seed: 200,
+        }
+    }
+
+    fn loaded(header: &MovieHeader) -> Machine {
+        let mut machine = Machine::new(Quirks::default());
+        header.apply(&mut machine);
+        machine.load(&ROM).unwrap();
+        machine
+    }
+
+    /// Replaces a movie's `name` header line, or removes it given None
+    fn with_field(movie: &str, name: &str, line: Option<&str>) -> String {
+        movie.lines()
+            .filter_map(|l| if l.split_whitespace().next() == Some(name) { line } else { Some(l) })
+            .map(|l| format!("{}\n", l))
+            .collect()
+    }
+
+    #[test]
+    fn display_and_parse_roundtrip() {
+        let mut movie = Movie::new(header());
+        movie.header.quirks.index_increment = IndexIncrement::X;
+        movie.frames = 120;
+        movie.events = vec![
+            (12, MovieEvent::Press(5)),
+            (30, MovieEvent::Release(5)),
+            (30, MovieEvent::Press(0xa)),
+            (59, MovieEvent::Check(String::from("4f0e"))),
+            (75, MovieEvent::Ipf(20)),
+        ];
+        let text = movie.to_string();
+        assert!(text.contains("\n30 -5 +a\n"));
+        assert_eq!(text.parse::<Movie>(), Ok(movie));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let text = Movie::new(header()).to_string();
+        assert_eq!(format!("{}10 +1\n5 -1\n", text).parse::<Movie>(), Err(String::from("line 16: frame 5 is out of order")));
+        assert_eq!(text.replacen("chip8-movie 1", "chip8-movie 9", 1).parse::<Movie>(),
+            Err(String::from("line 1: unsupported movie version in 'chip8-movie 9'")));
+        assert_eq!("hello".parse::<Movie>(), Err(String::from("not a chip8 movie")));
+        assert_eq!(with_field(&text, "seed", None).parse::<Movie>(), Err(String::from("missing 'seed' in header")));
+        assert_eq!(with_field(&text, "ipf", Some("ipf ten")).parse::<Movie>(), Err(String::from("line 4: invalid value 'ten'")));
+        assert_eq!(format!("{}3 +g\n", text).parse::<Movie>(), Err(String::from("line 15: unknown event '+g'")));
+    }
+
+    #[test]
+    fn movies_without_timing_run_fixed() {
+        let text = with_field(&Movie::new(header()).to_string(), "timing", None);
+        assert_eq!(text.parse::<Movie>().unwrap().header.timing, Timing::Fixed);
+    }
+
+    #[test]
+    fn playback_matches_the_recording() {
+        let header = header();
+        let mut machine = loaded(&header);
+        let mut recorder = Recorder::new(header.clone());
+        let mut input = InputState::default();
+        let mut frames = Vec::new();
+        for frame in 0..CHECKPOINT_FRAMES * 2 {
+            input.keys[0] = frame % 20 >= 10;
+            if frame == 70 {
+                machine.cycles_per_frame = 30;
+            }
+            recorder.run_frame(&mut machine, &input);
+            frames.push(frame_hash(&machine.cpu.fb));
+        }
+        let movie = recorder.movie().clone();
+        let checks = movie.events.iter().filter(|(_, event)| matches!(event, MovieEvent::Check(_))).count();
+        assert_eq!(checks, 2);
+        assert!(movie.events.contains(&(70, MovieEvent::Ipf(30))));
+
+        let movie: Movie = movie.to_string().parse().unwrap();
+        let mut machine = loaded(&movie.header);
+        let mut player = Player::new(movie, true);
+        for expected in &frames {
+            player.run_frame(&mut machine).unwrap();
+            assert_eq!(&frame_hash(&machine.cpu.fb), expected);
+        }
+        assert!(player.finished());
+    }
+
+    #[test]
+    fn playback_reports_desyncs() {
+        let header = header();
+        let mut machine = loaded(&header);
+        let mut recorder = Recorder::new(header.clone());
+        let input = InputState { keys: [true; 16] };
+        for _ in 0..CHECKPOINT_FRAMES {
+            recorder.run_frame(&mut machine, &input);
+        }
+
+        // A different seed draws different digits
+        let mut movie = recorder.movie().clone();
+        movie.header.seed += 1;
+        let mut machine = loaded(&movie.header);
+        let mut player = Player::new(movie, true);
+        let result = (0..CHECKPOINT_FRAMES).try_for_each(|_| player.run_frame(&mut machine).map(|_| ()));
+        assert_eq!(result.unwrap_err().frame, CHECKPOINT_FRAMES - 1);
+    }
+}
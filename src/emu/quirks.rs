@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+/// How FX55/FX65 leave the address register after a bulk load/store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexIncrement {
+    /// I is set to I + X + 1 (COSMAC VIP, XO-CHIP)
+    XPlusOne,
+    /// I is set to I + X (CHIP-48 and SUPER-CHIP 1.0)
+    X,
+    /// I is left unmodified (SUPER-CHIP 1.1)
+    Unchanged,
+}
+
+/// The set of behaviours that differ between CHIP-8 interpreters.
+///
+/// Each ambiguous opcode consults one of these flags instead of
+/// hardcoding a single interpretation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// 8XY6 and 8XYE shift VY into VX, rather than shifting VX in place
+    pub shift_vy: bool,
+    /// Effect of FX55 and FX65 on I
+    pub index_increment: IndexIncrement,
+    /// BNNN jumps to XNN + VX, rather than NNN + V0
+    pub jump_vx: bool,
+    /// Sprites are clipped at the screen edge, rather than wrapped around
+    pub clipping: bool,
+    /// DXYN waits for the next timer tick before the CPU continues
+    pub display_wait: bool,
+}
+
+/// Named quirk presets for the common CHIP-8 interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Platform {
+    #[default]
+    CosmacVip,
+    /// CHIP-48, and SUPER-CHIP 1.0 which shares its quirks
+    Chip48,
+    Schip11,
+    XoChip,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const fn cosmac_vip() -> Self {
+        Quirks {
+            vf_reset: true,
+            shift_vy: true,
+            index_increment: IndexIncrement::XPlusOne,
+            jump_vx: false,
+            clipping: true,
+            display_wait: true,
+        }
+    }
+
+    /// CHIP-48 for the HP-48 calculators, also used by SUPER-CHIP 1.0
+    pub const fn chip48() -> Self {
+        Quirks {
+            vf_reset: false,
+            shift_vy: false,
+            index_increment: IndexIncrement::X,
+            jump_vx: true,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    /// SUPER-CHIP 1.1
+    pub const fn schip11() -> Self {
+        Quirks {
+            vf_reset: false,
+            shift_vy: false,
+            index_increment: IndexIncrement::Unchanged,
+            jump_vx: true,
+            clipping: true,
+            display_wait: false,
+        }
+    }
+
+    /// XO-CHIP, as implemented by Octo
+    pub const fn xochip() -> Self {
+        Quirks {
+            vf_reset: false,
+            shift_vy: true,
+            index_increment: IndexIncrement::XPlusOne,
+            jump_vx: false,
+            clipping: false,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Platform::default().quirks()
+    }
+}
+
+impl Platform {
+    pub const ALL: [Platform; 4] = [
+        Platform::CosmacVip,
+        Platform::Chip48,
+        Platform::Schip11,
+        Platform::XoChip,
+    ];
+
+    /// Get the quirk preset for this platform
+    pub const fn quirks(self) -> Quirks {
+        match self {
+            Platform::CosmacVip => Quirks::cosmac_vip(),
+            Platform::Chip48    => Quirks::chip48(),
+            Platform::Schip11   => Quirks::schip11(),
+            Platform::XoChip    => Quirks::xochip(),
+        }
+    }
+
+    /// The name used to select this platform from the command line
+    pub const fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "vip",
+            Platform::Chip48    => "chip48",
+            Platform::Schip11   => "schip11",
+            Platform::XoChip    => "xochip",
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "cosmac-vip" | "chip8" | "chip-8" => Ok(Platform::CosmacVip),
+            "chip48" | "chip-48" | "schip10" | "schip-1.0"        => Ok(Platform::Chip48),
+            "schip" | "schip11" | "schip-1.1" | "superchip"       => Ok(Platform::Schip11),
+            "xochip" | "xo-chip" | "octo"                         => Ok(Platform::XoChip),
+            _ => {
+                let names: Vec<&str> = Platform::ALL.iter().map(|p| p.name()).collect();
+                Err(format!("unknown quirk profile '{}' (expected one of: {})", s, names.join(", ")))
+            }
+        }
+    }
+}
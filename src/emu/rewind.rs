@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+use crate::emu::{
+    cpu::CPU,
+    state::HASH_LEN,
+};
+
+/// Rewind snapshots never leave the process, so they aren't tied to a rom
+const NO_ROM: [u8; HASH_LEN] = [0; HASH_LEN];
+
+/// How to get from a snapshot back to the one taken before it
+enum Delta {
+    /// Run-length encoded XOR against the newer snapshot
+    Xor(Vec<u8>),
+    /// The older snapshot in full, used when the sizes differ
+    /// (e.g. after switching resolution)
+    Full(Vec<u8>),
+}
+
+/// A ring buffer of per-frame machine snapshots that can be played backwards.
+///
+/// Only the newest snapshot is kept in full. Every older frame is stored as
+/// a delta against the frame after it, which is tiny since most of memory
+/// doesn't change from one frame to the next.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+    capacity: usize,
+}
+
+impl Rewind {
+    /// Creates a buffer holding up to `capacity` frames of history
+    pub fn new(capacity: usize) -> Self {
+        Rewind { latest: None, deltas: VecDeque::new(), capacity }
+    }
+
+    /// Number of frames that can currently be rewound
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Drop all history, e.g. after loading a new rom
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Record the state of the cpu at the end of a frame
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let snapshot = cpu.save_state(&NO_ROM);
+        if let Some(prev) = self.latest.take() {
+            let delta = if prev.len() == snapshot.len() {
+                Delta::Xor(encode(&prev, &snapshot))
+            } else {
+                Delta::Full(prev)
+            };
+            self.deltas.push_back(delta);
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Step the cpu back by one frame. Returns false once history runs out.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> bool {
+        let (Some(latest), Some(delta)) = (self.latest.as_mut(), self.deltas.pop_back()) else {
+            return false;
+        };
+
+        match delta {
+            Delta::Xor(delta) => decode(&delta, latest),
+            Delta::Full(prev) => *latest = prev,
+        }
+
+        if let Err(err) = cpu.load_state(latest, &NO_ROM) {
+            log::error!("failed to restore rewind snapshot: {}", err);
+            self.clear();
+            return false;
+        }
+        true
+    }
+}
+
+/// Encodes `a ^ b` as runs of `[zero run][literal length][literal bytes]`,
+/// with both lengths written as LEB128 varints
+fn encode(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut idx = 0;
+    while idx < a.len() {
+        let zeros = a[idx..].iter().zip(&b[idx..])
+            .take_while(|(x, y)| x == y)
+            .count();
+        idx += zeros;
+        let literal = a[idx..].iter().zip(&b[idx..])
+            .take_while(|(x, y)| x != y)
+            .count();
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literal);
+        out.extend(a[idx..idx + literal].iter().zip(&b[idx..idx + literal]).map(|(x, y)| x ^ y));
+        idx += literal;
+    }
+    out
+}
+
+/// Applies a delta produced by encode to `data` in place
+fn decode(delta: &[u8], data: &mut [u8]) {
+    let mut delta = delta.iter().copied();
+    let mut idx = 0;
+    while let Some(zeros) = read_varint(&mut delta) {
+        idx += zeros;
+        let literal = read_varint(&mut delta).unwrap_or(0);
+        for (byte, x) in data[idx..idx + literal].iter_mut().zip(delta.by_ref()) {
+            *byte ^= x;
+        }
+        idx += literal;
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints_roundtrip() {
+        let values = [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x12345678];
+        let mut out = Vec::new();
+        for &value in &values {
+            write_varint(&mut out, value);
+        }
+        assert_eq!(out[..4], [0x00, 0x01, 0x7F, 0x80]);
+        let mut bytes = out.into_iter();
+        for &value in &values {
+            assert_eq!(read_varint(&mut bytes), Some(value));
+        }
+        assert_eq!(read_varint(&mut bytes), None);
+    }
+
+    #[test]
+    fn deltas_roundtrip() {
+        let old: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let mut new = old.clone();
+        new[0] ^= 1;
+        new[500..700].fill(0xAA);
+        new[999] = 7;
+
+        let delta = encode(&old, &new);
+        assert!(delta.len() < 250);
+        let mut data = new.clone();
+        decode(&delta, &mut data);
+        assert_eq!(data, old);
+
+        // One zero run with an empty literal
+        assert_eq!(encode(&old, &old), [0xE8, 0x07, 0x00]);
+    }
+
+    #[test]
+    fn rewinding_restores_earlier_frames() {
+        let mut rewind = Rewind::new(10);
+        let mut cpu = CPU::initialize();
+        for frame in 0..4 {
+            cpu.v[0] = frame;
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), 3);
+        for frame in (0..3).rev() {
+            assert!(rewind.rewind(&mut cpu));
+            assert_eq!(cpu.v[0], frame);
+        }
+        assert!(!rewind.rewind(&mut cpu));
+        assert_eq!(cpu.v[0], 0);
+    }
+
+    #[test]
+    fn resolution_changes_store_full_snapshots() {
+        let mut rewind = Rewind::new(10);
+        let mut cpu = CPU::initialize();
+        rewind.record(&cpu);
+        cpu.fb.set_hires(true);
+        rewind.record(&cpu);
+        cpu.v[0] = 1;
+        rewind.record(&cpu);
+        assert!(matches!(rewind.deltas[0], Delta::Full(_)));
+        assert!(matches!(rewind.deltas[1], Delta::Xor(_)));
+
+        assert!(rewind.rewind(&mut cpu));
+        assert_eq!((cpu.v[0], cpu.fb.hires()), (0, true));
+        assert!(rewind.rewind(&mut cpu));
+        assert!(!cpu.fb.hires());
+    }
+
+    #[test]
+    fn history_is_capped_at_the_capacity() {
+        let mut rewind = Rewind::new(2);
+        let mut cpu = CPU::initialize();
+        for frame in 0..5 {
+            cpu.v[0] = frame;
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.rewind(&mut cpu) && rewind.rewind(&mut cpu));
+        assert_eq!(cpu.v[0], 2);
+        assert!(!rewind.rewind(&mut cpu));
+
+        let mut disabled = Rewind::new(0);
+        disabled.record(&cpu);
+        disabled.record(&cpu);
+        assert!(disabled.is_empty() && !disabled.rewind(&mut cpu));
+    }
+}
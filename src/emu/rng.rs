@@ -0,0 +1,147 @@
+use std::{
+    fmt,
+    str::FromStr,
+};
+use rand::{
+    Rng as _,
+    SeedableRng,
+};
+use rand_chacha::ChaCha12Rng;
+
+/// RNG seed used unless another is given, so runs are reproducible by default
+pub const DEFAULT_SEED: u64 = 0xC8;
+
+/// Feedback taps for the LFSR model: x^16 + x^14 + x^13 + x^11 + 1
+const LFSR_TAPS: u16 = 0xB400;
+/// Used in place of a zero LFSR seed, which would only ever produce zeros
+const LFSR_FALLBACK: u16 = 0xACE1;
+
+/// The algorithms CXNN can draw random numbers from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngModel {
+    /// ChaCha12, the algorithm behind rand's StdRng
+    #[default]
+    Std,
+    /// Modelled on the COSMAC VIP interpreter's routine
+    Vip,
+    /// A 16-bit linear feedback shift register
+    Lfsr,
+}
+
+impl RngModel {
+    /// Identifies the model in save states
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            RngModel::Std  => 0,
+            RngModel::Vip  => 1,
+            RngModel::Lfsr => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(RngModel::Std),
+            1 => Some(RngModel::Vip),
+            2 => Some(RngModel::Lfsr),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for RngModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "std"  => Ok(RngModel::Std),
+            "vip"  => Ok(RngModel::Vip),
+            "lfsr" => Ok(RngModel::Lfsr),
+            _ => Err(format!("unknown rng '{}', expected std, vip or lfsr", s)),
+        }
+    }
+}
+
+impl fmt::Display for RngModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RngModel::Std  => "std",
+            RngModel::Vip  => "vip",
+            RngModel::Lfsr => "lfsr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The random number generator behind CXNN.
+///
+/// All of its state can be saved and restored, so a run replays exactly
+/// given the same seed and input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rng {
+    Std(Box<ChaCha12Rng>),
+    /// The VIP interpreter added a byte of its own code, picked by a
+    /// counter, to the last result. The counter moves on with each call and
+    /// each 60Hz interrupt, so results depend on timing as well as the seed.
+    /// Low memory holds the fonts here rather than the interpreter, so the
+    /// values differ from real hardware while following the same pattern.
+    Vip { counter: u8, last: u8 },
+    Lfsr(u16),
+}
+
+impl Rng {
+    pub fn new(model: RngModel, seed: u64) -> Self {
+        match model {
+            RngModel::Std => Rng::Std(Box::new(ChaCha12Rng::seed_from_u64(seed))),
+            RngModel::Vip => Rng::Vip { counter: seed as u8, last: (seed >> 8) as u8 },
+            RngModel::Lfsr => match seed as u16 {
+                0 => Rng::Lfsr(LFSR_FALLBACK),
+                state => Rng::Lfsr(state),
+            },
+        }
+    }
+
+    pub fn model(&self) -> RngModel {
+        match self {
+            Rng::Std(_)     => RngModel::Std,
+            Rng::Vip { .. } => RngModel::Vip,
+            Rng::Lfsr(_)    => RngModel::Lfsr,
+        }
+    }
+
+    /// Produces the next random byte. Only the VIP model reads memory
+    pub(crate) fn next(&mut self, mem: &[u8]) -> u8 {
+        match self {
+            Rng::Std(rng) => rng.gen(),
+            Rng::Vip { counter, last } => {
+                *counter = counter.wrapping_add(1);
+                *last = last.rotate_right(1).wrapping_add(mem[*counter as usize]);
+                *last
+            },
+            Rng::Lfsr(state) => {
+                let mut byte = 0;
+                for _ in 0..8 {
+                    let bit = *state & 1;
+                    *state >>= 1;
+                    if bit != 0 {
+                        *state ^= LFSR_TAPS;
+                    }
+                    byte = byte << 1 | bit as u8;
+                }
+                byte
+            },
+        }
+    }
+
+    /// Called on each 60Hz timer tick
+    pub(crate) fn tick(&mut self) {
+        if let Rng::Vip { counter, .. } = self {
+            *counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(RngModel::default(), DEFAULT_SEED)
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    error::Error,
+    fmt,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use crate::emu::{
+    cpu::{
+        CPU,
+        MEM_SIZE,
+    },
+    frame::{
+        HIRES,
+        LORES,
+        PLANES,
+    },
+    rng::{
+        Rng,
+        RngModel,
+    },
+};
+
+/// Identifies a save state file
+const MAGIC: &[u8; 4] = b"C8ST";
+/// Bumped whenever the layout below changes. Older states are rejected.
+pub const VERSION: u16 = 4;
+/// Length of a rom's SHA-1 digest
+pub const HASH_LEN: usize = 20;
+/// Stands for no key in the key wait
+const NO_KEY: u8 = 0xFF;
+
+/// Reasons a save state can't be restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    /// The data isn't a save state at all
+    BadMagic,
+    /// The state was written by an incompatible version of the emulator
+    UnsupportedVersion(u16),
+    /// The state was taken while running a different rom
+    RomMismatch,
+    /// The data ended early
+    Truncated,
+    /// A field holds a value the emulator can never be in
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "unsupported save state version {} (expected {})", version, VERSION),
+            StateError::RomMismatch => write!(f, "save state belongs to a different rom"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Corrupt(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+impl CPU {
+    /// Serializes the full machine state.
+    ///
+    /// The state is tagged with the hash of the running rom, so it can only
+    /// be restored on top of the same program. Quirks aren't included, as
+    /// they come from the configuration rather than the program. The RNG is,
+    /// so random numbers carry on from the same point after a load.
+    pub fn save_state(&self, rom_hash: &[u8; HASH_LEN]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(MEM_SIZE + 0x2000);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(rom_hash);
+
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.i.to_le_bytes());
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.push(self.sp);
+        for addr in self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.dt);
+        out.push(self.st);
+        out.extend_from_slice(&self.rpl);
+        out.push(self.exit as u8);
+        out.push(self.plane);
+        out.push(self.pattern.is_some() as u8);
+        out.extend_from_slice(&self.pattern.unwrap_or_default());
+        out.push(self.pitch);
+        out.push(self.vblank_wait as u8);
+        out.extend_from_slice(&self.frame_cycles.to_le_bytes());
+        write_rng(&mut out, &self.rng);
+
+        out.extend(self.kp.state.iter().map(|&key| key as u8));
+        out.push(self.kp.block as u8);
+        out.push(self.kp.block_reg as u8);
+        out.push(self.kp.block_key.map_or(NO_KEY, |key| key as u8));
+
+        out.extend_from_slice(&(self.fb.width as u16).to_le_bytes());
+        out.extend_from_slice(&(self.fb.height as u16).to_le_bytes());
+        out.extend_from_slice(&self.fb.data);
+
+        out.extend_from_slice(&self.mem);
+        out
+    }
+
+    /// Restores a state written by save_state.
+    ///
+    /// The whole state is validated before anything is written, so on error
+    /// the cpu is left untouched.
+    pub fn load_state(&mut self, data: &[u8], rom_hash: &[u8; HASH_LEN]) -> Result<(), StateError> {
+        let mut r = Reader { data };
+        if r.bytes(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if r.bytes(HASH_LEN)? != rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        let v = r.array::<16>()?;
+        let i = r.u16()?;
+        let pc = r.u16()?;
+        let sp = r.u8()?;
+        if sp as usize > self.stack.len() {
+            return Err(StateError::Corrupt("stack pointer"));
+        }
+        let mut stack = [0u16; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        let dt = r.u8()?;
+        let st = r.u8()?;
+        let rpl = r.array::<16>()?;
+        let exit = r.bool()?;
+        let plane = r.u8()?;
+        if plane >= 1 << PLANES {
+            return Err(StateError::Corrupt("plane mask"));
+        }
+        let has_pattern = r.bool()?;
+        let pattern = r.array::<16>()?;
+        let pitch = r.u8()?;
+        let vblank_wait = r.bool()?;
+        let frame_cycles = r.u32()?;
+        let rng = r.rng()?;
+
+        let mut keys = [false; 16];
+        for key in keys.iter_mut() {
+            *key = r.bool()?;
+        }
+        let block = r.bool()?;
+        let block_reg = r.u8()? as usize;
+        if block_reg >= self.v.len() {
+            return Err(StateError::Corrupt("key wait register"));
+        }
+        let block_key = match r.u8()? {
+            NO_KEY => None,
+            key if (key as usize) < keys.len() => Some(key as usize),
+            _ => return Err(StateError::Corrupt("key wait key")),
+        };
+
+        let width = r.u16()? as usize;
+        let height = r.u16()? as usize;
+        if (width, height) != (LORES.x, LORES.y) && (width, height) != (HIRES.x, HIRES.y) {
+            return Err(StateError::Corrupt("resolution"));
+        }
+        let pixels = r.bytes(width * height)?;
+        if pixels.iter().any(|&pixel| pixel >= 1 << PLANES) {
+            return Err(StateError::Corrupt("frame"));
+        }
+        let pixels = pixels.to_vec();
+        let mem = r.bytes(MEM_SIZE)?;
+        if !r.data.is_empty() {
+            return Err(StateError::Corrupt("length"));
+        }
+
+        self.v = v;
+        self.i = i;
+        self.pc = pc;
+        self.sp = sp;
+        self.stack = stack;
+        self.dt = dt;
+        self.st = st;
+        self.rpl = rpl;
+        self.exit = exit;
+        self.plane = plane;
+        self.pattern = has_pattern.then_some(pattern);
+        self.pitch = pitch;
+        self.vblank_wait = vblank_wait;
+        self.frame_cycles = frame_cycles;
+        self.rng = rng;
+        self.kp.state = keys;
+        self.kp.block = block;
+        self.kp.block_reg = block_reg;
+        self.kp.block_key = block_key;
+        self.fb.width = width;
+        self.fb.height = height;
+        self.fb.data = pixels;
+        self.fb.update = true;
+        self.mem.copy_from_slice(mem);
+        Ok(())
+    }
+}
+
+/// Writes the RNG's model, then its state
+fn write_rng(out: &mut Vec<u8>, rng: &Rng) {
+    out.push(rng.model().id());
+    match rng {
+        Rng::Std(rng) => {
+            out.extend_from_slice(&rng.get_seed());
+            out.extend_from_slice(&rng.get_word_pos().to_le_bytes());
+        },
+        Rng::Vip { counter, last } => {
+            out.push(*counter);
+            out.push(*last);
+        },
+        Rng::Lfsr(state) => out.extend_from_slice(&state.to_le_bytes()),
+    }
+}
+
+/// Reads fields from the front of a save state
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.bytes(N)?);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn rng(&mut self) -> Result<Rng, StateError> {
+        let model = RngModel::from_id(self.u8()?).ok_or(StateError::Corrupt("rng model"))?;
+        Ok(match model {
+            RngModel::Std => {
+                let mut rng = ChaCha12Rng::from_seed(self.array()?);
+                rng.set_word_pos(u128::from_le_bytes(self.array()?));
+                Rng::Std(Box::new(rng))
+            },
+            RngModel::Vip => Rng::Vip { counter: self.u8()?, last: self.u8()? },
+            RngModel::Lfsr => match self.u16()? {
+                0 => return Err(StateError::Corrupt("rng state")),
+                state => Rng::Lfsr(state),
+            },
+        })
+    }
+
+    fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Corrupt("flag")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emu::rng::RngModel;
+
+    #[test]
+    fn save_and_load_roundtrip() {
+        let hash = [7; HASH_LEN];
+        let mut cpu = CPU::initialize();
+        cpu.v[3] = 0x42;
+        cpu.i = 0x345;
+        cpu.pc = 0x210;
+        cpu.stack[0] = 0x204;
+        cpu.sp = 1;
+        cpu.dt = 30;
+        cpu.plane = 3;
+        cpu.pattern = Some([0xAA; 16]);
+        cpu.rng = Rng::new(RngModel::Lfsr, 99);
+        cpu.mem[0x300] = 0x5A;
+        cpu.fb.toggle(1, 2, 1, true);
+        let state = cpu.save_state(&hash);
+
+        let mut restored = CPU::initialize();
+        restored.load_state(&state, &hash).unwrap();
+        assert_eq!(restored.save_state(&hash), state);
+        assert_eq!((restored.v[3], restored.i, restored.pc, restored.sp), (0x42, 0x345, 0x210, 1));
+        assert_eq!(restored.rng, cpu.rng);
+        assert_eq!(restored.fb.data, cpu.fb.data);
+    }
+
+    #[test]
+    fn load_rejects_other_roms_and_bad_data() {
+        let cpu = CPU::initialize();
+        let state = cpu.save_state(&[1; HASH_LEN]);
+        let mut target = CPU::initialize();
+        assert_eq!(target.load_state(&state, &[2; HASH_LEN]), Err(StateError::RomMismatch));
+        assert_eq!(target.load_state(&state[..state.len() - 1], &[1; HASH_LEN]), Err(StateError::Truncated));
+        assert_eq!(target.load_state(b"nope", &[1; HASH_LEN]), Err(StateError::BadMagic));
+    }
+}
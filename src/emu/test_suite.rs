@@ -0,0 +1,56 @@
+use crate::emu::cpu::CPU;
+
+/// Address the test suite reads the selected test from
+pub const TEST_SELECT: usize = 0x1FF;
+/// Address the test suite reads the test's option (platform or keypad mode) from
+pub const TEST_OPTION: usize = 0x1FE;
+
+/// Platform targeted by the quirks test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirkTarget {
+    Chip8 = 1,
+    SuperChip = 2,
+    XoChip = 3,
+}
+
+/// Opcode exercised by the keypad test
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeypadTest {
+    /// ex9e with the key held down
+    Down = 1,
+    /// ex9e with the key released
+    Up = 2,
+    /// fx0a
+    GetKey = 3,
+}
+
+/// A test case from Timendus' chip8-test-suite.ch8.
+///
+/// The suite normally shows a menu. Writing the selection into memory
+/// just below 0x200 before running skips the menu and starts the test,
+/// numbered as in the suite's README.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    SplashScreen,
+    IbmLogo,
+    Corax,
+    Flags,
+    Quirks(QuirkTarget),
+    Keypad(KeypadTest),
+}
+
+impl Test {
+    /// Writes the selector bytes into memory. Call this after CPU::load.
+    pub fn select(self, cpu: &mut CPU) {
+        let (test, option) = match self {
+            Test::SplashScreen => (1, 0),
+            Test::IbmLogo => (2, 0),
+            Test::Corax => (3, 0),
+            Test::Flags => (4, 0),
+            Test::Quirks(target) => (5, target as u8),
+            Test::Keypad(mode) => (6, mode as u8),
+        };
+        cpu.mem[TEST_SELECT] = test;
+        cpu.mem[TEST_OPTION] = option;
+    }
+}
@@ -0,0 +1,113 @@
+//! How much work the machine does in each 60Hz frame.
+//!
+//! The fixed model runs a set number of instructions per frame, which suits
+//! most roms. The VIP model follows the original COSMAC VIP interpreter
+//! instead: every instruction costs the 1802 machine cycles its routine
+//! took, and the display interrupt's DMA eats a large part of each frame.
+//!
+//! The costs come from published cycle counts of the VIP interpreter's
+//! disassembly. They're close enough for roms that depend on VIP speed, but
+//! not exact for every operand, and instructions the VIP never had are
+//! charged as a short arithmetic instruction.
+
+use std::{
+    fmt,
+    str::FromStr,
+};
+use crate::emu::{
+    cpu::CPU,
+    instruction::Instruction,
+};
+
+/// 1802 machine cycles per 60Hz frame: a 1.76064MHz clock, 8 clocks per cycle
+pub const VIP_FRAME_CYCLES: u32 = 3668;
+/// Machine cycles the display interrupt takes from each frame: 128 lines of
+/// DMA at 14 cycles each, plus the interrupt routine that ticks the timers
+pub const VIP_INTERRUPT_CYCLES: u32 = 1832;
+/// Machine cycles left for the interpreter in each frame
+pub const VIP_INTERPRETER_CYCLES: u32 = VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES;
+
+/// Fetching and dispatching an instruction, on top of its own cost
+const FETCH_CYCLES: u32 = 40;
+/// Extra cost of a skip instruction when it skips
+const SKIP_CYCLES: u32 = 4;
+
+/// The timing models a machine can run under
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// A fixed number of instructions per frame
+    #[default]
+    Fixed,
+    /// Per-instruction cycle costs and display interrupt of the COSMAC VIP
+    Vip,
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fixed" => Ok(Timing::Fixed),
+            "vip"   => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing '{}', expected fixed or vip", s)),
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Timing::Fixed => "fixed",
+            Timing::Vip   => "vip",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Machine cycles the VIP interpreter takes to run an instruction, given
+/// the state of the cpu just before running it
+pub fn vip_cycles(instruction: Instruction, cpu: &CPU) -> u32 {
+    use Instruction::*;
+    let v = |reg: u8| cpu.v[reg as usize];
+    let key = |reg: u8| cpu.kp.state_of(v(reg) as usize & 0xf);
+    let skip = |taken: bool| if taken { SKIP_CYCLES } else { 0 };
+
+    let cycles = match instruction {
+        // The VIP clears the display a byte at a time
+        Clear                 => 3024,
+        Return                => 10,
+        Jump { .. }           => 12,
+        Call { .. }           => 26,
+        SkipEqImm { x, nn }   => 10 + skip(v(x) == nn),
+        SkipNeImm { x, nn }   => 10 + skip(v(x) != nn),
+        SkipEqReg { x, y }    => 14 + skip(v(x) == v(y)),
+        SkipNeReg { x, y }    => 14 + skip(v(x) != v(y)),
+        LoadImm { .. }        => 6,
+        AddImm { .. }         => 10,
+        LoadReg { .. }        => 12,
+        // The other 8XYN instructions run through a routine built in RAM
+        Or { .. } | And { .. } | Xor { .. } | AddReg { .. } | Sub { .. }
+            | ShiftRight { .. } | SubN { .. } | ShiftLeft { .. } => 44,
+        LoadI { .. }          => 12,
+        // Crossing into another page takes an extra instruction
+        JumpOffset { nnn, .. } => 22 + if (nnn & 0xff) + v(0) as u16 > 0xff { 2 } else { 0 },
+        Random { .. }         => 36,
+        // Each row is shifted into place, which takes longer off a byte boundary
+        Draw { x, n, .. }     => 26 + n as u32 * if v(x).is_multiple_of(8) { 34 } else { 46 },
+        SkipKey { x }         => 14 + skip(key(x)),
+        SkipNotKey { x }      => 14 + skip(!key(x)),
+        GetDelay { .. }       => 10,
+        WaitKey { .. }        => 19,
+        SetDelay { .. } | SetSound { .. } => 10,
+        AddI { .. }           => 16,
+        Font { .. }           => 16,
+        // Digits are found by repeated subtraction
+        Bcd { x }             => {
+            let value = v(x);
+            80 + 16 * (value / 100 + value / 10 % 10 + value % 10) as u32
+        },
+        Store { x } | Load { x } => 14 + 14 * (x as u32 + 1),
+        _ => 10,
+    };
+    FETCH_CYCLES + cycles
+}
@@ -0,0 +1,65 @@
+/// Default frequency of the fixed tone played when no pattern has been loaded
+pub const TONE_FREQ: f32 = 440.0;
+
+/// Generates the buzzer waveform: a fixed square wave tone, or the
+/// XO-CHIP pattern buffer played back one bit at a time.
+pub struct Tone {
+    sample_rate: f32,
+    frequency: f32,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    pattern: Option<[u8; 16]>,
+}
+
+impl Tone {
+    pub fn new(sample_rate: f32, volume: f32, frequency: f32) -> Self {
+        Tone {
+            sample_rate,
+            frequency,
+            phase_inc: frequency / sample_rate,
+            phase: 0.0,
+            volume,
+            pattern: None,
+        }
+    }
+
+    /// Sets the 128-bit XO-CHIP pattern buffer and its playback pitch.
+    /// With no pattern, a fixed square wave tone is played instead.
+    pub fn set_pattern(&mut self, pattern: Option<[u8; 16]>, pitch: u8) {
+        self.pattern = pattern;
+        self.phase_inc = match pattern {
+            // Pattern phase is measured in bits: 4000 * 2^((pitch - 64) / 48) bits per second
+            Some(_) => 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0) / self.sample_rate,
+            // Square wave phase is measured in periods
+            None => self.frequency / self.sample_rate,
+        };
+        self.phase = 0.0;
+    }
+
+    /// Fills out with the next samples of the waveform
+    pub fn fill(&mut self, out: &mut [f32]) {
+        match self.pattern {
+            // Play back the pattern buffer one bit at a time
+            Some(pattern) => {
+                for x in out.iter_mut() {
+                    let bit = self.phase as usize;
+                    let high = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                    *x = if high { self.volume } else { -self.volume };
+                    self.phase = (self.phase + self.phase_inc) % 128.0;
+                }
+            },
+            // Generate a square wave
+            None => {
+                for x in out.iter_mut() {
+                    if self.phase <= 0.5 {
+                        *x = self.volume;
+                    } else {
+                        *x = -self.volume;
+                    }
+                    self.phase = (self.phase + self.phase_inc) % 1.0;
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Execution traces: a record of every instruction the cpu executes, with
+//! the registers it changed.
+//!
+//! Traces are written either as text, one line per instruction:
+//!
+//! ```text
+//!      cycle   frame   pc op    instruction          changes
+//!          5       0 020a f029  LD F, V0             i=0000->003c
+//!          6       0 020c d125  DRW V1, V2, 5
+//!          7       1 020e 1204  JP 0x204             pc=020e->0204
+//! ```
+//!
+//! or in a compact binary format, for comparing against other emulators.
+//! A binary trace starts with the magic "C8TR" and a little-endian u16
+//! version, followed by fixed size little-endian records:
+//!
+//! | field  | type     | notes                                  |
+//! |--------|----------|----------------------------------------|
+//! | cycle  | u64      | instructions executed before this one  |
+//! | frame  | u32      | timer ticks before this instruction    |
+//! | pc     | u16      | address of the instruction             |
+//! | opcode | u16      | first word of the instruction          |
+//! | v      | [u8; 16] | registers after the instruction        |
+//! | i      | u16      | after the instruction                  |
+//! | sp     | u8       | after the instruction                  |
+//! | dt     | u8       | after the instruction                  |
+//! | st     | u8       | after the instruction                  |
+
+use std::{
+    fmt,
+    io::{
+        self,
+        Write,
+    },
+    ops::RangeInclusive,
+};
+use crate::emu::{
+    cpu::CPU,
+    instruction::Instruction,
+};
+
+/// Identifies a binary trace
+const MAGIC: &[u8; 4] = b"C8TR";
+/// Bumped whenever the binary record layout changes
+pub const VERSION: u16 = 1;
+/// Size of one binary record in bytes
+pub const RECORD_LEN: usize = 37;
+
+/// The registers a trace compares before and after each instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub dt: u8,
+    pub st: u8,
+}
+
+impl From<&CPU> for Registers {
+    fn from(cpu: &CPU) -> Self {
+        Registers { v: cpu.v, i: cpu.i, pc: cpu.pc, sp: cpu.sp, dt: cpu.dt, st: cpu.st }
+    }
+}
+
+/// One executed instruction, as stored in a binary trace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub frame: u32,
+    pub pc: u16,
+    pub opcode: u16,
+    /// Registers after the instruction. The pc field is the next pc
+    pub after: Registers,
+}
+
+impl TraceRecord {
+    fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut buf = [0u8; RECORD_LEN];
+        buf[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.frame.to_le_bytes());
+        buf[12..14].copy_from_slice(&self.pc.to_le_bytes());
+        buf[14..16].copy_from_slice(&self.opcode.to_le_bytes());
+        buf[16..32].copy_from_slice(&self.after.v);
+        buf[32..34].copy_from_slice(&self.after.i.to_le_bytes());
+        buf[34] = self.after.sp;
+        buf[35] = self.after.dt;
+        buf[36] = self.after.st;
+        out.write_all(&buf)
+    }
+
+    fn read(buf: &[u8]) -> Self {
+        let u16_at = |idx: usize| u16::from_le_bytes([buf[idx], buf[idx + 1]]);
+        let mut v = [0u8; 16];
+        v.copy_from_slice(&buf[16..32]);
+        TraceRecord {
+            cycle: u64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            frame: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            pc: u16_at(12),
+            opcode: u16_at(14),
+            // Binary records don't keep the next pc
+            after: Registers { v, i: u16_at(32), pc: 0, sp: buf[34], dt: buf[35], st: buf[36] },
+        }
+    }
+}
+
+/// Parses a binary trace into its records
+pub fn read_binary(data: &[u8]) -> Result<Vec<TraceRecord>, String> {
+    let body = data.strip_prefix(MAGIC.as_slice()).ok_or("not a binary trace")?;
+    let version = body.get(..2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .ok_or("binary trace is truncated")?;
+    if version != VERSION {
+        return Err(format!("unsupported trace version {} (expected {})", version, VERSION));
+    }
+    let records = &body[2..];
+    if !records.len().is_multiple_of(RECORD_LEN) {
+        return Err(String::from("binary trace ends partway through a record"));
+    }
+    Ok(records.chunks_exact(RECORD_LEN).map(TraceRecord::read).collect())
+}
+
+/// The first point at which two traces disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Position in the traces, counting from 0
+    pub index: usize,
+    pub left: Option<TraceRecord>,
+    pub right: Option<TraceRecord>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (left, right) = match (&self.left, &self.right) {
+            (Some(left), Some(right)) => (left, right),
+            (Some(_), None) => return write!(f, "right trace ends after {} records", self.index),
+            (None, _) => return write!(f, "left trace ends after {} records", self.index),
+        };
+        write!(f, "traces diverge at record {} (left cycle {}, right cycle {}):", self.index, left.cycle, right.cycle)?;
+        if left.pc != right.pc {
+            write!(f, " pc={:04x}/{:04x}", left.pc, right.pc)?;
+        }
+        if left.opcode != right.opcode {
+            write!(f, " op={:04x}/{:04x}", left.opcode, right.opcode)?;
+        } else {
+            write!(f, " at {:04x} {:04x}", left.pc, left.opcode)?;
+        }
+        for (idx, (l, r)) in left.after.v.iter().zip(&right.after.v).enumerate() {
+            if l != r {
+                write!(f, " v{:x}={:02x}/{:02x}", idx, l, r)?;
+            }
+        }
+        let (l, r) = (&left.after, &right.after);
+        if l.i != r.i {
+            write!(f, " i={:04x}/{:04x}", l.i, r.i)?;
+        }
+        for (name, l, r) in [("sp", l.sp, r.sp), ("dt", l.dt, r.dt), ("st", l.st, r.st)] {
+            if l != r {
+                write!(f, " {}={:02x}/{:02x}", name, l, r)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two traces by instruction, address and resulting registers.
+/// Cycle and frame counts are ignored, as other emulators count differently.
+pub fn first_divergence(left: &[TraceRecord], right: &[TraceRecord]) -> Option<Divergence> {
+    let same = |l: &TraceRecord, r: &TraceRecord| {
+        (l.pc, l.opcode, l.after.v, l.after.i, l.after.sp, l.after.dt, l.after.st)
+            == (r.pc, r.opcode, r.after.v, r.after.i, r.after.sp, r.after.dt, r.after.st)
+    };
+    let index = left.iter().zip(right).position(|(l, r)| !same(l, r))
+        .or_else(|| (left.len() != right.len()).then(|| left.len().min(right.len())))?;
+    Some(Divergence { index, left: left.get(index).copied(), right: right.get(index).copied() })
+}
+
+/// Which instructions get traced. Empty filters let everything through
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// Instruction addresses
+    pub pcs: Vec<RangeInclusive<u16>>,
+    /// Opcode classes, as a bitmask of first hex digits
+    pub classes: u16,
+    /// Frame numbers
+    pub frames: Option<RangeInclusive<u32>>,
+}
+
+impl TraceFilter {
+    /// Parses filters from the command line: comma separated hex addresses
+    /// or ranges like "200-2ff", comma separated opcode classes like "8,d",
+    /// and a frame range like "100-200" or "100-"
+    pub fn parse(pcs: Option<&str>, classes: Option<&str>, frames: Option<&str>) -> Result<Self, String> {
+        let mut filter = TraceFilter::default();
+        for range in pcs.into_iter().flat_map(|pcs| pcs.split(',')) {
+            let invalid = || format!("invalid address range '{}', expected e.g. 200-2ff", range);
+            let addr = |s: &str| u16::from_str_radix(s.trim().trim_start_matches("0x"), 16).map_err(|_| invalid());
+            let range = match range.split_once('-') {
+                Some((start, end)) => addr(start)?..=addr(end)?,
+                None => addr(range)?..=addr(range)?,
+            };
+            if range.is_empty() {
+                return Err(invalid());
+            }
+            filter.pcs.push(range);
+        }
+        for class in classes.into_iter().flat_map(|classes| classes.split(',')) {
+            let nibble = u8::from_str_radix(class.trim(), 16).ok().filter(|&nibble| nibble < 16)
+                .ok_or_else(|| format!("invalid opcode class '{}', expected a hex digit 0-f", class))?;
+            filter.classes |= 1 << nibble;
+        }
+        if let Some(frames) = frames {
+            let invalid = || format!("invalid frame range '{}', expected e.g. 100-200 or 100-", frames);
+            let (start, end) = frames.split_once('-').unwrap_or((frames, frames));
+            let start = start.trim().parse().map_err(|_| invalid())?;
+            let end = match end.trim() {
+                "" => u32::MAX,
+                end => end.parse().map_err(|_| invalid())?,
+            };
+            if start > end {
+                return Err(invalid());
+            }
+            filter.frames = Some(start..=end);
+        }
+        Ok(filter)
+    }
+
+    fn allows(&self, pc: u16, opcode: u16, frame: u32) -> bool {
+        (self.pcs.is_empty() || self.pcs.iter().any(|range| range.contains(&pc)))
+            && (self.classes == 0 || self.classes & 1 << (opcode >> 12) != 0)
+            && self.frames.as_ref().is_none_or(|frames| frames.contains(&frame))
+    }
+}
+
+/// How trace records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+}
+
+/// Writes a trace of the instructions a cpu executes. Attach one to
+/// `CPU::tracer` to start tracing.
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    /// Instructions executed so far, traced or not
+    cycle: u64,
+    /// Timer ticks so far
+    frame: u32,
+    /// Set once a write fails, after which tracing stops
+    failed: bool,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write + Send>, format: TraceFormat, filter: TraceFilter) -> io::Result<Self> {
+        let mut tracer = Tracer { out, format, filter, cycle: 0, frame: 0, failed: false };
+        match format {
+            TraceFormat::Text => writeln!(tracer.out, "{:>10} {:>7} {:>4} {:<4}  {:<20} changes",
+                "cycle", "frame", "pc", "op", "instruction")?,
+            TraceFormat::Binary => {
+                tracer.out.write_all(MAGIC)?;
+                tracer.out.write_all(&VERSION.to_le_bytes())?;
+            },
+        }
+        Ok(tracer)
+    }
+
+    /// Called after each executed instruction, with the registers from before it
+    pub(crate) fn record(&mut self, before: &Registers, opcode: u16, cpu: &CPU) {
+        let cycle = self.cycle;
+        self.cycle += 1;
+        if self.failed || !self.filter.allows(before.pc, opcode, self.frame) {
+            return;
+        }
+        let record = TraceRecord { cycle, frame: self.frame, pc: before.pc, opcode, after: Registers::from(cpu) };
+        let result = match self.format {
+            TraceFormat::Text => self.write_text(before, &record),
+            TraceFormat::Binary => record.write(&mut self.out),
+        };
+        if let Err(err) = result {
+            log::error!("failed to write trace, stopping: {}", err);
+            self.failed = true;
+        }
+    }
+
+    /// Called on each 60Hz timer tick
+    pub(crate) fn tick(&mut self) {
+        self.frame += 1;
+    }
+
+    fn write_text(&mut self, before: &Registers, record: &TraceRecord) -> io::Result<()> {
+        use std::fmt::Write as _;
+
+        let after = &record.after;
+        let instruction = Instruction::decode(record.opcode);
+        let disasm = instruction.map_or_else(|| String::from("???"), |instruction| instruction.to_string());
+        let mut line = format!("{:>10} {:>7} {:04x} {:04x}  {:<20}", record.cycle, record.frame, record.pc, record.opcode, disasm);
+
+        for (idx, (b, a)) in before.v.iter().zip(&after.v).enumerate() {
+            if b != a {
+                let _ = write!(line, " v{:x}={:02x}->{:02x}", idx, b, a);
+            }
+        }
+        if before.i != after.i {
+            let _ = write!(line, " i={:04x}->{:04x}", before.i, after.i);
+        }
+        // Only branches are worth showing for the pc
+        let size = instruction.map_or(2, |instruction| instruction.size());
+        if after.pc != before.pc.wrapping_add(size) {
+            let _ = write!(line, " pc={:04x}->{:04x}", before.pc, after.pc);
+        }
+        for (name, b, a) in [("sp", before.sp, after.sp), ("dt", before.dt, after.dt), ("st", before.st, after.st)] {
+            if b != a {
+                let _ = write!(line, " {}={:02x}->{:02x}", name, b, a);
+            }
+        }
+        writeln!(self.out, "{}", line.trim_end())
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .field("cycle", &self.cycle)
+            .field("frame", &self.frame)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(cycle: u64, pc: u16, opcode: u16, v0: u8) -> TraceRecord {
+        let mut v = [0; 16];
+        v[0] = v0;
+        TraceRecord {
+            cycle,
+            frame: (cycle / 10) as u32,
+            pc,
+            opcode,
+            after: Registers { v, i: 0x345, pc: 0, sp: 1, dt: 2, st: 3 },
+        }
+    }
+
+    fn binary(records: &[TraceRecord]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        for record in records {
+            record.write(&mut data).unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn parse_filters() {
+        let filter = TraceFilter::parse(Some("200-2ff,0x300"), Some("8,d"), Some("100-")).unwrap();
+        assert_eq!(filter.pcs, [0x200..=0x2ff, 0x300..=0x300]);
+        assert_eq!(filter.classes, 1 << 8 | 1 << 0xd);
+        assert_eq!(filter.frames, Some(100..=u32::MAX));
+        assert!(filter.allows(0x2ff, 0xd125, 100));
+        assert!(!filter.allows(0x302, 0xd125, 100));
+        assert!(!filter.allows(0x200, 0x6001, 100));
+        assert!(!filter.allows(0x200, 0xd125, 99));
+
+        assert_eq!(TraceFilter::parse(None, None, Some("7")).unwrap().frames, Some(7..=7));
+        assert_eq!(TraceFilter::parse(None, None, None).unwrap(), TraceFilter::default());
+    }
+
+    #[test]
+    fn parse_rejects_bad_filters() {
+        assert!(TraceFilter::parse(Some("2ff-200"), None, None).is_err());
+        assert!(TraceFilter::parse(Some("20g"), None, None).is_err());
+        assert!(TraceFilter::parse(None, Some("10"), None).is_err());
+        assert!(TraceFilter::parse(None, None, Some("200-100")).is_err());
+        assert!(TraceFilter::parse(None, None, Some("-100")).is_err());
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let records = [record(0, 0x200, 0x6042, 0x42), record(1, 0x202, 0xd125, 0x42)];
+        let data = binary(&records);
+        assert_eq!(data.len(), 6 + 2 * RECORD_LEN);
+        assert_eq!(read_binary(&data).unwrap(), records);
+
+        assert!(read_binary(&data[..data.len() - 1]).is_err());
+        assert!(read_binary(b"C8TR").is_err());
+        assert!(read_binary(b"C8TR\x02\x00").is_err());
+        assert!(read_binary(b"hello").is_err());
+    }
+
+    #[test]
+    fn first_divergence_ignores_counts() {
+        let left = [record(0, 0x200, 0x6042, 0x42), record(1, 0x202, 0x7001, 0x43)];
+        let mut right = left;
+        right[0].cycle = 50;
+        right[1].frame = 9;
+        assert_eq!(first_divergence(&left, &right), None);
+
+        right[1].after.v[0] = 0x44;
+        let divergence = first_divergence(&left, &right).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.to_string(), "traces diverge at record 1 (left cycle 1, right cycle 1): at 0202 7001 v0=43/44");
+
+        let divergence = first_divergence(&left, &left[..1]).unwrap();
+        assert_eq!((divergence.index, divergence.right), (1, None));
+        assert_eq!(divergence.to_string(), "right trace ends after 1 records");
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    error::Error,
+    time::{
+        Duration,
+        Instant,
+    },
+};
+use crate::emu::{
+    font::FONT,
+    frame::{
+        Frame,
+        Palette,
+    },
+    machine::{
+        AudioOutput,
+        InputState,
+    },
+};
+
+/// Emulator events raised by input, as opposed to chip-8 keypad presses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// The user asked to quit
+    Quit,
+    /// A new rom file was dropped in
+    RomDropped(String),
+    /// Save a state to the current slot
+    SaveState,
+    /// Load the state in the current slot
+    LoadState,
+    /// Select the next save slot
+    NextSlot,
+    /// Select the previous save slot
+    PrevSlot,
+    /// Held down: step back through the rewind buffer
+    Rewind,
+    /// Run more instructions per frame
+    SpeedUp,
+    /// Run fewer instructions per frame
+    SpeedDown,
+    /// The window was resized or uncovered, and the frame needs drawing again
+    Redraw,
+    /// Show or hide the speed stats overlay
+    ToggleStats,
+}
+
+/// Somewhere to present the framebuffer
+pub trait VideoSink {
+    /// Present a new frame
+    fn draw(&mut self, frame: &Frame) -> Result<(), Box<dyn Error>>;
+
+    /// Show a short status line, e.g. in the window title
+    fn set_title(&mut self, title: &str) -> Result<(), Box<dyn Error>>;
+
+    /// Change the colours pixel values are drawn with
+    fn set_palette(&mut self, palette: Palette);
+
+    /// Whether draw waits for the display's next refresh, which then paces
+    /// the main loop instead of sleeping
+    fn vsync(&self) -> bool {
+        false
+    }
+}
+
+/// Something that can play the chip-8 buzzer
+pub trait AudioSink {
+    /// Update playback to match the machine, called once per frame
+    fn update(&mut self, audio: &AudioOutput);
+}
+
+/// Where keypad state and emulator hotkeys come from
+pub trait InputSource {
+    /// Returns the current keypad state and any events since the last poll
+    fn poll(&mut self) -> Result<(InputState, Vec<InputEvent>), Box<dyn Error>>;
+
+    /// Rebind the keypad, given the names of the keys bound to each chip-8 key
+    fn set_keys(&mut self, keys: &[Vec<String>; 16]) -> Result<(), Box<dyn Error>>;
+}
+
+/// How long a readout stays on screen, in frames
+const READOUT_FRAMES: u32 = 90;
+
+/// A number shown briefly in the top left corner of the frame,
+/// drawn with the built-in hex font so it works on every frontend
+#[derive(Debug, Default)]
+pub struct Readout {
+    value: usize,
+    frames_left: u32,
+}
+
+impl Readout {
+    /// Shows value, replacing any current readout
+    pub fn show(&mut self, value: usize) {
+        self.value = value;
+        self.frames_left = READOUT_FRAMES;
+    }
+
+    /// Counts down a frame. Returns whether the readout was on screen,
+    /// which includes the frame it disappears and needs erasing.
+    pub fn tick(&mut self) -> bool {
+        let visible = self.frames_left > 0;
+        self.frames_left = self.frames_left.saturating_sub(1);
+        visible
+    }
+
+    /// Draws the value over the frame, if still visible
+    pub fn draw(&self, frame: &mut Frame) {
+        if self.frames_left > 0 {
+            draw_number(frame, 0, 0, self.value as u64);
+        }
+    }
+}
+
+/// Emulation speed over the last second, shown in the bottom left corner:
+/// instructions per second, above the average and longest host frame
+/// times in milliseconds
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub visible: bool,
+    window_start: Option<Instant>,
+    last_frame: Option<Instant>,
+    instructions: u64,
+    frames: u32,
+    frame_time_total: Duration,
+    frame_time_max: Duration,
+    ips: u64,
+    avg_ms: u64,
+    max_ms: u64,
+}
+
+impl Stats {
+    /// Records a host frame, and the instructions executed during it.
+    /// Returns whether the figures changed, once a second.
+    pub fn record(&mut self, now: Instant, instructions: u64) -> bool {
+        if let Some(last) = self.last_frame.replace(now) {
+            let frame_time = now - last;
+            self.frame_time_total += frame_time;
+            self.frame_time_max = self.frame_time_max.max(frame_time);
+            self.frames += 1;
+        }
+        self.instructions += instructions;
+
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now - start;
+        if elapsed < Duration::from_secs(1) {
+            return false;
+        }
+        self.ips = (self.instructions as f64 / elapsed.as_secs_f64()).round() as u64;
+        self.avg_ms = (self.frame_time_total / self.frames.max(1)).as_millis() as u64;
+        self.max_ms = self.frame_time_max.as_millis() as u64;
+        log::debug!("{} instructions/s, frame time {} ms average, {} ms max", self.ips, self.avg_ms, self.max_ms);
+
+        self.window_start = Some(now);
+        self.instructions = 0;
+        self.frames = 0;
+        self.frame_time_total = Duration::ZERO;
+        self.frame_time_max = Duration::ZERO;
+        true
+    }
+
+    /// Draws the figures over the frame, if visible
+    pub fn draw(&self, frame: &mut Frame) {
+        if !self.visible || frame.height < 14 {
+            return;
+        }
+        let bottom = frame.height - 7;
+        draw_number(frame, 0, bottom - 6, self.ips);
+        let width = draw_number(frame, 0, bottom, self.avg_ms);
+        draw_number(frame, width + 1, bottom, self.max_ms);
+    }
+}
+
+/// Draws a decimal number with the built-in hex font, on a background box
+/// with its top left corner at (x, y). Returns the width of the box.
+fn draw_number(frame: &mut Frame, x: usize, y: usize, value: u64) -> usize {
+    let digits: Vec<usize> = value.to_string().bytes().map(|digit| (digit - b'0') as usize).collect();
+
+    // Glyphs are 4x5 with a pixel of spacing, on a background with a 1 pixel border
+    let width = digits.len() * 5 + 1;
+    for row in y..(y + 7).min(frame.height) {
+        let start = row * frame.width + x.min(frame.width);
+        let end = row * frame.width + (x + width).min(frame.width);
+        frame.data[start..end].fill(0);
+    }
+    for (idx, &digit) in digits.iter().enumerate() {
+        for (row, bits) in FONT[digit * 5..digit * 5 + 5].iter().enumerate() {
+            for col in 0..4 {
+                let (px, py) = (x + 1 + idx * 5 + col, y + 1 + row);
+                if bits & (0x80 >> col) != 0 && px < frame.width && py < frame.height {
+                    frame.data[py * frame.width + px] = 1;
+                }
+            }
+        }
+    }
+    width
+}
@@ -1,3 +1,13 @@
 pub mod drivers;
 pub mod emu;
 pub mod config;
+pub mod disasm;
+pub mod asm;
+pub mod debug;
+pub mod frontend;
+pub mod rom_db;
+pub mod scheduler;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "libretro")]
+pub mod libretro;
@@ -0,0 +1,581 @@
+//! A libretro core, for running the emulator in RetroArch and other
+//! libretro frontends. Build with `--features libretro`; the cdylib then
+//! exports the `retro_*` entry points.
+//!
+//! The joypad is mapped onto the hex keypad as follows, so that the
+//! d-pad matches the 2/4/6/8 directions most games use:
+//!
+//! | joypad | key | joypad | key | joypad | key | joypad | key |
+//! |--------|-----|--------|-----|--------|-----|--------|-----|
+//! | up     | 2   | B      | 5   | select | B   | L2     | A   |
+//! | down   | 8   | A      | 0   | start  | F   | R2     | C   |
+//! | left   | 4   | Y      | 7   | L      | 1   | L3     | D   |
+//! | right  | 6   | X      | 9   | R      | 3   | R3     | E   |
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    ffi::{
+        c_char,
+        c_uint,
+        c_void,
+        CStr,
+    },
+    ptr,
+    slice,
+    sync::Mutex,
+};
+use crate::emu::{
+    cpu::MEM_SIZE,
+    frame::{
+        HIRES,
+        LORES,
+        PALETTE,
+    },
+    machine::{
+        AudioOutput,
+        InputState,
+        Machine,
+    },
+    quirks::Platform,
+    state::HASH_LEN,
+    tone::{
+        Tone,
+        TONE_FREQ,
+    },
+};
+
+const API_VERSION: c_uint = 1;
+const SAMPLE_RATE: f64 = 44_100.0;
+const FPS: f64 = 60.0;
+
+const ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const ENVIRONMENT_SET_GEOMETRY: c_uint = 37;
+const PIXEL_FORMAT_XRGB8888: c_uint = 1;
+const DEVICE_JOYPAD: c_uint = 1;
+const MEMORY_SYSTEM_RAM: c_uint = 2;
+const REGION_NTSC: c_uint = 0;
+
+/// Hex key for each libretro joypad button id, from B (0) to R3 (15)
+const JOYPAD_KEYS: [usize; 16] = [
+    0x5, 0x7, 0xB, 0xF, // B, Y, select, start
+    0x2, 0x8, 0x4, 0x6, // up, down, left, right
+    0x0, 0x9, 0x1, 0x3, // A, X, L, R
+    0xA, 0xC, 0xD, 0xE, // L2, R2, L3, R3
+];
+
+const OPTION_QUIRKS: &CStr = c"chip8_quirks";
+const OPTION_CYCLES: &CStr = c"chip8_cycles";
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct Variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+/// Callbacks registered by the frontend
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+/// The loaded game
+struct Core {
+    machine: Machine,
+    rom: Vec<u8>,
+    rom_hash: [u8; HASH_LEN],
+    tone: Tone,
+    /// Pattern and pitch the tone is playing. Setting them restarts the
+    /// waveform, so they're only passed on when they change
+    pattern: Option<[u8; 16]>,
+    pitch: u8,
+    video: Vec<u32>,
+    audio: Vec<f32>,
+    samples: Vec<i16>,
+    /// Fractional audio frames carried over, as 44.1kHz doesn't divide by 60
+    sample_debt: f64,
+    width: usize,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn callbacks() -> std::sync::MutexGuard<'static, Callbacks> {
+    CALLBACKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn core() -> std::sync::MutexGuard<'static, Option<Core>> {
+    CORE.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+unsafe fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+/// Reads a core option, if the frontend has a value for it
+unsafe fn variable(key: &CStr) -> Option<String> {
+    let mut var = Variable { key: key.as_ptr(), value: ptr::null() };
+    if !environment(ENVIRONMENT_GET_VARIABLE, &mut var as *mut _ as *mut c_void) || var.value.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(var.value).to_string_lossy().into_owned())
+}
+
+/// Applies the core options to the machine
+unsafe fn apply_options(machine: &mut Machine) {
+    if let Some(platform) = variable(OPTION_QUIRKS).and_then(|value| value.parse::<Platform>().ok()) {
+        machine.cpu.quirks = platform.quirks();
+    }
+    if let Some(cycles) = variable(OPTION_CYCLES).and_then(|value| value.parse().ok()) {
+        machine.cycles_per_frame = cycles;
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    callbacks().environment = Some(cb);
+
+    let variables = [
+        Variable {
+            key: OPTION_QUIRKS.as_ptr(),
+            value: c"Quirk profile; vip|chip48|schip11|xochip".as_ptr(),
+        },
+        Variable {
+            key: OPTION_CYCLES.as_ptr(),
+            value: c"Cycles per frame; 10|15|20|30|50|100|200|500|1000".as_ptr(),
+        },
+        Variable { key: ptr::null(), value: ptr::null() },
+    ];
+    cb(ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    callbacks().video_refresh = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    callbacks().audio_sample_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    callbacks().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    callbacks().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"chip8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8|xo8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+fn geometry(width: usize, height: usize) -> GameGeometry {
+    GameGeometry {
+        base_width: width as c_uint,
+        base_height: height as c_uint,
+        max_width: HIRES.x as c_uint,
+        max_height: HIRES.y as c_uint,
+        aspect_ratio: 2.0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo {
+        geometry: geometry(LORES.x, LORES.y),
+        timing: SystemTiming { fps: FPS, sample_rate: SAMPLE_RATE },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_reset() {
+    if let Some(core) = core().as_mut() {
+        // The rom fit when the game was loaded, so it still does
+        let _ = core.machine.load(&core.rom);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    let mut core = core();
+    let Some(core) = core.as_mut() else {
+        return;
+    };
+
+    let mut updated = false;
+    if environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+        apply_options(&mut core.machine);
+    }
+
+    let (input_poll, input_state, video_refresh, audio_sample_batch) = {
+        let cb = callbacks();
+        (cb.input_poll, cb.input_state, cb.video_refresh, cb.audio_sample_batch)
+    };
+
+    let mut input = InputState::default();
+    if let (Some(poll), Some(state)) = (input_poll, input_state) {
+        poll();
+        for (id, &key) in JOYPAD_KEYS.iter().enumerate() {
+            input.keys[key] = state(0, DEVICE_JOYPAD, 0, id as c_uint) != 0;
+        }
+    }
+
+    let output = core.machine.run_frame(&input);
+    for event in &output.events {
+        log::warn!("libretro core: {:?}", event);
+    }
+
+    let fb = &core.machine.cpu.fb;
+    if fb.width != core.width {
+        let mut geometry = geometry(fb.width, fb.height);
+        environment(ENVIRONMENT_SET_GEOMETRY, &mut geometry as *mut _ as *mut c_void);
+        core.width = fb.width;
+    }
+    core.video.clear();
+    core.video.extend(fb.data.iter().map(|&pixel| {
+        let [r, g, b] = PALETTE[pixel as usize & 0b11];
+        u32::from_be_bytes([0, r, g, b])
+    }));
+    if let Some(refresh) = video_refresh {
+        refresh(core.video.as_ptr() as *const c_void, fb.width as c_uint, fb.height as c_uint, fb.width * 4);
+    }
+
+    // Generate a frame's worth of stereo samples
+    core.sample_debt += SAMPLE_RATE / FPS;
+    let frames = core.sample_debt as usize;
+    core.sample_debt -= frames as f64;
+    core.audio.resize(frames, 0.0);
+    if output.audio.playing {
+        let AudioOutput { pattern, pitch, .. } = output.audio;
+        if (pattern, pitch) != (core.pattern, core.pitch) {
+            core.pattern = pattern;
+            core.pitch = pitch;
+            core.tone.set_pattern(pattern, pitch);
+        }
+        core.tone.fill(&mut core.audio);
+    } else {
+        core.audio.fill(0.0);
+    }
+    core.samples.clear();
+    core.samples.extend(core.audio.iter().flat_map(|&sample| {
+        let sample = (sample * i16::MAX as f32) as i16;
+        [sample, sample]
+    }));
+    if let Some(batch) = audio_sample_batch {
+        batch(core.samples.as_ptr(), frames);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    let Some(game) = game.as_ref() else {
+        return false;
+    };
+    if game.data.is_null() {
+        return false;
+    }
+
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut _ as *mut c_void) {
+        return false;
+    }
+
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    let mut machine = Machine::new(Platform::default().quirks());
+    apply_options(&mut machine);
+    if machine.load(&rom).is_err() {
+        return false;
+    }
+
+    *core() = Some(Core {
+        machine,
+        rom_hash: sha1_smol::Sha1::from(&rom).digest().bytes(),
+        rom,
+        tone: Tone::new(SAMPLE_RATE as f32, 0.1, TONE_FREQ),
+        pattern: None,
+        pitch: 64,
+        video: Vec::with_capacity(HIRES.x * HIRES.y),
+        audio: Vec::new(),
+        samples: Vec::new(),
+        sample_debt: 0.0,
+        width: LORES.x,
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_type: c_uint, _info: *const GameInfo, _num: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+/// Save states vary in size with the resolution, so each is stored behind
+/// a length prefix and padded out to the largest possible size
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    let core = core();
+    let Some(core) = core.as_ref() else {
+        return 0;
+    };
+    let frame = core.machine.cpu.fb.width * core.machine.cpu.fb.height;
+    let state = core.machine.cpu.save_state(&core.rom_hash).len();
+    4 + state - frame + HIRES.x * HIRES.y
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(core) = core.as_ref() else {
+        return false;
+    };
+    let state = core.machine.cpu.save_state(&core.rom_hash);
+    if data.is_null() || 4 + state.len() > size {
+        return false;
+    }
+    let out = slice::from_raw_parts_mut(data as *mut u8, size);
+    out[..4].copy_from_slice(&(state.len() as u32).to_le_bytes());
+    out[4..4 + state.len()].copy_from_slice(&state);
+    out[4 + state.len()..].fill(0);
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(core) = core.as_mut() else {
+        return false;
+    };
+    if data.is_null() || size < 4 {
+        return false;
+    }
+    let data = slice::from_raw_parts(data as *const u8, size);
+    let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let Some(state) = data[4..].get(..len) else {
+        return false;
+    };
+    match core.machine.cpu.load_state(state, &core.rom_hash) {
+        Ok(()) => {
+            core.machine.clear_fault();
+            true
+        },
+        Err(err) => {
+            log::error!("libretro core: {}", err);
+            false
+        },
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match core().as_mut() {
+        Some(core) if id == MEMORY_SYSTEM_RAM => core.machine.cpu.mem.as_mut_ptr() as *mut c_void,
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match core().as_ref() {
+        Some(_) if id == MEMORY_SYSTEM_RAM => MEM_SIZE,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    /// The core is global, so tests take turns
+    static LOCK: Mutex<()> = Mutex::new(());
+    static AUDIO_FRAMES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+    static AUDIO_NONZERO: AtomicUsize = AtomicUsize::new(0);
+
+    /// Sounds the buzzer for two seconds while counting up in V1
+    const ROM: [u8; 8] = [0x60, 0x78, 0xF0, 0x18, 0x71, 0x01, 0x12, 0x04];
+
+    unsafe extern "C" fn environment(cmd: c_uint, _data: *mut c_void) -> bool {
+        cmd == ENVIRONMENT_SET_PIXEL_FORMAT
+    }
+
+    unsafe extern "C" fn video_refresh(_data: *const c_void, _width: c_uint, _height: c_uint, _pitch: usize) {}
+
+    unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+        let samples = slice::from_raw_parts(data, frames * 2);
+        AUDIO_NONZERO.fetch_add(samples.iter().filter(|&&sample| sample != 0).count(), Ordering::Relaxed);
+        AUDIO_FRAMES.lock().unwrap().push(frames);
+        frames
+    }
+
+    fn load() -> std::sync::MutexGuard<'static, ()> {
+        let guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            retro_set_environment(environment);
+            retro_set_video_refresh(video_refresh);
+            retro_set_audio_sample_batch(audio_sample_batch);
+            let game = GameInfo {
+                path: ptr::null(),
+                data: ROM.as_ptr() as *const c_void,
+                size: ROM.len(),
+                meta: ptr::null(),
+            };
+            assert!(retro_load_game(&game));
+        }
+        guard
+    }
+
+    #[test]
+    fn serialize_roundtrip() {
+        let _guard = load();
+        unsafe {
+            for _ in 0..10 {
+                retro_run();
+            }
+            let size = retro_serialize_size();
+            let mut state = vec![0u8; size];
+            assert!(retro_serialize(state.as_mut_ptr() as *mut c_void, size));
+            let counter = core().as_ref().unwrap().machine.cpu.v[1];
+
+            for _ in 0..5 {
+                retro_run();
+            }
+            assert_ne!(core().as_ref().unwrap().machine.cpu.v[1], counter);
+
+            assert!(retro_unserialize(state.as_ptr() as *const c_void, size));
+            assert_eq!(core().as_ref().unwrap().machine.cpu.v[1], counter);
+            let mut again = vec![0u8; size];
+            assert!(retro_serialize(again.as_mut_ptr() as *mut c_void, size));
+            assert_eq!(again, state);
+
+            // Too small a buffer, and states from nowhere, are refused
+            assert!(!retro_serialize(state.as_mut_ptr() as *mut c_void, 10));
+            assert!(!retro_unserialize([0u8; 8].as_ptr() as *const c_void, 8));
+            retro_unload_game();
+        }
+    }
+
+    #[test]
+    fn each_frame_sends_735_audio_frames() {
+        let _guard = load();
+        AUDIO_FRAMES.lock().unwrap().clear();
+        unsafe {
+            for _ in 0..60 {
+                retro_run();
+            }
+            retro_unload_game();
+        }
+        let frames = AUDIO_FRAMES.lock().unwrap();
+        assert_eq!(frames.len(), 60);
+        assert!(frames.iter().all(|&count| count == 735));
+        assert!(AUDIO_NONZERO.load(Ordering::Relaxed) > 0);
+    }
+}
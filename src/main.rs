@@ -1,22 +1,71 @@
 use std::{
-    error::Error, 
+    error::Error,
+    fs,
+    io::BufWriter,
     time::{
-        Duration, 
-        Instant, 
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
     },
     thread,
 };
 use chip8::{
-    config::Config,
-    emu::cpu::CPU, 
+    config::{
+        Config,
+        Frontend,
+        Mode,
+        MovieMode,
+    },
+    disasm,
+    asm,
+    debug::{
+        DebugSession,
+        gdb::GdbStub,
+        repl::Repl,
+    },
+    emu::{
+        machine::{
+            InputState,
+            Machine,
+            MachineEvent,
+        },
+        movie::{
+            Movie,
+            MovieHeader,
+            Player,
+            Recorder,
+        },
+        rewind::Rewind,
+        trace::{
+            self,
+            Tracer,
+        },
+        rng::{
+            Rng,
+            DEFAULT_SEED,
+        },
+        timing::Timing,
+    },
     drivers::{
-        video::VideoDriver,
-        input::InputDriver,
-        audio::AudioDriver,
         file::FileDriver,
+        image::Image,
+        savestate::SaveStateDriver,
     },
+    frontend::{
+        AudioSink,
+        InputEvent,
+        InputSource,
+        Readout,
+        Stats,
+        VideoSink,
+    },
+    scheduler::Scheduler,
 };
 
+/// Instructions per frame selectable with the speed hotkeys
+const SPEED_STEPS: [usize; 14] = [1, 2, 3, 5, 7, 10, 15, 20, 30, 50, 100, 200, 500, 1000];
+
 fn main() -> Result<(), Box<dyn Error>> {
     let config = Config::from_args()?;
 
@@ -24,15 +73,109 @@ fn main() -> Result<(), Box<dyn Error>> {
         .with_level(config.log_level)
         .init()?;
 
+    match &config.mode {
+        Mode::Disasm => {
+            let rom_path = config.rom_path.ok_or("no rom file provided")?;
+            let rom = FileDriver::from_string(&rom_path)?;
+            print!("{}", disasm::disassemble(&rom.data));
+            Ok(())
+        },
+        Mode::Assemble { output_path } => {
+            let source_path = config.rom_path.ok_or("no source file provided")?;
+            let source = fs::read_to_string(&source_path)?;
+            let rom = asm::assemble(&source)
+                .map_err(|err| format!("{}:{}", source_path, err))?;
+            fs::write(output_path, rom)?;
+            log::info!("assembled {} into {}", source_path, output_path);
+            Ok(())
+        },
+        Mode::TraceDiff { left, right } => {
+            let read = |path: &String| -> Result<_, Box<dyn Error>> {
+                let data = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+                Ok(trace::read_binary(&data).map_err(|err| format!("{}: {}", path, err))?)
+            };
+            let (left_records, right_records) = (read(left)?, read(right)?);
+            match trace::first_divergence(&left_records, &right_records) {
+                Some(divergence) => Err(divergence.to_string().into()),
+                None => {
+                    println!("traces match ({} records)", left_records.len());
+                    Ok(())
+                },
+            }
+        },
+        Mode::Headless { frames, output_path, expect_path } => {
+            let rom_path = config.rom_path.as_ref().ok_or("no rom file provided")?;
+            let rom = FileDriver::from_string(rom_path)?;
+            let rom_config = config.rom_config(&rom)?;
+            let mut machine = Machine::new(rom_config.quirks);
+            machine.cycles_per_frame = rom_config.cycles_per_frame;
+            machine.timing = config.timing;
+            let seed = config.seed.unwrap_or(DEFAULT_SEED);
+            machine.cpu.rng = Rng::new(config.rng, seed);
+            let (mut recorder, mut player) = start_movie(&config, &rom, &mut machine, seed)?;
+            machine.cpu.tracer = start_trace(&config)?;
+            machine.load(&rom.data).map_err(|err| err.to_string())?;
+
+            // A movie keeps its own speed
+            let mut scheduler = Scheduler::new();
+            scheduler.set_cpu_hz(config.cpu_hz.filter(|_| player.is_none()));
+
+            let frames = player.as_ref().map_or(*frames, |player| player.frames());
+            for _ in 0..frames {
+                if let Some(cycles) = scheduler.cycles_for_tick() {
+                    machine.cycles_per_frame = cycles;
+                }
+                let output = match (&mut recorder, &mut player) {
+                    (_, Some(player)) => player.run_frame(&mut machine).map_err(|desync| desync.to_string())?,
+                    (Some(recorder), None) => recorder.run_frame(&mut machine, &InputState::default()),
+                    (None, None) => machine.run_frame(&InputState::default()),
+                };
+                match output.events.first() {
+                    Some(MachineEvent::Exited) => break,
+                    Some(MachineEvent::Fault(err)) => return Err(format!("cpu fault: {}", err).into()),
+                    None => {},
+                }
+            }
+            save_movie(&config, recorder.as_ref())?;
+
+            let image = Image::from_frame(&machine.cpu.fb);
+            match output_path {
+                Some(path) => image.save(path)?,
+                None if expect_path.is_none() => print!("{}", image.to_ascii()),
+                None => {},
+            }
+            if let Some(path) = expect_path {
+                image.compare(&Image::load(path)?)
+                    .map_err(|err| format!("{}: {}", path, err))?;
+                log::info!("frame matches {}", path);
+            }
+            Ok(())
+        },
+        Mode::Emulate => match config.frontend {
+            Frontend::Sdl => emulate_sdl(&config),
+            Frontend::Terminal { key_timeout, bell } => emulate_tui(&config, key_timeout, bell),
+        },
+    }
+}
+
+/// Opens an SDL window and runs the emulator in it
+#[cfg(feature = "sdl")]
+fn emulate_sdl(config: &Config) -> Result<(), Box<dyn Error>> {
+    use chip8::drivers::{
+        video::VideoDriver,
+        input::InputDriver,
+        audio::AudioDriver,
+    };
+
     let sdl_context = sdl2::init()?;
-    let mut video_driver = VideoDriver::new(&sdl_context)?;
-    let mut input_driver = InputDriver::new(&sdl_context)?;
-    let mut audio_driver = AudioDriver::new(&sdl_context)?;
+    let mut video_driver = VideoDriver::new(&sdl_context, config.scale_factor, config.scaling, config.palette, config.vsync)?;
+    let mut input_driver = InputDriver::new(&sdl_context, &config.keys)?;
+    let mut audio_driver = AudioDriver::new(&sdl_context, config.volume, config.tone)?;
 
-    let rom_path = match config.rom_path {
+    let rom_path = match &config.rom_path {
         Some(rom_path) => {
             log::debug!("rom file provided by cli: {}", rom_path);
-            rom_path
+            rom_path.clone()
         },
         // If no rom is provided by CLI, wait for user to drop a file in
         None => {
@@ -46,34 +189,335 @@ fn main() -> Result<(), Box<dyn Error>> {
     };
     let rom = FileDriver::from_string(&rom_path)?;
 
-    let mut cpu = CPU::initialize();
-    cpu.load(&rom.data);
+    emulate(config, &rom, &mut video_driver, &mut input_driver, &mut audio_driver)
+}
 
-    while input_driver.poll(&mut cpu).is_ok() {
-        let cycle_start_time = Instant::now();
+#[cfg(not(feature = "sdl"))]
+fn emulate_sdl(_config: &Config) -> Result<(), Box<dyn Error>> {
+    Err("built without the sdl feature, only `run --headless` is available".into())
+}
 
-        for _ in 0..10 {
-            cpu.step();
+/// Runs the emulator in the terminal
+#[cfg(feature = "tui")]
+fn emulate_tui(config: &Config, key_timeout: Duration, bell: bool) -> Result<(), Box<dyn Error>> {
+    use chip8::drivers::terminal::{
+        TerminalAudio,
+        TerminalGuard,
+        TerminalInput,
+        TerminalVideo,
+    };
+
+    let rom_path = config.rom_path.as_ref().ok_or("no rom file provided")?;
+    let rom = FileDriver::from_string(rom_path)?;
+
+    let terminal = TerminalGuard::new()?;
+    let mut video = TerminalVideo::new(config.palette);
+    let mut input = TerminalInput::new(&config.keys, key_timeout, terminal.reports_releases())?;
+    let mut audio = TerminalAudio::new(bell);
+
+    emulate(config, &rom, &mut video, &mut input, &mut audio)
+}
+
+#[cfg(not(feature = "tui"))]
+fn emulate_tui(_config: &Config, _key_timeout: Duration, _bell: bool) -> Result<(), Box<dyn Error>> {
+    Err("built without the tui feature".into())
+}
+
+/// The main loop: drives the machine at 60 ticks per second on any frontend
+#[cfg_attr(not(any(feature = "sdl", feature = "tui")), allow(dead_code))]
+fn emulate(
+    config: &Config,
+    rom: &FileDriver,
+    video: &mut impl VideoSink,
+    input: &mut impl InputSource,
+    audio: &mut impl AudioSink,
+) -> Result<(), Box<dyn Error>> {
+    let rom_config = config.rom_config(rom)?;
+    let mut machine = Machine::new(rom_config.quirks);
+    machine.cycles_per_frame = rom_config.cycles_per_frame;
+    machine.timing = config.timing;
+    machine.load(&rom.data).map_err(|err| err.to_string())?;
+    video.set_palette(rom_config.palette);
+    input.set_keys(&rom_config.keys)?;
+    let mut title = rom_config.title;
+
+    // Interactive sessions get a different random sequence each run, unless
+    // a seed is given. It's logged so the run can be repeated with --seed
+    let seed = config.seed.unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos() as u64)
+    });
+    log::info!("{} rng seed: {}", config.rng, seed);
+    machine.cpu.rng = Rng::new(config.rng, seed);
+
+    // A movie replaces the machine's settings with the recording's, so load
+    // the rom again on top of them. Playback stands in for the keypad
+    let (mut recorder, mut player) = start_movie(config, rom, &mut machine, seed)?;
+    machine.cpu.tracer = start_trace(config)?;
+    machine.load(&rom.data).map_err(|err| err.to_string())?;
+
+    // Without somewhere to keep them, run without save states rather than not at all
+    let mut save_states = SaveStateDriver::new(rom)
+        .map_err(|err| log::warn!("save states are disabled: {}", err))
+        .ok();
+    let mut rewind = Rewind::new(config.rewind_frames);
+    let mut readout = Readout::default();
+    let mut stats = Stats::default();
+    stats.visible = config.stats;
+    let mut scheduler = Scheduler::new();
+    scheduler.set_cpu_hz(config.cpu_hz.filter(|_| player.is_none()));
+
+    // The debugger takes over stepping, and handles faults itself
+    let mut debug_session: Option<Box<dyn DebugSession>> = match config.gdb_port {
+        Some(port) => Some(Box::new(GdbStub::listen(port)?)),
+        None if config.debug => Some(Box::new(Repl::start())),
+        None => None,
+    };
+
+    video.set_title(&title)?;
+
+    'emulate: loop {
+        let (keys, events) = input.poll()?;
+        let mut rewinding = false;
+        let mut redraw = false;
+        // Anything that changes the machine behind the movie's back would desync it
+        let movie_active = recorder.is_some() || player.is_some();
+        for event in events {
+            match event {
+                InputEvent::Quit => break 'emulate,
+                InputEvent::RomDropped(_) | InputEvent::LoadState | InputEvent::Rewind if movie_active => {
+                    log::warn!("{:?} is disabled while a movie is recording or playing", event);
+                },
+                InputEvent::SpeedUp | InputEvent::SpeedDown if player.is_some() => {
+                    log::warn!("speed is set by the movie during playback");
+                },
+                InputEvent::SpeedUp | InputEvent::SpeedDown if machine.timing == Timing::Vip => {
+                    log::warn!("speed is set by the cycle costs under vip timing");
+                },
+                InputEvent::RomDropped(path) => {
+                    let rom = FileDriver::from_string(&path)?;
+                    let rom_config = config.rom_config(&rom)?;
+                    machine.cpu.quirks = rom_config.quirks;
+                    machine.cycles_per_frame = rom_config.cycles_per_frame;
+                    machine.load(&rom.data).map_err(|err| err.to_string())?;
+                    video.set_palette(rom_config.palette);
+                    input.set_keys(&rom_config.keys)?;
+                    if let Some(save_states) = save_states.as_mut() {
+                        save_states.set_rom(&rom);
+                    }
+                    rewind.clear();
+                    title = rom_config.title;
+                    video.set_title(&title)?;
+                },
+                InputEvent::SaveState | InputEvent::LoadState | InputEvent::NextSlot | InputEvent::PrevSlot => {
+                    let Some(save_states) = save_states.as_mut() else {
+                        show_status(video, &title, "save states are disabled")?;
+                        continue;
+                    };
+                    let slot = save_states.slot;
+                    let status = match event {
+                        InputEvent::SaveState => match save_states.save(&machine.cpu) {
+                            Ok(()) => format!("saved slot {}", slot),
+                            Err(err) => format!("failed to save slot {}: {}", slot, err),
+                        },
+                        InputEvent::LoadState => match save_states.load(&mut machine.cpu) {
+                            Ok(()) => {
+                                machine.clear_fault();
+                                format!("loaded slot {}", slot)
+                            },
+                            Err(err) => format!("failed to load slot {}: {}", slot, err),
+                        },
+                        InputEvent::NextSlot => {
+                            save_states.next_slot();
+                            format!("slot {}", save_states.slot)
+                        },
+                        _ => {
+                            save_states.prev_slot();
+                            format!("slot {}", save_states.slot)
+                        },
+                    };
+                    show_status(video, &title, &status)?;
+                },
+                InputEvent::Rewind => rewinding = true,
+                InputEvent::SpeedUp | InputEvent::SpeedDown => {
+                    // The keys take over from --cpu-hz
+                    scheduler.set_cpu_hz(None);
+                    let current = machine.cycles_per_frame;
+                    let next = if event == InputEvent::SpeedUp {
+                        SPEED_STEPS.iter().copied().find(|&step| step > current)
+                    } else {
+                        SPEED_STEPS.iter().copied().rev().find(|&step| step < current)
+                    };
+                    machine.cycles_per_frame = next.unwrap_or(current);
+                    log::info!("instructions per frame: {}", machine.cycles_per_frame);
+                    readout.show(machine.cycles_per_frame);
+                },
+                InputEvent::Redraw => redraw = true,
+                InputEvent::ToggleStats => {
+                    stats.visible = !stats.visible;
+                    redraw = true;
+                },
+            }
         }
-        
-        cpu.tick();
 
-        if cpu.fb.update{
-            video_driver.draw_screen(&cpu.fb.data)?;
-            cpu.fb.update= false;
+        // Run however many 60Hz ticks are due. The host loop may run faster
+        // than that with vsync, or fall behind, without changing game speed
+        let ticks = scheduler.advance();
+        let instructions = machine.cpu.instructions;
+        let mut frame = None;
+        for _ in 0..ticks {
+            if player.as_ref().is_some_and(|player| player.finished()) {
+                log::info!("movie finished, handing over to the keyboard");
+                player = None;
+            }
+            if let Some(cycles) = scheduler.cycles_for_tick() {
+                machine.cycles_per_frame = cycles;
+            }
+
+            let running = match debug_session.as_mut() {
+                Some(session) => {
+                    session.poll(&mut machine.cpu);
+                    !session.debugger().paused()
+                },
+                // A CPU fault pauses emulation until a new rom is dropped in
+                None => machine.fault().is_none(),
+            };
+
+            let output = if rewinding {
+                if rewind.rewind(&mut machine.cpu) && machine.fault().is_some() {
+                    machine.clear_fault();
+                    video.set_title(&title)?;
+                }
+                machine.output()
+            } else if let Some(session) = debug_session.as_mut().filter(|_| running) {
+                machine.set_input(&keys);
+                session.run(&mut machine);
+                machine.output()
+            } else if running {
+                match (&mut recorder, &mut player) {
+                    (_, Some(playing)) => match playing.run_frame(&mut machine) {
+                        Ok(output) => output,
+                        Err(desync) => {
+                            log::error!("{}", desync);
+                            video.set_title(&format!("{} - {}", title, desync))?;
+                            player = None;
+                            machine.output()
+                        },
+                    },
+                    (Some(recorder), None) => recorder.run_frame(&mut machine, &keys),
+                    (None, None) => machine.run_frame(&keys),
+                }
+            } else {
+                machine.output()
+            };
+
+            for event in &output.events {
+                if let MachineEvent::Fault(err) = event {
+                    let cpu = &machine.cpu;
+                    log::error!("cpu fault: {}", err);
+                    log::error!("v: {:02x?} i: {:#06x} pc: {:#06x} sp: {} stack: {:04x?}",
+                        cpu.v, cpu.i, cpu.pc, cpu.sp, cpu.stack);
+                    video.set_title(&format!("{} - paused: {}", title, err))?;
+                }
+            }
+
+            redraw |= readout.tick();
+            frame = output.frame.or(frame);
+
+            let mut sound = output.audio;
+            sound.playing &= running || rewinding;
+            audio.update(&sound);
+
+            if running && !rewinding {
+                rewind.record(&machine.cpu);
+            }
+
+            if machine.cpu.exit {
+                log::info!("program exited");
+                break 'emulate;
+            }
         }
-        
 
-        if cpu.sound_state() {
-            audio_driver.on();
-        } else {
-            audio_driver.off();
+        redraw |= stats.record(Instant::now(), machine.cpu.instructions - instructions) && stats.visible;
+
+        // Overlays are drawn over a copy of the frame, and erased once they go.
+        // With vsync every pass presents a frame, as that's what paces the loop
+        if redraw || frame.is_some() || video.vsync() {
+            let mut frame = frame.unwrap_or_else(|| machine.cpu.fb.clone());
+            readout.draw(&mut frame);
+            stats.draw(&mut frame);
+            video.draw(&frame)?;
+        }
+        if !video.vsync() {
+            thread::sleep(scheduler.until_next());
         }
+    }
 
-        let cycle_elapsed_time = Instant::now() - cycle_start_time;
+    save_movie(config, recorder.as_ref())
+}
 
-        thread::sleep(Duration::new(0, 1_000_000_000u32 / 60).saturating_sub(cycle_elapsed_time));
+/// Logs the outcome of a save state hotkey and shows it in the title, as
+/// the log is usually off
+fn show_status(video: &mut impl VideoSink, title: &str, status: &str) -> Result<(), Box<dyn Error>> {
+    log::info!("{}", status);
+    video.set_title(&format!("{} - {}", title, status))
+}
+
+/// Starts recording or playing a movie, if one was asked for. Playback
+/// replaces the machine's quirks, speed and RNG with the recording's.
+/// Either way, load the rom afterwards so the movie starts from a reset.
+fn start_movie(
+    config: &Config,
+    rom: &FileDriver,
+    machine: &mut Machine,
+    seed: u64,
+) -> Result<(Option<Recorder>, Option<Player>), Box<dyn Error>> {
+    match &config.movie {
+        None => Ok((None, None)),
+        Some(MovieMode::Record(path)) => {
+            log::info!("recording movie to {}", path.display());
+            let header = MovieHeader {
+                rom_hash: rom.hash_hex(),
+                quirks: machine.cpu.quirks,
+                cycles_per_frame: machine.cycles_per_frame,
+                timing: machine.timing,
+                rng: machine.cpu.rng.model(),
+                seed,
+            };
+            Ok((Some(Recorder::new(header)), None))
+        },
+        Some(MovieMode::Play { path, verify }) => {
+            let text = fs::read_to_string(path)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            let movie: Movie = text.parse()
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            if movie.header.rom_hash != rom.hash_hex() {
+                return Err(format!("{} was recorded with a different rom ({})",
+                    path.display(), movie.header.rom_hash).into());
+            }
+            log::info!("playing {} frames from {}", movie.frames, path.display());
+            movie.header.apply(machine);
+            Ok((None, Some(Player::new(movie, *verify))))
+        },
     }
+}
 
+/// Opens the trace file, if tracing was asked for
+fn start_trace(config: &Config) -> Result<Option<Tracer>, Box<dyn Error>> {
+    let Some(trace) = &config.trace else {
+        return Ok(None);
+    };
+    let file = fs::File::create(&trace.path)
+        .map_err(|err| format!("{}: {}", trace.path.display(), err))?;
+    log::info!("tracing to {}", trace.path.display());
+    Ok(Some(Tracer::new(Box::new(BufWriter::new(file)), trace.format, trace.filter.clone())?))
+}
+
+/// Writes out the movie being recorded, if there is one
+fn save_movie(config: &Config, recorder: Option<&Recorder>) -> Result<(), Box<dyn Error>> {
+    if let (Some(MovieMode::Record(path)), Some(recorder)) = (&config.movie, recorder) {
+        fs::write(path, recorder.movie().to_string())
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        log::info!("saved {} frame movie to {}", recorder.movie().frames, path.display());
+    }
     Ok(())
 }
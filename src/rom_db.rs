@@ -0,0 +1,237 @@
+//! Metadata for known roms, from the CHIP-8 community database
+//! (https://github.com/chip-8/chip-8-database), looked up by SHA-1.
+//!
+//! The database's database/programs.json and database/sha1-hashes.json are
+//! bundled into the binary from data/. The copies there are a trimmed
+//! subset in the same format; copy both files over from the database
+//! repository for the full set.
+
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+};
+use serde::Deserialize;
+use crate::{
+    config::file::parse_colour,
+    emu::quirks::{
+        IndexIncrement,
+        Platform,
+        Quirks,
+    },
+};
+
+const PROGRAMS: &str = include_str!("../data/programs.json");
+const HASHES: &str = include_str!("../data/sha1-hashes.json");
+
+/// Keyboard keys bound to the database's logical buttons, alongside the usual keypad layout
+const BUTTON_KEYS: [(&str, &str); 6] = [
+    ("up", "Up"),
+    ("down", "Down"),
+    ("left", "Left"),
+    ("right", "Right"),
+    ("a", "Space"),
+    ("b", "Return"),
+];
+
+#[derive(Debug, Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    tickrate: Option<usize>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+/// What the database knows about a rom
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    /// The first platform the rom targets that the emulator supports
+    pub platform: Option<Platform>,
+    /// The platform's quirks, with any the rom needs changed
+    pub quirks: Option<Quirks>,
+    /// Recommended instructions per frame
+    pub tickrate: Option<usize>,
+    /// Colours for as many pixel values as the rom specifies
+    pub colours: Vec<[u8; 3]>,
+    /// Keyboard key names to bind to chip-8 keys
+    pub keys: Vec<(&'static str, u8)>,
+}
+
+pub struct RomDb {
+    programs: Vec<Program>,
+    /// Index into programs, by lowercase SHA-1
+    hashes: HashMap<String, usize>,
+}
+
+impl RomDb {
+    /// The database bundled with the emulator, parsed on first use
+    pub fn bundled() -> &'static RomDb {
+        static DB: OnceLock<RomDb> = OnceLock::new();
+        DB.get_or_init(|| {
+            RomDb::from_json(PROGRAMS, HASHES).unwrap_or_else(|err| {
+                log::error!("failed to parse the rom database: {}", err);
+                RomDb { programs: Vec::new(), hashes: HashMap::new() }
+            })
+        })
+    }
+
+    /// Parses the contents of programs.json and sha1-hashes.json
+    pub fn from_json(programs: &str, hashes: &str) -> Result<Self, serde_json::Error> {
+        Ok(RomDb {
+            programs: serde_json::from_str(programs)?,
+            hashes: serde_json::from_str(hashes)?,
+        })
+    }
+
+    /// Looks up a rom by its hex SHA-1
+    pub fn lookup(&self, hash: &str) -> Option<RomInfo> {
+        let hash = hash.to_ascii_lowercase();
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let rom = program.roms.get(&hash);
+
+        let platform = rom.and_then(|rom| {
+            rom.platforms.iter().find_map(|id| platform(id).map(|platform| (id, platform)))
+        });
+        let quirks = platform.map(|(id, platform)| {
+            let mut quirks = platform.quirks();
+            if let Some(overrides) = rom.and_then(|rom| rom.quirky_platforms.get(id)) {
+                apply_quirks(&mut quirks, overrides);
+            }
+            quirks
+        });
+
+        let colours = rom
+            .and_then(|rom| rom.colors.as_ref())
+            .map(|colors| colors.pixels.iter().filter_map(|colour| parse_colour(colour).ok()).collect())
+            .unwrap_or_default();
+
+        let keys = BUTTON_KEYS.iter()
+            .filter_map(|&(button, name)| {
+                let key = *rom?.keys.get(button)?;
+                (key < 16).then_some((name, key))
+            })
+            .collect();
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform: platform.map(|(_, platform)| platform),
+            quirks,
+            tickrate: rom.and_then(|rom| rom.tickrate),
+            colours,
+            keys,
+        })
+    }
+}
+
+/// The emulator platform for a database platform id, if it's supported
+fn platform(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some(Platform::CosmacVip),
+        "modernChip8" | "chip48"      => Some(Platform::Chip48),
+        "superchip1"                  => Some(Platform::Chip48),
+        "superchip"                   => Some(Platform::Schip11),
+        "xochip"                      => Some(Platform::XoChip),
+        _ => None,
+    }
+}
+
+/// Applies the database's named quirk flags
+fn apply_quirks(quirks: &mut Quirks, overrides: &HashMap<String, bool>) {
+    for (name, &enabled) in overrides {
+        match name.as_str() {
+            "shift"  => quirks.shift_vy = !enabled,
+            "logic"  => quirks.vf_reset = enabled,
+            "jump"   => quirks.jump_vx = enabled,
+            "wrap"   => quirks.clipping = !enabled,
+            "vblank" => quirks.display_wait = enabled,
+            "memoryIncrementByX" | "memoryLeaveIUnchanged" if !enabled => {},
+            "memoryIncrementByX" => quirks.index_increment = IndexIncrement::X,
+            "memoryLeaveIUnchanged" => quirks.index_increment = IndexIncrement::Unchanged,
+            _ => log::debug!("ignoring unknown quirk '{}' in the rom database", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn db() -> RomDb {
+        let programs = r##"[{
+            "title": "Test Program",
+            "authors": ["Someone"],
+            "roms": {
+                "0123456789abcdef0123456789abcdef01234567": {
+                    "tickrate": 20,
+                    "platforms": ["megachip8", "superchip", "xochip"],
+                    "quirkyPlatforms": {
+                        "superchip": { "shift": false, "vblank": true, "memoryIncrementByX": true }
+                    },
+                    "colors": { "pixels": ["#112233", "#445566"] },
+                    "keys": { "up": 5, "a": 6, "b": 16 }
+                }
+            }
+        }]"##;
+        RomDb::from_json(programs, &format!(r#"{{ "{}": 0 }}"#, HASH)).unwrap()
+    }
+
+    #[test]
+    fn lookup_applies_the_first_supported_platform_and_its_quirks() {
+        let info = db().lookup(&HASH.to_ascii_uppercase()).unwrap();
+        assert_eq!(info.title, "Test Program");
+        assert_eq!(info.authors, ["Someone"]);
+        assert_eq!(info.platform, Some(Platform::Schip11));
+        assert_eq!(info.tickrate, Some(20));
+
+        let mut quirks = Platform::Schip11.quirks();
+        quirks.shift_vy = true;
+        quirks.display_wait = true;
+        quirks.index_increment = IndexIncrement::X;
+        assert_eq!(info.quirks, Some(quirks));
+
+        assert_eq!(info.colours, [[0x11, 0x22, 0x33], [0x44, 0x55, 0x66]]);
+        // Key 16 doesn't exist on the keypad
+        let mut keys = info.keys.clone();
+        keys.sort();
+        assert_eq!(keys, [("Space", 6), ("Up", 5)]);
+    }
+
+    #[test]
+    fn unknown_roms_are_not_found() {
+        assert_eq!(db().lookup("ffffffffffffffffffffffffffffffffffffffff"), None);
+    }
+
+    #[test]
+    fn bundled_database_knows_the_ibm_logo() {
+        let hash = sha1_smol::Sha1::from(include_bytes!("../tests/programs/ibm_logo.ch8")).digest().to_string();
+        let info = RomDb::bundled().lookup(&hash).unwrap();
+        assert_eq!(info.title, "IBM Logo");
+        assert_eq!(info.platform, Some(Platform::CosmacVip));
+        assert_eq!(info.quirks, Some(Platform::CosmacVip.quirks()));
+        assert_eq!(info.tickrate, None);
+    }
+}
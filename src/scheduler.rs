@@ -0,0 +1,151 @@
+use std::time::{
+    Duration,
+    Instant,
+};
+
+/// Timer ticks per second
+pub const TICK_HZ: u64 = 60;
+/// Most ticks run at once to catch up after a stall. Any more are dropped,
+/// so a host that can't keep up slows the game down instead of falling
+/// further and further behind.
+pub const MAX_CATCH_UP: u32 = 4;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Paces the machine's 60Hz ticks against real time, independently of how
+/// often the host loop runs.
+///
+/// Elapsed time is accumulated exactly, in units of 1/60 ns, so the tick
+/// rate doesn't drift however the host loop is scheduled. Likewise, a cpu
+/// rate that isn't a multiple of 60Hz is spread over the ticks, with the
+/// fraction of an instruction carried from one tick to the next.
+#[derive(Debug)]
+pub struct Scheduler {
+    last: Instant,
+    /// Time not yet run, in nanoseconds times TICK_HZ
+    pending: u128,
+    /// Instructions per second, if the speed was given that way
+    cpu_hz: Option<u32>,
+    /// Instructions owed to the next tick, in 1/60ths of an instruction
+    cycles_pending: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler { last: Instant::now(), pending: 0, cpu_hz: None, cycles_pending: 0 }
+    }
+
+    /// Sets the instructions per second, or None to leave the speed alone
+    pub fn set_cpu_hz(&mut self, hz: Option<u32>) {
+        self.cpu_hz = hz;
+        self.cycles_pending = 0;
+    }
+
+    /// Instructions to run in the next tick at the set cpu rate, so that
+    /// every second runs exactly that many
+    pub fn cycles_for_tick(&mut self) -> Option<usize> {
+        let owed = self.cycles_pending + self.cpu_hz? as u64;
+        self.cycles_pending = owed % TICK_HZ;
+        Some((owed / TICK_HZ) as usize)
+    }
+
+    /// Accounts for the time since the last call, and returns the number
+    /// of ticks now due
+    pub fn advance(&mut self) -> u32 {
+        self.advance_to(Instant::now())
+    }
+
+    pub fn advance_to(&mut self, now: Instant) -> u32 {
+        self.pending += now.saturating_duration_since(self.last).as_nanos() * TICK_HZ as u128;
+        self.last = now;
+        let due = self.pending / NANOS_PER_SEC;
+        self.pending %= NANOS_PER_SEC;
+        if due > MAX_CATCH_UP as u128 {
+            log::debug!("running behind, dropped {} ticks", due - MAX_CATCH_UP as u128);
+        }
+        due.min(MAX_CATCH_UP as u128) as u32
+    }
+
+    /// Time left until the next tick is due
+    pub fn until_next(&self) -> Duration {
+        let nanos = (NANOS_PER_SEC - self.pending).div_ceil(TICK_HZ as u128);
+        Duration::from_nanos(nanos as u64).saturating_sub(self.last.elapsed())
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One tick, rounded up to whole nanoseconds
+    fn tick() -> Duration {
+        Duration::from_nanos((NANOS_PER_SEC as u64).div_ceil(TICK_HZ))
+    }
+
+    #[test]
+    fn ticks_come_due_at_60hz() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler { last: start, ..Scheduler::new() };
+        assert_eq!(scheduler.advance_to(start + tick() / 2), 0);
+        assert_eq!(scheduler.advance_to(start + tick()), 1);
+        assert_eq!(scheduler.advance_to(start + tick() * 3), 2);
+        // Going back in time doesn't run anything
+        assert_eq!(scheduler.advance_to(start), 0);
+    }
+
+    #[test]
+    fn a_second_of_uneven_calls_runs_60_ticks() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler { last: start, ..Scheduler::new() };
+        let ticks: u32 = (1..=1000)
+            .map(|ms| scheduler.advance_to(start + Duration::from_millis(ms) + Duration::from_nanos(ms % 7)))
+            .sum();
+        assert_eq!(ticks, 60);
+    }
+
+    #[test]
+    fn catching_up_is_capped_without_drifting() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler { last: start, ..Scheduler::new() };
+        // A stall of 10.5 ticks runs at most MAX_CATCH_UP, and drops the rest
+        let stall = start + tick() * 21 / 2;
+        assert_eq!(scheduler.advance_to(stall), MAX_CATCH_UP);
+        // The half tick left over still counts
+        assert_eq!(scheduler.advance_to(stall + tick() / 2), 1);
+        assert_eq!(scheduler.advance_to(stall + tick() * 3 / 2), 1);
+    }
+
+    #[test]
+    fn until_next_counts_down_to_the_next_tick() {
+        let mut scheduler = Scheduler::new();
+        let now = scheduler.last;
+        scheduler.advance_to(now);
+        assert!(scheduler.until_next() <= tick());
+        assert!(scheduler.until_next() > tick() - Duration::from_millis(5));
+
+        // A quarter of the way through
+        scheduler.pending = NANOS_PER_SEC / 4;
+        assert!(scheduler.until_next() <= tick() * 3 / 4);
+    }
+
+    #[test]
+    fn cpu_rate_carries_fractions_between_ticks() {
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.cycles_for_tick(), None);
+
+        scheduler.set_cpu_hz(Some(500));
+        let cycles: Vec<usize> = (0..60).map(|_| scheduler.cycles_for_tick().unwrap()).collect();
+        assert!(cycles.iter().all(|&n| n == 8 || n == 9));
+        assert_eq!(cycles.iter().sum::<usize>(), 500);
+
+        scheduler.set_cpu_hz(Some(30));
+        let cycles: Vec<usize> = (0..4).map(|_| scheduler.cycles_for_tick().unwrap()).collect();
+        assert_eq!(cycles, [0, 1, 0, 1]);
+    }
+}
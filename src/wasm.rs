@@ -0,0 +1,90 @@
+//! JavaScript bindings for running the emulator in a browser or node.
+//! See web/ for a canvas and WebAudio frontend built on these.
+
+use wasm_bindgen::prelude::*;
+use crate::emu::{
+    frame::PALETTE,
+    machine::{
+        InputState,
+        Machine,
+        MachineEvent,
+    },
+    quirks::Platform,
+};
+
+/// A chip-8 machine, driven one 60Hz frame at a time from JavaScript
+#[wasm_bindgen]
+pub struct Emulator {
+    machine: Machine,
+    input: InputState,
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    /// Creates a machine with the quirks of the named platform
+    /// (vip, chip48, schip11 or xochip) and an RNG seed
+    #[wasm_bindgen(constructor)]
+    pub fn new(platform: &str, seed: u32) -> Result<Emulator, JsError> {
+        let platform: Platform = platform.parse().map_err(|err: String| JsError::new(&err))?;
+        let mut machine = Machine::new(platform.quirks());
+        machine.cpu.seed(seed as u64);
+        Ok(Emulator { machine, input: InputState::default() })
+    }
+
+    /// Resets the machine and loads a rom at 0x200
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        self.machine.load(rom).map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// Runs one frame. Throws if the cpu faults.
+    /// Returns false once the program has exited.
+    pub fn run_frame(&mut self) -> Result<bool, JsError> {
+        match self.machine.run_frame(&self.input).events.first() {
+            Some(MachineEvent::Exited) => Ok(false),
+            Some(MachineEvent::Fault(err)) => Err(JsError::new(&err.to_string())),
+            None => Ok(true),
+        }
+    }
+
+    /// Presses or releases a key on the hex keypad
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        if let Some(state) = self.input.keys.get_mut(key as usize) {
+            *state = pressed;
+        }
+    }
+
+    /// Framebuffer width in pixels: 64, or 128 in high resolution
+    pub fn width(&self) -> usize {
+        self.machine.cpu.fb.width
+    }
+
+    /// Framebuffer height in pixels: 32, or 64 in high resolution
+    pub fn height(&self) -> usize {
+        self.machine.cpu.fb.height
+    }
+
+    /// The framebuffer as RGBA bytes, ready for an ImageData
+    pub fn framebuffer_rgba(&self) -> Vec<u8> {
+        self.machine.cpu.fb.data.iter()
+            .flat_map(|&pixel| {
+                let [r, g, b] = PALETTE[pixel as usize & 0b11];
+                [r, g, b, 0xFF]
+            })
+            .collect()
+    }
+
+    /// Whether the sound timer is running
+    pub fn sound_playing(&self) -> bool {
+        self.machine.cpu.sound_state()
+    }
+
+    /// The 16 byte XO-CHIP audio pattern, if one has been loaded
+    pub fn audio_pattern(&self) -> Option<Vec<u8>> {
+        self.machine.cpu.pattern.map(|pattern| pattern.to_vec())
+    }
+
+    /// Pattern playback rate in bits per second
+    pub fn audio_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.machine.cpu.pitch as f64 - 64.0) / 48.0)
+    }
+}
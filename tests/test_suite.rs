@@ -0,0 +1,174 @@
+//! Runs test roms headlessly and compares the final screen of each test
+//! against a golden image in `tests/golden/`.
+//!
+//! Timendus' chip8-test-suite isn't redistributed here. Put
+//! `chip8-test-suite.ch8` in `tests/roms/` (or point `CHIP8_TEST_SUITE` at
+//! it) and run `cargo test -- --ignored`. The programs in `tests/programs/`,
+//! as roms or assembled from source, always run. Run with `BLESS=1` to
+//! write the golden images from the current output, and check them by eye
+//! before committing.
+
+use std::{
+    env,
+    fs,
+    ops::Range,
+    path::PathBuf,
+};
+use chip8::{
+    asm,
+    drivers::image::Image,
+    emu::{
+        cpu::CPU,
+        quirks::Platform,
+        test_suite::{
+            KeypadTest,
+            QuirkTarget,
+            Test,
+        },
+    },
+};
+
+/// Every test finishes well within this many frames
+const FRAMES: usize = 600;
+
+/// A chip-8 key held down over a range of frames
+struct KeyPress {
+    key: usize,
+    frames: Range<usize>,
+}
+
+fn suite_rom() -> Vec<u8> {
+    let path = env::var_os("CHIP8_TEST_SUITE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms/chip8-test-suite.ch8"));
+    fs::read(&path).unwrap_or_else(|err| panic!("can't read {} ({})", path.display(), err))
+}
+
+fn program(name: &str) -> Vec<u8> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/programs/{}.8o", name));
+    let source = fs::read_to_string(&path).unwrap_or_else(|err| panic!("can't read {} ({})", path.display(), err));
+    asm::assemble(&source).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+/// Runs the suite with a test selected, pressing keys as given
+fn check_suite(name: &str, test: Test, platform: Platform, presses: &[KeyPress]) {
+    let mut cpu = CPU::with_quirks(platform.quirks());
+    cpu.load(&suite_rom()).unwrap();
+    test.select(&mut cpu);
+    run(name, cpu, presses);
+}
+
+fn check_program(name: &str, platform: Platform) {
+    let mut cpu = CPU::with_quirks(platform.quirks());
+    cpu.load(&program(name)).unwrap();
+    run(name, cpu, &[]);
+}
+
+fn check_rom(name: &str, platform: Platform) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/programs/{}.ch8", name));
+    let rom = fs::read(&path).unwrap_or_else(|err| panic!("can't read {} ({})", path.display(), err));
+    let mut cpu = CPU::with_quirks(platform.quirks());
+    cpu.load(&rom).unwrap();
+    run(name, cpu, &[]);
+}
+
+fn run(name: &str, mut cpu: CPU, presses: &[KeyPress]) {
+    for frame in 0..FRAMES {
+        cpu.kp.release_all();
+        for press in presses.iter().filter(|press| press.frames.contains(&frame)) {
+            cpu.kp.set(press.key, true);
+        }
+        cpu.run_frame().unwrap_or_else(|err| panic!("{}: cpu fault: {}", name, err));
+    }
+
+    let golden = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(format!("tests/golden/{}.txt", name));
+    let golden = golden.to_str().unwrap();
+    let actual = Image::from_frame(&cpu.fb);
+    if env::var_os("BLESS").is_some() {
+        fs::create_dir_all(PathBuf::from(golden).parent().unwrap()).unwrap();
+        actual.save(golden).unwrap();
+        return;
+    }
+
+    let expected = Image::load(golden)
+        .unwrap_or_else(|err| panic!("{}: can't read {} ({}), run with BLESS=1 to create it", name, golden, err));
+    if let Err(err) = actual.compare(&expected) {
+        panic!("{}: {}\n{}", name, err, actual.to_ascii());
+    }
+}
+
+#[test]
+fn vf_flags() {
+    check_program("vf_flags", Platform::CosmacVip);
+}
+
+#[test]
+fn ibm_logo() {
+    check_rom("ibm_logo", Platform::CosmacVip);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn suite_splash_screen() {
+    check_suite("suite_splash_screen", Test::SplashScreen, Platform::CosmacVip, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn suite_ibm_logo() {
+    check_suite("suite_ibm_logo", Test::IbmLogo, Platform::CosmacVip, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn corax() {
+    check_suite("corax", Test::Corax, Platform::CosmacVip, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn flags() {
+    check_suite("flags", Test::Flags, Platform::CosmacVip, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn quirks_chip8() {
+    check_suite("quirks_chip8", Test::Quirks(QuirkTarget::Chip8), Platform::CosmacVip, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn quirks_schip() {
+    check_suite("quirks_schip", Test::Quirks(QuirkTarget::SuperChip), Platform::Schip11, &[]);
+}
+
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn quirks_xochip() {
+    check_suite("quirks_xochip", Test::Quirks(QuirkTarget::XoChip), Platform::XoChip, &[]);
+}
+
+/// Holds keys 5 and A to the end, so the test shows them as down
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn keypad_down() {
+    let presses = [KeyPress { key: 0x5, frames: 100..FRAMES }, KeyPress { key: 0xA, frames: 200..FRAMES }];
+    check_suite("keypad_down", Test::Keypad(KeypadTest::Down), Platform::CosmacVip, &presses);
+}
+
+/// Presses and releases key 5, and holds A to the end
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn keypad_up() {
+    let presses = [KeyPress { key: 0x5, frames: 100..200 }, KeyPress { key: 0xA, frames: 300..FRAMES }];
+    check_suite("keypad_up", Test::Keypad(KeypadTest::Up), Platform::CosmacVip, &presses);
+}
+
+/// Presses and releases key 7. FX0A only returns once it's released again
+#[test]
+#[ignore = "needs chip8-test-suite.ch8"]
+fn keypad_get_key() {
+    let presses = [KeyPress { key: 0x7, frames: 100..160 }];
+    check_suite("keypad_get_key", Test::Keypad(KeypadTest::GetKey), Platform::CosmacVip, &presses);
+}
@@ -0,0 +1,132 @@
+// Canvas and WebAudio frontend for the wasm build. See README.md for how to build pkg/.
+import init, { Emulator } from './pkg/chip8.js';
+
+const FRAME_MS = 1000 / 60;
+
+// Same layout as the SDL and terminal frontends
+const KEYMAP = {
+  Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xC,
+  KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xD,
+  KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xE,
+  KeyZ: 0xA, KeyX: 0x0, KeyC: 0xB, KeyV: 0xF,
+};
+
+/// Plays the default tone, or loops the XO-CHIP pattern buffer
+class Buzzer {
+  constructor() {
+    this.ctx = null;
+    this.source = null;
+    this.pattern = null;
+    this.rate = 0;
+  }
+
+  update(emu) {
+    if (!emu.sound_playing()) {
+      this.stop();
+      return;
+    }
+    // Browsers only allow audio to start after a user gesture
+    this.ctx ??= new AudioContext();
+
+    const pattern = emu.audio_pattern();
+    const rate = emu.audio_rate();
+    const key = pattern ? pattern.join() : null;
+    if (this.source && key === this.pattern && rate === this.rate) {
+      return;
+    }
+    this.stop();
+    this.pattern = key;
+    this.rate = rate;
+
+    const gain = this.ctx.createGain();
+    gain.gain.value = 0.03;
+    gain.connect(this.ctx.destination);
+
+    if (pattern) {
+      // One sample per pattern bit at 4000 bits/s, sped up to the pitch
+      const buffer = this.ctx.createBuffer(1, 128, 4000);
+      const samples = buffer.getChannelData(0);
+      for (let bit = 0; bit < 128; bit++) {
+        samples[bit] = pattern[bit >> 3] & (0x80 >> (bit & 7)) ? 1 : -1;
+      }
+      this.source = this.ctx.createBufferSource();
+      this.source.buffer = buffer;
+      this.source.loop = true;
+      this.source.playbackRate.value = rate / 4000;
+    } else {
+      this.source = this.ctx.createOscillator();
+      this.source.type = 'square';
+      this.source.frequency.value = 440;
+    }
+    this.source.connect(gain);
+    this.source.start();
+  }
+
+  stop() {
+    this.source?.stop();
+    this.source = null;
+  }
+}
+
+async function main() {
+  await init();
+
+  const canvas = document.getElementById('screen');
+  const ctx = canvas.getContext('2d');
+  const buzzer = new Buzzer();
+  let emu = null;
+
+  document.getElementById('rom').addEventListener('change', async (event) => {
+    const file = event.target.files[0];
+    if (!file) return;
+    const platform = document.getElementById('platform').value;
+    emu = new Emulator(platform, Math.floor(Math.random() * 0xFFFFFFFF));
+    emu.load(new Uint8Array(await file.arrayBuffer()));
+  });
+
+  for (const [type, pressed] of [['keydown', true], ['keyup', false]]) {
+    document.addEventListener(type, (event) => {
+      const key = KEYMAP[event.code];
+      if (emu && key !== undefined) {
+        emu.set_key(key, pressed);
+        event.preventDefault();
+      }
+    });
+  }
+
+  // Run at 60 frames per second whatever the display refresh rate
+  let last = performance.now();
+  let pending = 0;
+  function tick(now) {
+    pending = Math.min(pending + now - last, FRAME_MS * 4);
+    last = now;
+    if (emu) {
+      while (pending >= FRAME_MS) {
+        pending -= FRAME_MS;
+        try {
+          if (!emu.run_frame()) {
+            emu = null;
+            break;
+          }
+        } catch (err) {
+          console.error('cpu fault:', err);
+          emu = null;
+          break;
+        }
+      }
+    }
+    if (emu) {
+      canvas.width = emu.width();
+      canvas.height = emu.height();
+      const pixels = new Uint8ClampedArray(emu.framebuffer_rgba());
+      ctx.putImageData(new ImageData(pixels, canvas.width, canvas.height), 0, 0);
+      buzzer.update(emu);
+    } else {
+      buzzer.stop();
+    }
+    requestAnimationFrame(tick);
+  }
+  requestAnimationFrame(tick);
+}
+
+main();
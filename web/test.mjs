@@ -0,0 +1,63 @@
+// Smoke test of the wasm API under node. Build pkg-node/ first, see README.md.
+import assert from 'node:assert/strict';
+import { test } from 'node:test';
+import { createRequire } from 'node:module';
+
+const require = createRequire(import.meta.url);
+const { Emulator } = require('./pkg-node/chip8.js');
+
+// Draws the font glyph for 0 at (0, 0), starts the sound timer, then loops
+const ROM = new Uint8Array([
+  0x00, 0xE0, // CLS
+  0xA0, 0x00, // LD I, 0x000
+  0x60, 0x00, // LD V0, 0x00
+  0xD0, 0x05, // DRW V0, V0, 5
+  0x61, 0x05, // LD V1, 0x05
+  0xF1, 0x18, // LD ST, V1
+  0x12, 0x0C, // JP 0x20C
+]);
+
+const OFF = [145, 145, 135, 255];
+const ON = [32, 42, 52, 255];
+
+function pixel(emu, rgba, x, y) {
+  const idx = (y * emu.width() + x) * 4;
+  return Array.from(rgba.slice(idx, idx + 4));
+}
+
+test('draws to the framebuffer', () => {
+  const emu = new Emulator('vip', 1);
+  emu.load(ROM);
+  assert.equal(emu.run_frame(), true);
+
+  assert.equal(emu.width(), 64);
+  assert.equal(emu.height(), 32);
+  const rgba = emu.framebuffer_rgba();
+  assert.equal(rgba.length, 64 * 32 * 4);
+  // The top row of the 0 glyph is 0xF0
+  assert.deepEqual(pixel(emu, rgba, 0, 0), ON);
+  assert.deepEqual(pixel(emu, rgba, 3, 0), ON);
+  assert.deepEqual(pixel(emu, rgba, 4, 0), OFF);
+  // The second row is 0x90
+  assert.deepEqual(pixel(emu, rgba, 1, 1), OFF);
+});
+
+test('sound follows the sound timer', () => {
+  const emu = new Emulator('vip', 1);
+  emu.load(ROM);
+  emu.run_frame();
+  assert.equal(emu.sound_playing(), true);
+  assert.equal(emu.audio_pattern(), undefined);
+  for (let i = 0; i < 10; i++) {
+    emu.run_frame();
+  }
+  assert.equal(emu.sound_playing(), false);
+});
+
+test('rejects unknown platforms and faults', () => {
+  assert.throws(() => new Emulator('nes', 1));
+
+  const emu = new Emulator('vip', 1);
+  emu.load(new Uint8Array([0x00, 0xEE])); // RET with an empty stack
+  assert.throws(() => emu.run_frame());
+});